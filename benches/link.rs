@@ -0,0 +1,88 @@
+//! links thousands of small modules with [Linker], and a part of them with [linker::cat_together] for comparison.
+//! run with `cargo bench --bench link`
+
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+use h6_bytecode::{Bytecode, OpEncoding, linker};
+use h6_bytecode::linker::Linker;
+use h6_compiler::{lex, parse, lower};
+
+const MODULES: usize = 4000;
+const CAT_TOGETHER_MODULES: usize = 500;
+
+struct NoUnresolved;
+
+impl linker::Target for NoUnresolved {
+    fn allow_undeclared_symbol(&self, _: &str) -> bool {
+        false
+    }
+}
+
+/// every module calls the global of the module before it, and the last one calls the last global
+fn module(idx: usize, last: bool) -> Vec<u8> {
+    let mut src = match idx {
+        0 => "f0: { 0 }\n".to_string(),
+        _ => format!("f{}: {{ f{}! 1 + }}\n", idx, idx - 1),
+    };
+    if last {
+        src.push_str(&format!("f{}!\n", idx));
+    }
+
+    let toks = lex::lex(src.as_str()).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let mut out = Cursor::new(vec!());
+    lower::lower_full(&mut out, exprs.iter(), false, OpEncoding::Fixed, None, None).unwrap();
+    out.into_inner()
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let begin = Instant::now();
+    let out = f();
+    (out, begin.elapsed())
+}
+
+fn run(bytes: &[u8]) -> Vec<h6_runtime::Value> {
+    let mut rt = h6_runtime::Runtime::new(Bytecode::try_from(bytes).unwrap()).unwrap();
+    while rt.step().unwrap().is_some() {}
+    rt.stack.into()
+}
+
+fn main() {
+    let modules = (0..MODULES).map(|x| module(x, x + 1 == MODULES)).collect::<Vec<_>>();
+
+    let (linked, took) = time(|| {
+        let mut ld = Linker::new();
+        for m in modules.iter() {
+            ld.add(Bytecode::try_from(m.as_slice()).unwrap()).unwrap();
+        }
+        ld.link(&NoUnresolved).unwrap()
+    });
+    println!("Linker: {} modules in {:?} ({} bytes)", MODULES, took, linked.len());
+    assert!(h6_bytecode::verify::verify(linked.as_slice()).is_empty());
+    assert_eq!(run(linked.as_slice()), vec!(h6_runtime::Value::Num(MODULES as i32 - 1)));
+
+    let few = (0..CAT_TOGETHER_MODULES).map(|x| module(x, x + 1 == CAT_TOGETHER_MODULES)).collect::<Vec<_>>();
+    let (_, took) = time(|| {
+        let mut ld = Linker::new();
+        for m in few.iter() {
+            ld.add(Bytecode::try_from(m.as_slice()).unwrap()).unwrap();
+        }
+        ld.link(&NoUnresolved).unwrap()
+    });
+    println!("Linker: {} modules in {:?}", CAT_TOGETHER_MODULES, took);
+
+    let (_, took) = time(|| {
+        let mut out = Cursor::new(vec!());
+        let mut header = h6_bytecode::Header::default();
+        header.set_op_encoding(OpEncoding::Fixed);
+        header.write(&mut out).unwrap();
+        h6_bytecode::Op::Terminate.write(&mut out).unwrap();
+        for m in few.iter() {
+            linker::cat_together(&mut out, m.as_slice()).unwrap();
+        }
+        let mut bytes = out.into_inner();
+        linker::self_link(&mut bytes, &NoUnresolved).unwrap();
+        bytes
+    });
+    println!("cat_together: {} modules in {:?}", CAT_TOGETHER_MODULES, took);
+}
@@ -0,0 +1,122 @@
+//! static archives (`.h6a`): a set of bytecode files with an index of the globals they define.
+//! the linker only takes the members that define symbols which are still unresolved, see [crate::linker::Linker::add_archives]
+
+use nostd::prelude::*;
+use nostd::collections::BTreeMap;
+use nostd::str;
+use crate::{ByteCodeError, Bytecode};
+
+pub const ARCHIVE_MAGIC: [u8;4] = *b"H6A\0";
+
+/// one bytecode file in an [Archive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    /// usually the file name of the member
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// archive file:
+///   magic: "H6A\0"
+///   num symbols: u32_le
+///   symbols: num symbols * { name: utf8, null terminated; member: u32_le }
+///   num members: u32_le
+///   members: num members * { name: utf8, null terminated; len: u32_le; bytes: len bytes }
+///
+/// the symbol index maps the name of every global to the member that defines it.
+/// if multiple members define the same global, the first one is used
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Archive {
+    pub symbols: BTreeMap<String, u32>,
+    pub members: Vec<Member>,
+}
+
+impl Archive {
+    /// creates an archive with the symbol index of the given members
+    pub fn new(members: Vec<Member>) -> Result<Self, ByteCodeError> {
+        let mut symbols = BTreeMap::new();
+        for (idx, member) in members.iter().enumerate() {
+            for global in Bytecode::try_from(member.bytes.as_slice())?.named_globals() {
+                symbols.entry(global?.0.to_string()).or_insert(idx as u32);
+            }
+        }
+        Ok(Self { symbols, members })
+    }
+
+    /// the member that defines the global [name]
+    pub fn member_defining(&self, name: &str) -> Option<&Member> {
+        self.members.get(*self.symbols.get(name)? as usize)
+    }
+
+    pub fn is_archive(bytes: &[u8]) -> bool {
+        bytes.starts_with(&ARCHIVE_MAGIC)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        fn put_str(out: &mut Vec<u8>, s: &str) {
+            out.extend_from_slice(s.as_bytes());
+            out.push(0);
+        }
+
+        let mut out = ARCHIVE_MAGIC.to_vec();
+        out.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        for (name, member) in self.symbols.iter() {
+            put_str(&mut out, name);
+            out.extend_from_slice(&member.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.members.len() as u32).to_le_bytes());
+        for member in self.members.iter() {
+            put_str(&mut out, &member.name);
+            out.extend_from_slice(&(member.bytes.len() as u32).to_le_bytes());
+            out.extend_from_slice(&member.bytes);
+        }
+        out
+    }
+}
+
+impl<'asm> TryFrom<&'asm [u8]> for Archive {
+    type Error = ByteCodeError;
+
+    fn try_from(value: &'asm [u8]) -> Result<Self, Self::Error> {
+        fn get_u32(from: &[u8], at: &mut usize) -> Result<u32, ByteCodeError> {
+            let slice = from.get(*at..*at + 4).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let mut bytes = [0_u8;4];
+            bytes.copy_from_slice(slice);
+            *at += 4;
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        fn get_str(from: &[u8], at: &mut usize) -> Result<String, ByteCodeError> {
+            let sl = from.get(*at..).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let term = sl.iter().position(|&b| b == 0).ok_or(ByteCodeError::InvalidStringEncoding)?;
+            let s = str::from_utf8(&sl[0..term]).map_err(|_| ByteCodeError::InvalidStringEncoding)?;
+            *at += term + 1;
+            Ok(s.to_string())
+        }
+
+        if !Archive::is_archive(value) {
+            return Err(ByteCodeError::InvalidMagic);
+        }
+        let mut at = ARCHIVE_MAGIC.len();
+
+        let mut symbols = BTreeMap::new();
+        for _ in 0..get_u32(value, &mut at)? {
+            let name = get_str(value, &mut at)?;
+            symbols.insert(name, get_u32(value, &mut at)?);
+        }
+
+        let mut members = vec!();
+        for _ in 0..get_u32(value, &mut at)? {
+            let name = get_str(value, &mut at)?;
+            let len = get_u32(value, &mut at)? as usize;
+            let bytes = value.get(at..at + len).ok_or(ByteCodeError::NotEnoughBytes)?.to_vec();
+            at += len;
+            members.push(Member { name, bytes });
+        }
+
+        if symbols.values().any(|x| *x as usize >= members.len()) {
+            return Err(ByteCodeError::ElementNotFound);
+        }
+        Ok(Self { symbols, members })
+    }
+}
@@ -0,0 +1,1029 @@
+//! textual representation of bytecode files. see `langref/asm.md` for the format.
+//!
+//! [assemble] and [disassemble] round-trip: assembling the output of [disassemble] gives back the
+//! exact same bytes, as long as the input file has the layout written by the compiler and linker.
+
+use nostd::prelude::*;
+use nostd::{fmt, ops::Bound, collections::{BTreeMap, HashMap}};
+use crate::*;
+use crate::debug_info::{DebugInfo, DebugEntry, DEBUG_INFO_TAG};
+use crate::disasm::Disasm;
+
+#[derive(Clone, PartialEq)]
+pub enum AsmErrorTy {
+    UnterminatedString,
+    /// only `\n`, `\t`, `\\`, `\"` and `\xHH` (below 0x80) are supported
+    InvalidEscape,
+    UnknownDirective(String),
+    UnknownOp(String),
+    InvalidNumber(String),
+    /// a token that is not allowed at this position
+    UnexpectedToken(String),
+    /// the given directive is missing operands
+    MissingOperand(&'static str),
+    LabelDefinedTwice(String),
+    UnknownLabel(String),
+    /// before V4, constant arrays can have at most 65535 elements
+    ArrTooLong,
+    /// before V4, there can be at most 65535 globals
+    TooManyGlobals,
+    /// section tags have to be 4 bytes long
+    InvalidTag(String),
+    /// weak globals need V5 or later
+    WeakBeforeV5,
+    /// the ops could not be relaxed, see [crate::encoding::relax]
+    Relax(ByteCodeError),
+}
+
+impl fmt::Debug for AsmErrorTy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmErrorTy::UnterminatedString => write!(f, "Unterminated string"),
+            AsmErrorTy::InvalidEscape => write!(f, "Invalid escape sequence in string"),
+            AsmErrorTy::UnknownDirective(d) => write!(f, "Unknown directive `{}`", d),
+            AsmErrorTy::UnknownOp(op) => write!(f, "Unknown op `{}`", op),
+            AsmErrorTy::InvalidNumber(n) => write!(f, "Invalid number `{}`", n),
+            AsmErrorTy::UnexpectedToken(t) => write!(f, "Unexpected `{}`", t),
+            AsmErrorTy::MissingOperand(d) => write!(f, "Missing operand for `{}`", d),
+            AsmErrorTy::LabelDefinedTwice(l) => write!(f, "Label `{}` defined twice", l),
+            AsmErrorTy::UnknownLabel(l) => write!(f, "Unknown label `{}`", l),
+            AsmErrorTy::ArrTooLong => write!(f, "Constant array has more than 65535 elements"),
+            AsmErrorTy::TooManyGlobals => write!(f, "More than 65535 globals"),
+            AsmErrorTy::InvalidTag(t) => write!(f, "Section tag `{}` is not 4 bytes long", t),
+            AsmErrorTy::WeakBeforeV5 => write!(f, "Weak globals need V5 or later"),
+            AsmErrorTy::Relax(e) => write!(f, "Could not relax ops: {:?}", e),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct AsmError {
+    /// 1-based
+    pub line: usize,
+    pub ty: AsmErrorTy,
+}
+
+impl fmt::Debug for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {:?}", self.line, self.ty)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Tok<'src> {
+    Word(&'src str),
+    Str(String),
+    /// `<kind: arg>`
+    Angle(&'src str, Box<Tok<'src>>),
+}
+
+impl<'src> Tok<'src> {
+    fn text(&self) -> String {
+        match self {
+            Tok::Word(w) => w.to_string(),
+            Tok::Str(s) => escape(s),
+            Tok::Angle(kind, arg) => format!("<{}: {}>", kind, arg.text()),
+        }
+    }
+
+    fn directive(&self) -> Option<&'src str> {
+        match self {
+            Tok::Word(w) if w.len() > 1 && w.starts_with('.') => Some(w),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> Option<&'src str> {
+        match self {
+            Tok::Word(w) if w.len() > 1 && w.ends_with(':') => Some(&w[..w.len() - 1]),
+            _ => None,
+        }
+    }
+}
+
+struct Lexer<'src> {
+    src: &'src str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'src> Lexer<'src> {
+    fn err(&self, ty: AsmErrorTy) -> AsmError {
+        AsmError { line: self.line, ty }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                self.pos = self.src[self.pos..].find('\n')
+                    .map_or(self.src.len(), |x| self.pos + x);
+            } else if c.is_whitespace() {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                self.pos += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// until whitespace, or [stop]
+    fn word(&mut self, stop: char) -> &'src str {
+        let begin = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == stop {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        &self.src[begin..self.pos]
+    }
+
+    fn string(&mut self) -> Result<String, AsmError> {
+        let mut out = String::new();
+        self.pos += 1;
+        loop {
+            let c = self.peek()
+                .filter(|c| *c != '\n')
+                .ok_or(self.err(AsmErrorTy::UnterminatedString))?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => break,
+                '\\' => {
+                    let esc = self.peek().ok_or(self.err(AsmErrorTy::UnterminatedString))?;
+                    self.pos += esc.len_utf8();
+                    out.push(match esc {
+                        'n' => '\n',
+                        't' => '\t',
+                        '\\' => '\\',
+                        '"' => '"',
+                        'x' => {
+                            let v = self.src.get(self.pos..self.pos + 2)
+                                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                                .filter(|x| *x < 0x80)
+                                .ok_or(self.err(AsmErrorTy::InvalidEscape))?;
+                            self.pos += 2;
+                            v as char
+                        }
+                        _ => Err(self.err(AsmErrorTy::InvalidEscape))?,
+                    });
+                }
+                _ => out.push(c),
+            }
+        }
+        Ok(out)
+    }
+
+    fn next(&mut self) -> Option<Result<(usize, Tok<'src>), AsmError>> {
+        self.skip_space();
+        let line = self.line;
+        let c = self.peek()?;
+        let rest = &self.src[self.pos..];
+        Some(if c == '"' {
+            self.string().map(|s| (line, Tok::Str(s)))
+        } else if c == '<' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+            let kind = self.word(':');
+            if self.peek() != Some(':') {
+                return Some(Err(self.err(AsmErrorTy::UnknownOp(format!("<{}", kind)))));
+            }
+            self.pos += 1;
+            self.skip_space();
+            let arg = if self.peek() == Some('"') {
+                match self.string() {
+                    Ok(s) => Tok::Str(s),
+                    Err(e) => return Some(Err(e)),
+                }
+            } else {
+                Tok::Word(self.word('>'))
+            };
+            self.skip_space();
+            if self.peek() != Some('>') {
+                return Some(Err(self.err(AsmErrorTy::UnexpectedToken(format!("<{}: {}", kind, arg.text())))));
+            }
+            self.pos += 1;
+            Ok((line, Tok::Angle(kind, Box::new(arg))))
+        } else {
+            Ok((line, Tok::Word(self.word(char::MAX))))
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum RefKind {
+    Label(String),
+    /// `data+N`
+    Data(u32),
+    /// the first `.str` entry with that content, which is appended to the data table if missing
+    Str(String),
+}
+
+#[derive(Clone, Debug)]
+struct Ref {
+    line: usize,
+    kind: RefKind,
+}
+
+#[derive(Clone, Debug)]
+enum AsmOp {
+    Op(Op),
+    Const(Ref),
+    Unresolved(Ref),
+    ArrAt(PushConstArrType, Ref),
+}
+
+impl AsmOp {
+    fn size(&self, enc: OpEncoding) -> usize {
+        match self {
+            AsmOp::Op(op) => op.relocatable_size(enc),
+            _ => Op::Const { idx: 0 }.relocatable_size(enc),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Entry {
+    Str(String),
+    Code(Vec<AsmOp>),
+    Arr(PushConstArrType, Vec<Num>),
+    Bytes(Vec<u8>),
+}
+
+impl Entry {
+    fn size(&self, arr_len_size: usize, enc: OpEncoding) -> usize {
+        match self {
+            Entry::Str(s) => s.len() + 1,
+            Entry::Code(ops) => ops.iter().map(|x| x.size(enc)).sum::<usize>() + 1,
+            Entry::Arr(PushConstArrType::U8, v) => arr_len_size + v.len(),
+            Entry::Arr(PushConstArrType::I16, v) => arr_len_size + v.len() * 2,
+            Entry::Bytes(b) => b.len(),
+        }
+    }
+
+    fn section(&self) -> Section {
+        match self {
+            Entry::Str(_) | Entry::Bytes(_) => Section::Strings,
+            Entry::Code(_) => Section::Code,
+            Entry::Arr(..) => Section::Pool,
+        }
+    }
+}
+
+/// sections of the data table, in the order they are laid out. ignored before V4
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Strings,
+    Code,
+    Pool,
+}
+
+impl Section {
+    fn name(&self) -> &'static str {
+        match self {
+            Section::Strings => "strings",
+            Section::Code => "code",
+            Section::Pool => "pool",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Module {
+    version: Option<(u8, u8)>,
+    /// encoding, weather it is relaxed, and the line of the `.encoding` directive
+    encoding: Option<(OpEncoding, bool, usize)>,
+    /// section set by the last `.section` directive
+    section: Option<Section>,
+    /// line of the first constant array that is too long before V4
+    long_arr: Option<usize>,
+    entries: Vec<(Section, Entry)>,
+    /// label name -> index of the next entry
+    labels: HashMap<String, usize>,
+    /// (name, value, weak)
+    globals: Vec<(Ref, Ref, bool)>,
+    /// line of the `.weak_globals` directive
+    weak_globals: Option<usize>,
+    main: Vec<AsmOp>,
+    dso: Vec<Ref>,
+    debug: Option<DebugInfo>,
+    sections: Vec<(SectionTag, Vec<u8>)>,
+}
+
+fn num<T: TryFrom<i64>>(line: usize, tok: &Tok) -> Result<T, AsmError> {
+    let err = || AsmError { line, ty: AsmErrorTy::InvalidNumber(tok.text()) };
+    let Tok::Word(w) = tok else { return Err(err()) };
+    let (neg, digits) = match w.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, *w),
+    };
+    let v = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse::<i64>(),
+    }.map_err(|_| err())?;
+    T::try_from(if neg { -v } else { v }).map_err(|_| err())
+}
+
+fn reference(line: usize, tok: &Tok) -> Result<Ref, AsmError> {
+    let kind = match tok {
+        Tok::Str(s) => RefKind::Str(s.clone()),
+        Tok::Word(w) => match w.strip_prefix("data+") {
+            Some(off) => RefKind::Data(num(line, &Tok::Word(off))?),
+            None => RefKind::Label(w.to_string()),
+        },
+        Tok::Angle(..) => Err(AsmError { line, ty: AsmErrorTy::UnexpectedToken(tok.text()) })?,
+    };
+    Ok(Ref { line, kind })
+}
+
+fn simple_op(word: &str) -> Option<Op> {
+    Some(match word {
+        "+" => Op::Add,
+        "-" => Op::Sub,
+        "*" => Op::Mul,
+        "%" => Op::Mod,
+        "/" => Op::Div,
+        "." => Op::Dup,
+        "$" => Op::Swap,
+        ";" => Op::Pop,
+        "!" => Op::Exec,
+        "?" => Op::Select,
+        "<" => Op::Lt,
+        ">" => Op::Gt,
+        "=" => Op::Eq,
+        "~" => Op::Not,
+        "l" => Op::RoL,
+        "r" => Op::RoR,
+        "@+" => Op::ArrCat,
+        "@0" => Op::ArrFirst,
+        "@<" => Op::ArrSkip1,
+        "@*" => Op::ArrLen,
+        "_" => Op::Pack,
+        "typeid!" => Op::TypeId,
+        "[!]" => Op::Materialize,
+        "opsOf!" => Op::OpsOf,
+        "constAt!" => Op::ConstAt,
+        "{" => Op::ArrBegin,
+        "}" => Op::ArrEnd,
+        _ => None?,
+    })
+}
+
+fn op(line: usize, tok: &Tok) -> Result<AsmOp, AsmError> {
+    let unknown = || AsmError { line, ty: AsmErrorTy::UnknownOp(tok.text()) };
+    Ok(match tok {
+        Tok::Word(w) => match simple_op(w) {
+            Some(op) => AsmOp::Op(op),
+            None if w.starts_with(|c: char| c == '-' || c.is_ascii_digit()) =>
+                AsmOp::Op(Op::Push { val: num(line, tok)? }),
+            None => Err(unknown())?,
+        },
+
+        Tok::Angle(kind, arg) => match *kind {
+            "const" => AsmOp::Const(reference(line, arg)?),
+            "unresolved" => AsmOp::Unresolved(reference(line, arg)?),
+            "arr-at(U8)" => AsmOp::ArrAt(PushConstArrType::U8, reference(line, arg)?),
+            "arr-at(I16)" => AsmOp::ArrAt(PushConstArrType::I16, reference(line, arg)?),
+            "reach" => AsmOp::Op(Op::Reach { down: num(line, arg)? }),
+            "system" => AsmOp::Op(Op::System { id: num(line, arg)? }),
+            "dso" => AsmOp::Op(Op::DsoConst { dso_id: num(line, arg)? }),
+            _ => Err(unknown())?,
+        },
+
+        Tok::Str(_) => Err(AsmError { line, ty: AsmErrorTy::UnexpectedToken(tok.text()) })?,
+    })
+}
+
+fn parse(src: &str) -> Result<Module, AsmError> {
+    let mut lexer = Lexer { src, pos: 0, line: 1 };
+    let mut toks = vec!();
+    while let Some(tok) = lexer.next() {
+        toks.push(tok?);
+    }
+
+    let mut m = Module::default();
+    let mut toks = toks.into_iter().peekable();
+    while let Some((line, tok)) = toks.next() {
+        if let Some(label) = tok.label() {
+            if m.labels.insert(label.to_string(), m.entries.len()).is_some() {
+                Err(AsmError { line, ty: AsmErrorTy::LabelDefinedTwice(label.to_string()) })?;
+            }
+            continue;
+        }
+
+        let Some(dir) = tok.directive() else {
+            Err(AsmError { line, ty: AsmErrorTy::UnexpectedToken(tok.text()) })?
+        };
+
+        // operands go until the next directive or label
+        let mut operands = vec!();
+        while let Some((l, t)) = toks.next_if(|(_, t)| t.directive().is_none() && t.label().is_none()) {
+            operands.push((l, t));
+        }
+
+        let dir: &'static str = match dir {
+            ".version" => ".version",
+            ".encoding" => ".encoding",
+            ".str" => ".str",
+            ".code" => ".code",
+            ".main" => ".main",
+            ".u8arr" => ".u8arr",
+            ".i16arr" => ".i16arr",
+            ".bytes" => ".bytes",
+            ".global" => ".global",
+            ".weak" => ".weak",
+            ".weak_globals" => ".weak_globals",
+            ".dso" => ".dso",
+            ".file" => ".file",
+            ".loc" => ".loc",
+            ".section" => ".section",
+            ".ext" => ".ext",
+            _ => Err(AsmError { line, ty: AsmErrorTy::UnknownDirective(dir.to_string()) })?,
+        };
+
+        let fixed = |n: usize| -> Result<(), AsmError> {
+            if let Some((l, t)) = operands.get(n) {
+                Err(AsmError { line: *l, ty: AsmErrorTy::UnexpectedToken(t.text()) })
+            } else if operands.len() < n {
+                Err(AsmError { line, ty: AsmErrorTy::MissingOperand(dir) })
+            } else {
+                Ok(())
+            }
+        };
+
+        let push = |m: &mut Module, e: Entry| m.entries.push((m.section.unwrap_or(e.section()), e));
+
+        match dir {
+            ".version" => {
+                fixed(2)?;
+                m.version = Some((num(operands[0].0, &operands[0].1)?, num(operands[1].0, &operands[1].1)?));
+            }
+
+            ".encoding" => {
+                fixed(1)?;
+                m.encoding = Some(match &operands[0] {
+                    (_, Tok::Word("fixed")) => (OpEncoding::Fixed, false, line),
+                    (_, Tok::Word("compact")) => (OpEncoding::Compact, false, line),
+                    (_, Tok::Word("relaxed")) => (OpEncoding::Compact, true, line),
+                    (l, t) => Err(AsmError { line: *l, ty: AsmErrorTy::UnexpectedToken(t.text()) })?,
+                });
+            }
+
+            ".str" => {
+                fixed(1)?;
+                match &operands[0] {
+                    (_, Tok::Str(s)) => push(&mut m, Entry::Str(s.clone())),
+                    (l, t) => Err(AsmError { line: *l, ty: AsmErrorTy::UnexpectedToken(t.text()) })?,
+                }
+            }
+
+            ".code" => {
+                let ops = operands.iter().map(|(l, t)| op(*l, t)).collect::<Result<_, _>>()?;
+                push(&mut m, Entry::Code(ops));
+            }
+
+            ".main" => {
+                for (l, t) in operands.iter() {
+                    m.main.push(op(*l, t)?);
+                }
+            }
+
+            ".u8arr" | ".i16arr" => {
+                let (ty, vals) = if dir == ".u8arr" {
+                    (PushConstArrType::U8, operands.iter()
+                        .map(|(l, t)| num::<u8>(*l, t).map(Num::from))
+                        .collect::<Result<_, _>>()?)
+                } else {
+                    (PushConstArrType::I16, operands.iter()
+                        .map(|(l, t)| num::<i16>(*l, t).map(Num::from))
+                        .collect::<Result<_, _>>()?)
+                };
+                if operands.len() > u16::MAX as usize {
+                    m.long_arr.get_or_insert(line);
+                }
+                push(&mut m, Entry::Arr(ty, vals));
+            }
+
+            ".bytes" => {
+                let bytes = operands.iter().map(|(l, t)| num(*l, t)).collect::<Result<_, _>>()?;
+                push(&mut m, Entry::Bytes(bytes));
+            }
+
+            ".global" | ".weak" => {
+                fixed(2)?;
+                m.globals.push((reference(operands[0].0, &operands[0].1)?, reference(operands[1].0, &operands[1].1)?, dir == ".weak"));
+            }
+
+            ".weak_globals" => {
+                fixed(0)?;
+                m.weak_globals = Some(line);
+            }
+
+            ".section" => {
+                fixed(1)?;
+                m.section = Some(match &operands[0] {
+                    (_, Tok::Word("strings")) => Section::Strings,
+                    (_, Tok::Word("code")) => Section::Code,
+                    (_, Tok::Word("pool")) => Section::Pool,
+                    (l, t) => Err(AsmError { line: *l, ty: AsmErrorTy::UnexpectedToken(t.text()) })?,
+                });
+            }
+
+            ".ext" => {
+                let Some((l, t)) = operands.first() else {
+                    Err(AsmError { line, ty: AsmErrorTy::MissingOperand(dir) })?
+                };
+                let Tok::Str(tag) = t else {
+                    Err(AsmError { line: *l, ty: AsmErrorTy::UnexpectedToken(t.text()) })?
+                };
+                let tag = SectionTag::try_from(tag.as_bytes())
+                    .map_err(|_| AsmError { line: *l, ty: AsmErrorTy::InvalidTag(tag.clone()) })?;
+                let data = operands[1..].iter().map(|(l, t)| num(*l, t)).collect::<Result<_, _>>()?;
+                m.sections.push((tag, data));
+            }
+
+            ".dso" => {
+                fixed(1)?;
+                m.dso.push(reference(operands[0].0, &operands[0].1)?);
+            }
+
+            ".file" => {
+                fixed(1)?;
+                match &operands[0] {
+                    (_, Tok::Str(s)) => m.debug.get_or_insert_default().files.push(s.clone()),
+                    (l, t) => Err(AsmError { line: *l, ty: AsmErrorTy::UnexpectedToken(t.text()) })?,
+                }
+            }
+
+            ".loc" => {
+                fixed(5)?;
+                let v = operands.iter().map(|(l, t)| num(*l, t)).collect::<Result<Vec<u32>, _>>()?;
+                m.debug.get_or_insert_default().entries.push(DebugEntry {
+                    pos: v[0],
+                    len: v[1],
+                    file: v[2],
+                    line: v[3],
+                    col: v[4],
+                });
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(m)
+}
+
+impl Module {
+    fn refs(&self) -> impl Iterator<Item = &Ref> {
+        fn op_ref(op: &AsmOp) -> Option<&Ref> {
+            match op {
+                AsmOp::Op(_) => None,
+                AsmOp::Const(r) | AsmOp::Unresolved(r) | AsmOp::ArrAt(_, r) => Some(r),
+            }
+        }
+
+        self.entries.iter()
+            .flat_map(|(_, e)| match e {
+                Entry::Code(ops) => ops.as_slice(),
+                _ => &[],
+            })
+            .filter_map(op_ref)
+            .chain(self.globals.iter().flat_map(|(a, b, _)| [a, b]))
+            .chain(self.main.iter().filter_map(op_ref))
+            .chain(self.dso.iter())
+    }
+}
+
+/// assembles the text format into a bytecode file
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut m = parse(src)?;
+
+    let (enc, relaxed, encoding_line) = m.encoding.unwrap_or_default();
+    let mut header = Header::default();
+    header.set_op_encoding(enc);
+    if let Some((min_reader_version, writer_version)) = m.version {
+        header.min_reader_version = min_reader_version;
+        header.writer_version = writer_version;
+    }
+
+    // strings referenced by content
+    let mut strs = HashMap::new();
+    for (idx, (_, e)) in m.entries.iter().enumerate() {
+        if let Entry::Str(s) = e {
+            strs.entry(s.clone()).or_insert(idx);
+        }
+    }
+    let missing = m.refs()
+        .filter_map(|r| match &r.kind {
+            RefKind::Str(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for s in missing {
+        if !strs.contains_key(&s) {
+            strs.insert(s.clone(), m.entries.len());
+            m.entries.push((Section::Strings, Entry::Str(s)));
+        }
+    }
+
+    // since V4, the entries are grouped by section
+    let mut order = (0..m.entries.len()).collect::<Vec<_>>();
+    if header.has_sections() {
+        order.sort_by_key(|idx| m.entries[*idx].0);
+    }
+    let arr_len_size = header.arr_len_size();
+    if let (false, Some(line)) = (header.has_sections(), m.long_arr) {
+        Err(AsmError { line, ty: AsmErrorTy::ArrTooLong })?;
+    }
+
+    // offsets[idx] is the offset of entry idx. labels after the last entry point to the end
+    let mut offs = vec!(0_u32; m.entries.len() + 1);
+    let mut pos = 0_u32;
+    for idx in order.iter() {
+        let (section, e) = &m.entries[*idx];
+        offs[*idx] = pos;
+        pos += e.size(arr_len_size, enc) as u32;
+        match section {
+            Section::Strings => header.strings_len = pos,
+            Section::Code => header.code_len = pos - header.strings_len,
+            Section::Pool => (),
+        }
+    }
+    offs[m.entries.len()] = pos;
+    if !header.has_sections() {
+        header.strings_len = 0;
+        header.code_len = 0;
+    }
+
+    let resolve = |r: &Ref| -> Result<u32, AsmError> {
+        Ok(match &r.kind {
+            RefKind::Data(off) => *off,
+            RefKind::Str(s) => offs[strs[s]],
+            RefKind::Label(l) => offs[*m.labels.get(l)
+                .ok_or(AsmError { line: r.line, ty: AsmErrorTy::UnknownLabel(l.clone()) })?],
+        })
+    };
+
+    let write_ops = |out: &mut Vec<u8>, ops: &[AsmOp]| -> Result<(), AsmError> {
+        for op in ops.iter() {
+            let op = match op {
+                AsmOp::Op(op) => op.clone(),
+                AsmOp::Const(r) => Op::Const { idx: resolve(r)? },
+                AsmOp::Unresolved(r) => Op::Unresolved { id: resolve(r)? },
+                AsmOp::ArrAt(ty, r) => Op::ArrAt { ty: ty.clone(), idx: resolve(r)? },
+            };
+            op.write_relocatable(out, enc).unwrap();
+        }
+        Ok(())
+    };
+
+    let data_begin = header.data_begin();
+    let mut out = vec![0_u8; data_begin];
+    for idx in order.iter() {
+        match &m.entries[*idx].1 {
+            Entry::Str(s) => {
+                out.extend_from_slice(s.as_bytes());
+                out.push(0);
+            }
+
+            Entry::Code(ops) => {
+                write_ops(&mut out, ops)?;
+                Op::Terminate.write(&mut out).unwrap();
+            }
+
+            Entry::Arr(ty, vals) => {
+                out.extend_from_slice(&(vals.len() as u32).to_le_bytes()[..arr_len_size]);
+                for v in vals.iter() {
+                    match ty {
+                        PushConstArrType::U8 => out.push(*v as u8),
+                        PushConstArrType::I16 => out.extend_from_slice(&(*v as i16).to_le_bytes()),
+                    }
+                }
+            }
+
+            Entry::Bytes(b) => out.extend_from_slice(b),
+        }
+    }
+
+    header.globals_tab_off = (out.len() - data_begin) as u32;
+    header.globals_tab_num = m.globals.len() as u32;
+    if !header.has_sections() && m.globals.len() > u16::MAX as usize {
+        Err(AsmError { line: m.globals[u16::MAX as usize].0.line, ty: AsmErrorTy::TooManyGlobals })?;
+    }
+    // the linker keeps the flag even if no weak global is left
+    if let Some(line) = m.globals.iter().find(|g| g.2).map(|g| g.0.line).or(m.weak_globals) {
+        if header.writer_version < 5 {
+            Err(AsmError { line, ty: AsmErrorTy::WeakBeforeV5 })?;
+        }
+        header.set_weak_globals();
+    }
+    for (name, val, weak) in m.globals.iter() {
+        Export { name: resolve(name)?, const_id: resolve(val)?, weak: *weak }.write(&mut out).unwrap();
+    }
+
+    write_ops(&mut out, &m.main)?;
+    Op::Terminate.write(&mut out).unwrap();
+
+    let ex = Extensions {
+        dso: m.dso.iter().map(resolve).collect::<Result<_, _>>()?,
+        debug_info: m.debug,
+        sections: m.sections,
+    };
+    header._extended_header_off = ex.write(out.len(), &mut out);
+
+    out[0..data_begin].copy_from_slice(&header.serialize());
+
+    if relaxed {
+        out = encoding::relax(&out).map_err(|e| AsmError { line: encoding_line, ty: AsmErrorTy::Relax(e) })?;
+    }
+    Ok(out)
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\x7f' => out.push_str(format!("\\x{:02x}", c as u32).as_str()),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// if [bytes] consists only of null terminated strings without control characters
+fn split_strings(bytes: &[u8]) -> Option<Vec<&str>> {
+    let bytes = bytes.strip_suffix(&[0])?;
+    let s = str::from_utf8(bytes).ok()?;
+    if s.chars().any(|c| c.is_control() && c != '\0') {
+        return None;
+    }
+    Some(s.split('\0').collect())
+}
+
+#[derive(Clone, Debug)]
+enum EntryKind {
+    Str,
+    Arr(PushConstArrType),
+    Code,
+}
+
+struct Writer<'bc, 'asm> {
+    bc: &'bc Bytecode<'asm>,
+    /// every data table offset that gets a label, and the entry at it, if it can be decoded
+    labels: BTreeMap<usize, Option<(EntryKind, usize)>>,
+    /// content -> offset of the first `.str` entry with that content
+    first_str: HashMap<&'asm str, usize>,
+}
+
+impl<'bc, 'asm> Writer<'bc, 'asm> {
+    /// end of the entry, if it can be decoded and fits into the data table
+    fn extent(&self, off: usize, kind: &EntryKind) -> Option<usize> {
+        let data = self.bc.data_table();
+        let end = match kind {
+            EntryKind::Str => off + self.bc.string(off as u32).ok()?.len() + 1,
+
+            EntryKind::Arr(ty) => off + self.bc.const_arr_bytes(ty, off as u32).ok()?.len(),
+
+            EntryKind::Code => {
+                let mut iter = self.bc.const_ops(off as u32).ok()?;
+                for op in iter.by_ref() {
+                    op.ok()?;
+                }
+                iter.base - self.bc.header.data_begin() + 1
+            }
+        };
+        (end <= data.len()).then_some(end)
+    }
+
+    fn label(&self, off: u32) -> String {
+        if self.labels.contains_key(&(off as usize)) {
+            format!("d{}", off)
+        } else {
+            format!("data+{}", off)
+        }
+    }
+
+    /// references to strings are written as the string itself where that resolves to the same offset
+    fn str_ref(&self, off: u32) -> String {
+        match self.bc.string(off) {
+            Ok(s) if self.first_str.get(s) == Some(&(off as usize)) => escape(s),
+            _ => self.label(off),
+        }
+    }
+
+    fn op(&self, op: &Op) -> Result<String, ByteCodeError> {
+        Ok(match op {
+            Op::Const { idx } => format!("<const: {}>", self.label(*idx)),
+            Op::Unresolved { id } => format!("<unresolved: {}>", self.str_ref(*id)),
+            Op::ArrAt { ty, idx } => format!("<arr-at({:?}): {}>", ty, self.label(*idx)),
+            Op::ArrBegin => "{".to_string(),
+            Op::ArrEnd => "}".to_string(),
+            _ => Disasm::new(self.bc).op(op)?,
+        })
+    }
+
+    fn ops(&self, out: &mut String, dir: &str, iter: OpsIter) -> Result<(), ByteCodeError> {
+        out.push_str(dir);
+        for op in iter {
+            out.push(' ');
+            out.push_str(self.op(&op?.1)?.as_str());
+        }
+        out.push('\n');
+        Ok(())
+    }
+}
+
+/// writes the text format of the whole module
+pub fn disassemble(bc: &Bytecode) -> Result<String, ByteCodeError> {
+    // the assembler lays out relaxed files as relocatable first, so positions have to be listed in that layout
+    if bc.header.op_encoding() == OpEncoding::Compact && !encoding::is_relocatable(bc)? {
+        let bytes = encoding::reencode(bc.bytes, OpEncoding::Compact)?;
+        return write_module(&Bytecode::try_from(bytes.as_slice())?, true);
+    }
+    write_module(bc, false)
+}
+
+fn write_module(bc: &Bytecode, relaxed: bool) -> Result<String, ByteCodeError> {
+    let data = bc.data_table();
+
+    let mut strs = vec!();
+    let mut arrs = vec!();
+    let codes = bc.codes_in_data_table()?;
+    for iter in codes.iter().map(|c| bc.const_ops(*c as u32))
+        .chain([Ok(bc.main_ops())])
+    {
+        for op in iter? {
+            match op?.1 {
+                Op::Unresolved { id } => strs.push(id as usize),
+                Op::ArrAt { ty, idx } => arrs.push((ty, idx as usize)),
+                _ => (),
+            }
+        }
+    }
+    strs.extend(bc.globals().map(|g| g.name as usize));
+    strs.extend(bc.dso_names()?.into_iter().map(|x| x as usize));
+
+    // codes take priority over arrays, which take priority over strings
+    let mut kinds = BTreeMap::new();
+    kinds.extend(strs.into_iter().map(|off| (off, EntryKind::Str)));
+    kinds.extend(arrs.into_iter().map(|(ty, off)| (off, EntryKind::Arr(ty))));
+    kinds.extend(codes.into_iter().map(|off| (off, EntryKind::Code)));
+
+    let mut w = Writer {
+        bc,
+        labels: BTreeMap::new(),
+        first_str: HashMap::new(),
+    };
+    for (off, kind) in kinds.into_iter().filter(|(off, _)| *off < data.len()) {
+        let ent = w.extent(off, &kind).map(|end| (kind, end));
+        w.labels.insert(off, ent);
+    }
+
+    // beginning of every section, since V4
+    let sections = match (bc.strings_section(), bc.code_section(), bc.pool_section()) {
+        (Some(s), Some(c), Some(p)) => vec!((s.start, Section::Strings), (c.start, Section::Code), (p.start, Section::Pool)),
+        _ => vec!(),
+    };
+
+    // split the data table into entries. everything that can't be decoded is written as raw bytes.
+    // entries never cross section boundaries
+    let mut chunks = vec!();
+    let mut cursor = 0;
+    while cursor < data.len() {
+        let next_label = w.labels.range((Bound::Excluded(cursor), Bound::Unbounded))
+            .next()
+            .map_or(data.len(), |(off, _)| *off);
+        let next = sections.iter()
+            .map(|(off, _)| *off)
+            .filter(|off| *off > cursor)
+            .fold(next_label, usize::min);
+        match w.labels.get(&cursor) {
+            Some(Some((kind, end))) if *end <= next => {
+                if let EntryKind::Str = kind {
+                    w.first_str.entry(bc.string(cursor as u32)?).or_insert(cursor);
+                }
+                chunks.push((cursor, Some(kind.clone())));
+                cursor = *end;
+            }
+            _ => {
+                // unreferenced strings, for example names of symbols that got linked
+                match split_strings(&data[cursor..next]) {
+                    Some(strs) => for s in strs {
+                        w.first_str.entry(s).or_insert(cursor);
+                        chunks.push((cursor, Some(EntryKind::Str)));
+                        cursor += s.len() + 1;
+                    },
+                    None => {
+                        chunks.push((cursor, None));
+                        cursor = next;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("# assemble with `h6 as`\n");
+    out.push_str(format!(".version {} {}\n", bc.header.min_reader_version, bc.header.writer_version).as_str());
+    if bc.header.op_encoding() == OpEncoding::Compact {
+        out.push_str(if relaxed { ".encoding relaxed\n" } else { ".encoding compact\n" });
+    }
+    if bc.header.flags & FLAG_WEAK_GLOBALS != 0 {
+        out.push_str(".weak_globals\n");
+    }
+    out.push('\n');
+
+    let mut section = None;
+    for (idx, (off, kind)) in chunks.iter().enumerate() {
+        let end = chunks.get(idx + 1).map_or(data.len(), |x| x.0);
+        if let Some((_, sec)) = sections.iter().rev().find(|(begin, _)| begin <= off)
+            && section != Some(*sec)
+        {
+            out.push_str(format!(".section {}\n", sec.name()).as_str());
+            section = Some(*sec);
+        }
+        if w.labels.contains_key(off) {
+            out.push_str(format!("d{}: ", off).as_str());
+        }
+        match kind {
+            Some(EntryKind::Str) => {
+                out.push_str(format!(".str {}\n", escape(bc.string(*off as u32)?)).as_str());
+            }
+
+            Some(EntryKind::Code) => w.ops(&mut out, ".code", bc.const_ops(*off as u32)?)?,
+
+            Some(EntryKind::Arr(ty)) => {
+                let (dir, elt) = match ty {
+                    PushConstArrType::U8 => (".u8arr", 1),
+                    PushConstArrType::I16 => (".i16arr", 2),
+                };
+                out.push_str(dir);
+                for v in data[off + bc.header.arr_len_size()..end].chunks(elt) {
+                    let v = match ty {
+                        PushConstArrType::U8 => v[0] as Num,
+                        PushConstArrType::I16 => i16::from_le_bytes([v[0], v[1]]) as Num,
+                    };
+                    out.push_str(format!(" {}", v).as_str());
+                }
+                out.push('\n');
+            }
+
+            None => {
+                for (i, line) in data[*off..end].chunks(16).enumerate() {
+                    if i > 0 {
+                        out.push_str("    ");
+                    }
+                    out.push_str(".bytes");
+                    for b in line.iter() {
+                        out.push_str(format!(" {:#04x}", b).as_str());
+                    }
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out.push('\n');
+
+    for global in bc.globals() {
+        let dir = if global.weak { ".weak" } else { ".global" };
+        out.push_str(format!("{} {} {}\n", dir, w.str_ref(global.name), w.label(global.const_id)).as_str());
+    }
+
+    w.ops(&mut out, ".main", bc.main_ops())?;
+
+    for dso in bc.dso_names()? {
+        out.push_str(format!(".dso {}\n", w.str_ref(dso)).as_str());
+    }
+
+    if let Some(debug) = bc.debug_info() {
+        let debug = debug?;
+        for file in debug.files.iter() {
+            out.push_str(format!(".file {}\n", escape(file)).as_str());
+        }
+        for ent in debug.entries.iter() {
+            out.push_str(format!(".loc {} {} {} {} {}\n", ent.pos, ent.len, ent.file, ent.line, ent.col).as_str());
+        }
+    }
+
+    // the debug info section is written by .file and .loc
+    for section in bc.named_sections()?.into_iter().filter(|x| x.tag != DEBUG_INFO_TAG) {
+        let tag = section.tag.iter().map(|b| char::from(*b)).collect::<String>();
+        out.push_str(format!(".ext {}", escape(&tag)).as_str());
+        for b in bc.section_data(&section)? {
+            out.push_str(format!(" {}", b).as_str());
+        }
+        out.push('\n');
+    }
+
+    Ok(out)
+}
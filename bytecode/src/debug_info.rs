@@ -0,0 +1,145 @@
+use nostd::prelude::*;
+use nostd::{io, str};
+use crate::{ByteCodeError, SectionTag};
+
+/// tag of the named section that contains the [DebugInfo]
+pub const DEBUG_INFO_TAG: SectionTag = *b"DBUG";
+
+/// one contiguous range of op bytes that originate from the same source location
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugEntry {
+    /// relative to data table.
+    /// positions at or after the globals table offset point into the main ops area
+    pub pos: u32,
+    /// amount of bytes covered by this entry
+    pub len: u32,
+    /// index into [DebugInfo::files]
+    pub file: u32,
+    /// 1-based
+    pub line: u32,
+    /// 1-based
+    pub col: u32,
+}
+
+impl DebugEntry {
+    pub fn contains(&self, pos: u32) -> bool {
+        pos >= self.pos && pos - self.pos < self.len
+    }
+}
+
+/// debug info section:
+///   num files: u32_le
+///   files: num files * (utf8, null terminated)
+///   num entries: u32_le
+///   entries: num entries * { pos: u32_le, len: u32_le, file: u32_le, line: u32_le, col: u32_le }
+///
+/// entries are sorted by pos and do not overlap
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub entries: Vec<DebugEntry>,
+}
+
+impl DebugInfo {
+    /// adds the location of an op. merges with the previous entry if possible
+    pub fn add(&mut self, pos: u32, len: u32, file: u32, line: u32, col: u32) {
+        if let Some(last) = self.entries.last_mut()
+            && last.pos + last.len == pos && last.file == file && last.line == line && last.col == col
+        {
+            last.len += len;
+            return;
+        }
+        self.entries.push(DebugEntry { pos, len, file, line, col });
+    }
+
+    /// position relative to data table
+    pub fn lookup(&self, pos: u32) -> Option<(&str, &DebugEntry)> {
+        let idx = self.entries.partition_point(|x| x.pos <= pos).checked_sub(1)?;
+        let ent = &self.entries[idx];
+        if !ent.contains(pos) {
+            return None;
+        }
+        let file = self.files.get(ent.file as usize)?;
+        Some((file.as_str(), ent))
+    }
+
+    /// appends all files and entries of [other] to this.
+    /// [reloc] maps the positions of [other] to the new positions
+    pub fn merge<F: Fn(u32) -> u32>(&mut self, other: &DebugInfo, reloc: F) {
+        let file_off = self.files.len() as u32;
+        self.files.extend(other.files.iter().cloned());
+        self.entries.extend(other.entries.iter().map(|x| DebugEntry {
+            pos: reloc(x.pos),
+            file: x.file + file_off,
+            ..x.clone()
+        }));
+        self.entries.sort_by_key(|x| x.pos);
+    }
+
+    /// applies [reloc] to the position of every entry
+    pub fn relocate<F: Fn(u32) -> u32>(&mut self, reloc: F) {
+        for ent in self.entries.iter_mut() {
+            ent.pos = reloc(ent.pos);
+        }
+        self.entries.sort_by_key(|x| x.pos);
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec!();
+        out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for file in self.files.iter() {
+            out.extend_from_slice(file.as_bytes());
+            out.push(0);
+        }
+        out.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for ent in self.entries.iter() {
+            for v in [ent.pos, ent.len, ent.file, ent.line, ent.col] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    pub fn write<W: io::Write>(&self, to: &mut W) -> io::Result<()> {
+        to.write_all(self.serialize().as_slice())
+    }
+}
+
+impl<'asm> TryFrom<&'asm [u8]> for DebugInfo {
+    type Error = ByteCodeError;
+
+    fn try_from(value: &'asm [u8]) -> Result<Self, Self::Error> {
+        fn get_u32(from: &[u8], at: &mut usize) -> Result<u32, ByteCodeError> {
+            let slice = from.get(*at..*at + 4).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let mut bytes = [0_u8;4];
+            bytes.copy_from_slice(slice);
+            *at += 4;
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        let mut at = 0;
+        let num_files = get_u32(value, &mut at)?;
+        let mut files = vec!();
+        for _ in 0..num_files {
+            let sl = value.get(at..).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let term = sl.iter().position(|&b| b == 0).ok_or(ByteCodeError::InvalidStringEncoding)?;
+            let name = str::from_utf8(&sl[0..term]).map_err(|_| ByteCodeError::InvalidStringEncoding)?;
+            files.push(name.to_string());
+            at += term + 1;
+        }
+
+        let num_entries = get_u32(value, &mut at)?;
+        let mut entries = vec!();
+        for _ in 0..num_entries {
+            entries.push(DebugEntry {
+                pos: get_u32(value, &mut at)?,
+                len: get_u32(value, &mut at)?,
+                file: get_u32(value, &mut at)?,
+                line: get_u32(value, &mut at)?,
+                col: get_u32(value, &mut at)?,
+            });
+        }
+
+        Ok(Self { files, entries })
+    }
+}
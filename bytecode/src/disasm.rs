@@ -1,38 +1,136 @@
 use nostd::prelude::*;
-use crate::{Op, Bytecode, ByteCodeError, OpsIter};
+use nostd::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::{Num, Op, OpType, OpEncoding, PushConstArrType, Bytecode, ByteCodeError, encoding};
 
 pub struct Disasm<'bc, 'asm> {
     asm: &'bc Bytecode<'asm>,
+    /// constant offset -> name of the first global bound to it
+    names: HashMap<u32, &'asm str>,
 }
 
-// TODO: disasm shouldn't fail if bytecode semi-invalid
+/// one line of a listing
+enum Line<'asm> {
+    Op(Op),
+    /// bytes that could not be decoded, and why
+    Bad(ByteCodeError, &'asm [u8]),
+    /// the area ended before a Terminate op
+    MissingTerminate,
+}
+
+/// decodes the ops at [base] (absolute) until Terminate, skipping over undecodable bytes.
+/// [area] starts at [base], and the ops have to terminate inside of it
+fn decode(base: usize, area: &[u8], enc: OpEncoding) -> Vec<(usize, Line<'_>)> {
+    let mut out = vec!();
+    let mut pos = 0;
+    loop {
+        if pos >= area.len() {
+            out.push((base + pos, Line::MissingTerminate));
+            break;
+        }
+        match OpType::read_enc(&area[pos..], enc) {
+            Ok((_, Op::Terminate)) => break,
+
+            Ok((size, op)) => {
+                out.push((base + pos, Line::Op(op)));
+                pos += size;
+            }
+
+            Err(e @ ByteCodeError::NotEnoughBytes) => {
+                out.push((base + pos, Line::Bad(e, &area[pos..])));
+                out.push((base + area.len(), Line::MissingTerminate));
+                break;
+            }
+
+            Err(e) => {
+                out.push((base + pos, Line::Bad(e, &area[pos..pos + 1])));
+                pos += 1;
+            }
+        }
+    }
+    out
+}
+
+/// printable U8 arrays are shown as string literal, everything else as array of numbers
+fn arr_literal(ty: &PushConstArrType, vals: &[Num]) -> String {
+    let text = vals.iter()
+        .map(|x| u8::try_from(*x).ok().map(char::from).filter(|c| *c == '\n' || !c.is_ascii_control() && c.is_ascii()))
+        .collect::<Option<String>>();
+    match (ty, text) {
+        (PushConstArrType::U8, Some(text)) if !text.is_empty() => format!("{:?}", text),
+        _ => format!("{{ {} }}", vals.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")),
+    }
+}
 
 impl<'bc, 'asm> Disasm<'bc, 'asm> {
     pub fn new(asm: &'bc Bytecode<'asm>) -> Self {
-        Self { asm }
+        let mut names = HashMap::new();
+        for (name, idx) in asm.named_globals().filter_map(Result::ok) {
+            names.entry(idx).or_insert(name);
+        }
+        Self { asm, names }
+    }
+
+    /// area of the constant at [idx] (relative to data table), starting at its absolute position
+    fn const_area(&self, idx: u32) -> Option<(usize, &'asm [u8])> {
+        self.asm.data_table().get(idx as usize..)
+            .map(|area| (self.asm.header.data_begin() + idx as usize, area))
+    }
+
+    /// ops of the constant at [idx], if it can be fully decoded
+    fn const_ops(&self, idx: u32) -> Option<Vec<Op>> {
+        let (base, area) = self.const_area(idx)?;
+        decode(base, area, self.asm.header.op_encoding()).into_iter()
+            .map(|(_, line)| match line {
+                Line::Op(op) => Some(op),
+                _ => None,
+            })
+            .collect()
     }
 
+    /// stops with a `(bad)` marker at the first undecodable op
     pub fn absolute_ops(&self, pos: usize) -> Result<String, ByteCodeError> {
-        let by = &self.asm.bytes[pos..];
-        let ops = OpsIter::new(pos, by)
-            .map(|vp| vp.map(|(_,b)| b))
-            .collect::<Result<Vec<Op>, _>>()?;
-        Ok(self.ops(ops.into_iter())?)
+        let by = self.asm.bytes.get(pos..).ok_or(ByteCodeError::NotEnoughBytes)?;
+        let mut ops = vec!();
+        let mut bad = false;
+        for (_, line) in decode(pos, by, self.asm.header.op_encoding()) {
+            match line {
+                Line::Op(op) => ops.push(op),
+                _ => {
+                    bad = true;
+                    break;
+                }
+            }
+        }
+        let mut out = self.ops(ops.into_iter())?;
+        if bad {
+            out.push_str("(bad) ");
+        }
+        Ok(out)
     }
 
     pub fn arr<I: Iterator<Item = Op>>(&self, len: usize, iter: I) -> Result<String, ByteCodeError> {
+        self.arr_rec(len, iter, &mut vec!())
+    }
+
+    /// anonymous constants are inlined as `<const: data+N = ...>`.
+    /// constants that are currently being inlined are in [visiting], to detect cycles
+    fn arr_rec<I: Iterator<Item = Op>>(&self, len: usize, iter: I, visiting: &mut Vec<u32>) -> Result<String, ByteCodeError> {
         let mut out = String::new();
         if len == 0 {
             out.push_str("{} ");
         } else {
             out.push_str("{ ");
-            out.push_str(self.ops(iter)?.as_str());
+            out.push_str(self.ops_rec(iter, visiting)?.as_str());
             out.push_str("} ");
         }
         Ok(out)
     }
 
     pub fn ops<I: Iterator<Item = Op>>(&self, iter: I) -> Result<String, ByteCodeError> {
+        self.ops_rec(iter, &mut vec!())
+    }
+
+    fn ops_rec<I: Iterator<Item = Op>>(&self, iter: I, visiting: &mut Vec<u32>) -> Result<String, ByteCodeError> {
         let mut iter = iter;
         let mut out = String::new();
 
@@ -41,42 +139,73 @@ impl<'bc, 'asm> Disasm<'bc, 'asm> {
                 let mut items = Vec::new();
                 let mut ind = 1;
 
-                while ind > 0 {
-                    let item = iter.next().ok_or(ByteCodeError::ArrEndMismatch)?;
+                // an unclosed array is printed without its `}`
+                for item in iter.by_ref() {
                     match item {
                         Op::ArrBegin => { ind += 1; }
                         Op::ArrEnd => { ind -= 1; }
                         _ => {}
                     }
 
-                    if ind > 0 {
-                        items.push(item);
+                    if ind == 0 {
+                        break;
                     }
+                    items.push(item);
                 }
 
-                out.push_str(self.arr(items.len(), items.into_iter())?.as_str());
+                if ind == 0 {
+                    out.push_str(self.arr_rec(items.len(), items.into_iter(), visiting)?.as_str());
+                } else {
+                    out.push_str("{ ");
+                    out.push_str(self.ops_rec(items.into_iter(), visiting)?.as_str());
+                }
+            } else if let Op::Const { idx } = op {
+                out.push_str(self.inline_const(idx, visiting)?.as_str());
+                out.push(' ');
             } else {
                 out.push_str(self.op(&op)?.as_str());
-                out.push_str(" ");
+                out.push(' ');
             }
         }
 
         Ok(out)
-    } 
+    }
+
+    fn inline_const(&self, idx: u32, visiting: &mut Vec<u32>) -> Result<String, ByteCodeError> {
+        if self.names.contains_key(&idx) {
+            return self.op(&Op::Const { idx });
+        }
+        if visiting.contains(&idx) {
+            return Ok(format!("<const: data+{} (recursive)>", idx));
+        }
+        let Some(ops) = self.const_ops(idx) else {
+            return Ok(format!("<const: data+{} (bad)>", idx));
+        };
+
+        visiting.push(idx);
+        let body = self.ops_rec(ops.into_iter(), visiting);
+        visiting.pop();
+        Ok(format!("<const: data+{} = {}>", idx, body?.trim_end()))
+    }
 
     pub fn op(&self, op: &Op) -> Result<String, ByteCodeError> {
         Ok(match op {
             Op::Runtime(rt) => format!("<rt typeid={:?} enum={}>", rt.0.type_id(), rt.0.enum_id()),
 
-            Op::Terminate |
-            Op::ArrBegin |
-            Op::ArrEnd => "<wtf>".to_string(),
+            Op::Terminate => "<wtf>".to_string(),
+            Op::ArrBegin => "{".to_string(),
+            Op::ArrEnd => "}".to_string(),
 
-            Op::Unresolved { id } => format!("<unresolved: \"{}\">", self.asm.string(*id)?),
+            Op::Unresolved { id } => match self.asm.string(*id) {
+                Ok(name) => format!("<unresolved: \"{}\">", name),
+                Err(_) => format!("<unresolved: data+{} (bad)>", id),
+            },
             Op::Frontend(v) => format!("<frontend: {:?}>", v),
 
-            Op::Const { idx } => format!("<const: data+{}>", idx), // NOT DISASSEMBLING FOR NOW BECAUSE
-                                                                   // INFINITE RECURSION
+            Op::Const { idx } => match self.names.get(idx) {
+                Some(name) => format!("<const: {}>", name),
+                None => format!("<const: data+{}>", idx),
+            },
             Op::Push { val } => format!("{}", val),
             Op::System { id } => format!("<system: {}>", id),
             Op::TypeId => format!("typeid!"),
@@ -84,7 +213,10 @@ impl<'bc, 'asm> Disasm<'bc, 'asm> {
             Op::OpsOf => format!("opsOf!"),
             Op::ConstAt => format!("constAt!"),
 
-            Op::ArrAt { ty, idx } => format!("<arr-at({:?}): data+{}>", ty, idx),
+            Op::ArrAt { ty, idx } => match self.asm.const_arr(ty, *idx) {
+                Ok(vals) => format!("<arr-at({:?}): data+{} = {}>", ty, idx, arr_literal(ty, &vals)),
+                Err(_) => format!("<arr-at({:?}): data+{} (bad)>", ty, idx),
+            },
 
             Op::Add => format!("+"),
             Op::Sub => format!("-"),
@@ -114,4 +246,152 @@ impl<'bc, 'asm> Disasm<'bc, 'asm> {
             Op::DsoConst { dso_id } => format!("<dso: {}>", dso_id),
         })
     }
+
+    fn lines(&self, out: &mut String, lines: &[(usize, Line)]) {
+        let mut depth = 0_usize;
+        for (pos, line) in lines.iter() {
+            let text = match line {
+                Line::Op(op) => {
+                    if *op == Op::ArrEnd {
+                        depth = depth.saturating_sub(1);
+                    }
+                    let text = format!("{}{}", "  ".repeat(depth), self.op(op).unwrap());
+                    if *op == Op::ArrBegin {
+                        depth += 1;
+                    }
+                    text
+                }
+
+                Line::Bad(e, bytes) => {
+                    let hex = bytes.iter().map(|b| format!("{:#04x}", b)).collect::<Vec<_>>();
+                    format!("(bad) {}  ; {:?}", hex.join(" "), e)
+                }
+
+                Line::MissingTerminate => "(missing terminate)".to_string(),
+            };
+            out.push_str(format!("  {:#06x}:  {}\n", pos, text).as_str());
+        }
+    }
+
+    /// human readable listing of the whole module, with the byte position of every op.
+    /// does not stop at invalid bytecode, but marks it
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let bc = self.asm;
+
+        let relocatable = match encoding::is_relocatable(bc) {
+            Ok(true) => "",
+            Ok(false) => ", relaxed",
+            Err(_) => ", (bad)",
+        };
+        out.push_str(format!("op encoding: {:?}{}\n\n", bc.header.op_encoding(), relocatable).as_str());
+
+        if let (Some(strings), Some(code), Some(pool)) = (bc.strings_section(), bc.code_section(), bc.pool_section()) {
+            out.push_str("sections:\n");
+            for (name, range) in [("strings", strings), ("code", code), ("pool", pool)] {
+                out.push_str(format!("  {} \tdata+{}..data+{}\n", name, range.start, range.end).as_str());
+            }
+            out.push('\n');
+        }
+
+        out.push_str("globals:\n");
+        for global in bc.globals() {
+            let name = bc.string(global.name).unwrap_or("<bad name>");
+            let weak = if global.weak { " weak" } else { "" };
+            out.push_str(format!("  {} \tdata+{} (={}){}\n", name, global.const_id, global.const_id as usize + bc.header.data_begin(), weak).as_str());
+        }
+        out.push('\n');
+
+        out.push_str("dso references:\n");
+        match bc.dso_names() {
+            Ok(dsos) => for (id, dso) in dsos.into_iter().enumerate() {
+                out.push_str(format!("  {} \t{}\n", id, bc.string(dso).unwrap_or("<bad name>")).as_str());
+            },
+            Err(e) => out.push_str(format!("  (bad)  ; {:?}\n", e).as_str()),
+        }
+        out.push('\n');
+
+        match bc.named_sections() {
+            Ok(sections) if sections.is_empty() => (),
+            Ok(sections) => {
+                out.push_str("named sections:\n");
+                for section in sections {
+                    out.push_str(format!("  {:?} \t{:#x} \t{} bytes\n", String::from_utf8_lossy(&section.tag), section.off, section.len).as_str());
+                }
+                out.push('\n');
+            }
+            Err(e) => out.push_str(format!("named sections:\n  (bad)  ; {:?}\n\n", e).as_str()),
+        }
+
+        match bc.metadata() {
+            None => (),
+            Some(Ok(meta)) => {
+                out.push_str("metadata:\n");
+                for module in meta.modules.iter() {
+                    out.push_str(format!("  module {:?} \tversion {:?} \tsources {:?}\n", module.name, module.version, module.sources).as_str());
+                    if !module.doc.is_empty() {
+                        out.push_str(format!("    {:?}\n", module.doc).as_str());
+                    }
+                    for (name, doc) in module.docs.iter() {
+                        out.push_str(format!("    {} \t{:?}\n", name, doc).as_str());
+                    }
+                }
+                out.push('\n');
+            }
+            Some(Err(e)) => out.push_str(format!("metadata:\n  (bad)  ; {:?}\n\n", e).as_str()),
+        }
+
+        // find all reachable constants, and who references them
+        let main_begin = bc.header.main_ops_area_begin_idx();
+        let main = decode(main_begin, bc.main_ops_area(), bc.header.op_encoding());
+        let mut codes = BTreeMap::<u32, Vec<(usize, Line)>>::new();
+        let mut xrefs = BTreeMap::<u32, BTreeSet<Option<u32>>>::new();
+        let mut todo = bc.globals().map(|g| (g.const_id, None)).collect::<Vec<_>>();
+        for (_, line) in main.iter() {
+            if let Line::Op(Op::Const { idx }) = line {
+                todo.push((*idx, Some(None)));
+            }
+        }
+        while let Some((idx, from)) = todo.pop() {
+            if let Some(from) = from {
+                xrefs.entry(idx).or_default().insert(from);
+            }
+            if codes.contains_key(&idx) {
+                continue;
+            }
+            let Some((base, area)) = self.const_area(idx) else { continue };
+            let lines = decode(base, area, bc.header.op_encoding());
+            for (_, line) in lines.iter() {
+                if let Line::Op(Op::Const { idx: to }) = line {
+                    todo.push((*to, Some(Some(idx))));
+                }
+            }
+            codes.insert(idx, lines);
+        }
+
+        for (idx, lines) in codes.iter() {
+            let name = self.names.get(idx).copied().unwrap_or("anonymous");
+            out.push_str(format!("data+{} (={}) : {}\n", idx, *idx as usize + bc.header.data_begin(), name).as_str());
+            if let Some(refs) = xrefs.get(idx) {
+                let refs = refs.iter()
+                    .map(|r| match r {
+                        None => "main".to_string(),
+                        Some(r) => match self.names.get(r) {
+                            Some(name) => name.to_string(),
+                            None => format!("data+{}", r),
+                        },
+                    })
+                    .collect::<Vec<_>>();
+                out.push_str(format!("  ; referenced by: {}\n", refs.join(", ")).as_str());
+            }
+            self.lines(&mut out, lines);
+            out.push('\n');
+        }
+
+        out.push_str(format!("main (={})\n", main_begin).as_str());
+        self.lines(&mut out, &main);
+        out.push('\n');
+
+        out
+    }
 }
@@ -0,0 +1,61 @@
+//! which library the dso imports of a linked file come from, see [crate::linker::bind_dso]
+
+use nostd::prelude::*;
+use nostd::str;
+use crate::{ByteCodeError, SectionTag};
+
+/// tag of the named section that contains the [DsoLibraries]
+pub const DSO_LIBRARIES_TAG: SectionTag = *b"DSOL";
+
+/// dso libraries section:
+///   any number of records:
+///     import: utf8, null terminated
+///     library: utf8, null terminated
+///
+/// records refer to imports by name instead of dso id, so that the linker can merge the section by concatenation
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DsoLibraries {
+    /// (import name, library file name)
+    pub imports: Vec<(String, String)>,
+}
+
+impl DsoLibraries {
+    /// library of the dso import [name], from the first record of it
+    pub fn library(&self, name: &str) -> Option<&str> {
+        self.imports.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, lib)| lib.as_str())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec!();
+        for (import, library) in self.imports.iter() {
+            for s in [import, library] {
+                out.extend_from_slice(s.as_bytes());
+                out.push(0);
+            }
+        }
+        out
+    }
+}
+
+impl<'asm> TryFrom<&'asm [u8]> for DsoLibraries {
+    type Error = ByteCodeError;
+
+    fn try_from(value: &'asm [u8]) -> Result<Self, Self::Error> {
+        fn get_str(from: &[u8], at: &mut usize) -> Result<String, ByteCodeError> {
+            let sl = from.get(*at..).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let term = sl.iter().position(|&b| b == 0).ok_or(ByteCodeError::InvalidStringEncoding)?;
+            let s = str::from_utf8(&sl[0..term]).map_err(|_| ByteCodeError::InvalidStringEncoding)?;
+            *at += term + 1;
+            Ok(s.to_string())
+        }
+
+        let mut imports = vec!();
+        let mut at = 0;
+        while at < value.len() {
+            imports.push((get_str(value, &mut at)?, get_str(value, &mut at)?));
+        }
+        Ok(Self { imports })
+    }
+}
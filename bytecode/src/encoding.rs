@@ -0,0 +1,198 @@
+//! conversion between op encodings, see [OpEncoding].
+//!
+//! the linker patches ops in place, which only works if the new param fits into the old op. in the compact
+//! encoding, the compiler and linker therefore write relocatable ops with [RELOCATABLE_PARAM_SIZE].
+//! after everything is linked, [relax] shrinks them. this moves code, so the ops are laid out again until the
+//! layout does not change anymore. relaxed files can still be used as linker input, because the linker expands
+//! them again with [reencode].
+
+use nostd::prelude::*;
+use nostd::collections::BTreeMap;
+use crate::*;
+
+/// rewrites a V4 or later file with the given op encoding.
+/// in the compact encoding, relocatable ops are written with [RELOCATABLE_PARAM_SIZE]
+pub fn reencode(bytes: &[u8], enc: OpEncoding) -> Result<Vec<u8>, ByteCodeError> {
+    rewrite(bytes, enc, false)
+}
+
+/// rewrites a V4 or later file in the compact encoding, with every param as small as possible
+pub fn relax(bytes: &[u8]) -> Result<Vec<u8>, ByteCodeError> {
+    rewrite(bytes, OpEncoding::Compact, true)
+}
+
+/// if every relocatable op can be patched in place, which is not the case after [relax]
+pub fn is_relocatable(bc: &Bytecode) -> Result<bool, ByteCodeError> {
+    let enc = bc.header.op_encoding();
+    let relocatable = |size: usize, op: &Op| size == op.relocatable_size(enc);
+
+    // the code section is a sequence of Terminate separated codes, so it is decoded linearly
+    if let Some(code) = bc.code_section() {
+        let mut bytes = bc.data_table().get(code).ok_or(ByteCodeError::InvalidSections)?;
+        while !bytes.is_empty() {
+            let (size, op) = OpType::read_enc(bytes, enc)?;
+            if !relocatable(size, &op) {
+                return Ok(false);
+            }
+            bytes = &bytes[size..];
+        }
+    }
+    let mut bytes = bc.main_ops_area();
+    loop {
+        let (size, op) = OpType::read_enc(bytes, enc)?;
+        if op == Op::Terminate {
+            return Ok(true);
+        }
+        if !relocatable(size, &op) {
+            return Ok(false);
+        }
+        bytes = &bytes[size..];
+    }
+}
+
+fn rewrite(bytes: &[u8], enc: OpEncoding, relaxed: bool) -> Result<Vec<u8>, ByteCodeError> {
+    let bc = Bytecode::try_from(bytes)?;
+    let (Some(strings), Some(code)) = (bc.strings_section(), bc.code_section()) else {
+        return Err(ByteCodeError::UnsupportedVersion);
+    };
+    let old_enc = bc.header.op_encoding();
+    let data_begin = bc.header.data_begin();
+    let data = bc.data_table();
+
+    // all ops of the code section including their Terminate ops, then the main ops.
+    // positions are relative to the data table
+    let mut ops = vec!();
+    let code_bytes = data.get(code.clone()).ok_or(ByteCodeError::InvalidSections)?;
+    let mut pos = 0;
+    while pos < code_bytes.len() {
+        let (size, op) = OpType::read_enc(&code_bytes[pos..], old_enc)?;
+        ops.push((code.start + pos, op));
+        pos += size;
+    }
+    let num_code_ops = ops.len();
+    let mut main = bc.main_ops();
+    for op in &mut main {
+        let (pos, op) = op?;
+        ops.push((pos - data_begin, op));
+    }
+    let old_main_end = main.base - data_begin;
+    let old_pool = code.end;
+    let old_globals_tab = bc.header.globals_tab_off as usize;
+
+    let size_of = |op: &Op| if relaxed { op.size(enc) } else { op.relocatable_size(enc) };
+    // when relaxing, relocatable ops start as small as possible, and only grow
+    let mut sizes = ops.iter()
+        .map(|(_, op)| if relaxed && op.is_relocatable() { 2 } else { size_of(op) })
+        .collect::<Vec<_>>();
+
+    loop {
+        let mut map = BTreeMap::new();
+        let mut pos = strings.end;
+        for (idx, (old, _)) in ops.iter().enumerate().take(num_code_ops) {
+            map.insert(*old, pos);
+            pos += sizes[idx];
+        }
+        map.insert(old_pool, pos);
+        let new_pool = pos;
+        let new_globals_tab = new_pool + old_globals_tab - old_pool;
+        let mut pos = new_globals_tab + bc.header.globals_tab_num as usize * 8;
+        for (idx, (old, _)) in ops.iter().enumerate().skip(num_code_ops) {
+            map.insert(*old, pos);
+            pos += sizes[idx];
+        }
+        map.insert(old_main_end, pos);
+
+        let reloc = |off: u32| -> Result<u32, ByteCodeError> {
+            let off = off as usize;
+            Ok(if off < strings.end {
+                off
+            } else if off < old_pool {
+                *map.get(&off).ok_or(ByteCodeError::ElementNotFound)?
+            } else {
+                off - old_pool + new_pool
+            } as u32)
+        };
+
+        let mut err = None;
+        let new_ops = ops.iter()
+            .map(|(_, op)| op.clone().relocate(|off| reloc(off).unwrap_or_else(|e| {
+                err = Some(e);
+                off
+            })))
+            .collect::<Vec<_>>();
+        if let Some(e) = err {
+            return Err(e);
+        }
+
+        let mut grown = false;
+        for (size, op) in sizes.iter_mut().zip(new_ops.iter()) {
+            if size_of(op) > *size {
+                *size = size_of(op);
+                grown = true;
+            }
+        }
+        if grown {
+            continue;
+        }
+
+        let mut out = data[..strings.end].to_vec();
+        for (op, size) in new_ops.iter().zip(sizes.iter()).take(num_code_ops) {
+            out.extend_from_slice(&op.encode_sized(enc, *size)?);
+        }
+        out.extend_from_slice(&data[old_pool..]);
+        for global in bc.globals() {
+            Export { name: global.name, const_id: reloc(global.const_id)?, weak: global.weak }.write(&mut out).unwrap();
+        }
+        for (op, size) in new_ops.iter().zip(sizes.iter()).skip(num_code_ops) {
+            out.extend_from_slice(&op.encode_sized(enc, *size)?);
+        }
+        Op::Terminate.write(&mut out).unwrap();
+
+        // positions inside of an op are moved to the beginning of the op
+        let map_pos = |old: u32| map.range(..=old as usize).next_back()
+            .map_or(old, |(_, new)| *new as u32);
+        let mut ex = bc.extensions()?;
+        if let Some(debug) = &mut ex.debug_info {
+            for ent in debug.entries.iter_mut() {
+                let end = map_pos(ent.pos + ent.len);
+                ent.pos = map_pos(ent.pos);
+                ent.len = end - ent.pos;
+            }
+            debug.entries.retain(|x| x.len > 0);
+        }
+        // every relocation is at the beginning of an op
+        for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == reloc::RELOCATIONS_TAG) {
+            let mut relocs = reloc::Relocations::try_from(section.as_slice())?;
+            let mut missing = false;
+            relocs.relocate(|pos| map.get(&(pos as usize)).map(|x| *x as u32).or_else(|| {
+                missing = true;
+                None
+            }));
+            if missing {
+                return Err(ByteCodeError::ElementNotFound);
+            }
+            *section = relocs.serialize();
+        }
+        for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == init::INITIALIZERS_TAG) {
+            let mut init = init::Initializers::try_from(section.as_slice())?;
+            for x in init.modules.iter_mut() {
+                let end = map_pos(x.pos + x.len);
+                x.pos = map_pos(x.pos);
+                x.len = end - x.pos;
+            }
+            *section = init.serialize();
+        }
+
+        let mut header = Header {
+            globals_tab_off: new_globals_tab as u32,
+            code_len: (new_pool - strings.end) as u32,
+            ..bc.header.clone()
+        };
+        header.set_op_encoding(enc);
+        header._extended_header_off = ex.write(data_begin + out.len(), &mut out);
+
+        let mut file = header.serialize();
+        file.append(&mut out);
+        return Ok(file);
+    }
+}
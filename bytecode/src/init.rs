@@ -0,0 +1,69 @@
+//! which module the main ops of a linked file come from, see [crate::linker::set_main]
+
+use nostd::prelude::*;
+use nostd::str;
+use crate::{ByteCodeError, SectionTag};
+
+/// tag of the named section that contains the [Initializers]
+pub const INITIALIZERS_TAG: SectionTag = *b"INIT";
+
+/// the main ops of one module
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Initializer {
+    /// from the metadata of the module. empty if unknown
+    pub module: String,
+    /// of the first op, relative to the data table
+    pub pos: u32,
+    /// in bytes
+    pub len: u32,
+}
+
+/// initializers section:
+///   any number of records, in the order they run:
+///     pos: u32_le
+///     len: u32_le
+///     module: utf8, null terminated
+///
+/// the linker writes one record for every module with main ops, in link order
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Initializers {
+    pub modules: Vec<Initializer>,
+}
+
+impl Initializers {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec!();
+        for init in self.modules.iter() {
+            out.extend_from_slice(&init.pos.to_le_bytes());
+            out.extend_from_slice(&init.len.to_le_bytes());
+            out.extend_from_slice(init.module.as_bytes());
+            out.push(0);
+        }
+        out
+    }
+}
+
+impl<'asm> TryFrom<&'asm [u8]> for Initializers {
+    type Error = ByteCodeError;
+
+    fn try_from(value: &'asm [u8]) -> Result<Self, Self::Error> {
+        fn get_u32(from: &[u8], at: &mut usize) -> Result<u32, ByteCodeError> {
+            let by = from.get(*at..*at + 4).ok_or(ByteCodeError::NotEnoughBytes)?;
+            *at += 4;
+            Ok(u32::from_le_bytes(by.try_into().unwrap()))
+        }
+
+        let mut modules = vec!();
+        let mut at = 0;
+        while at < value.len() {
+            let pos = get_u32(value, &mut at)?;
+            let len = get_u32(value, &mut at)?;
+            let sl = &value[at..];
+            let term = sl.iter().position(|&b| b == 0).ok_or(ByteCodeError::InvalidStringEncoding)?;
+            let module = str::from_utf8(&sl[0..term]).map_err(|_| ByteCodeError::InvalidStringEncoding)?;
+            at += term + 1;
+            modules.push(Initializer { module: module.to_string(), pos, len });
+        }
+        Ok(Self { modules })
+    }
+}
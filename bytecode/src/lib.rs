@@ -3,8 +3,18 @@ use nostd::prelude::*;
 
 pub mod linker;
 pub mod disasm;
-
-use nostd::{io, fmt, any, rc, collections::HashSet, ops::Range, str};
+pub mod debug_info;
+pub mod verify;
+pub mod asm;
+pub mod upgrade;
+pub mod encoding;
+pub mod metadata;
+pub mod archive;
+pub mod dso;
+pub mod reloc;
+pub mod init;
+
+use nostd::{io, fmt, any, rc, collections::{BTreeSet, HashSet}, ops::Range, str};
 
 use int_enum::IntEnum;
 
@@ -127,6 +137,75 @@ pub enum Op {
 }
 
 impl Op {
+    /// if the op references the data table or the dso table, so that the linker has to relocate it
+    pub fn is_relocatable(&self) -> bool {
+        matches!(self, Op::Unresolved { .. } | Op::Const { .. } | Op::ArrAt { .. } | Op::DsoConst { .. })
+    }
+
+    /// param, as stored in the fixed encoding
+    fn param(&self) -> Option<u32> {
+        match self {
+            Op::Unresolved { id } => Some(*id),
+            Op::Const { idx } => Some(*idx),
+            Op::ArrAt { idx, .. } => Some(*idx),
+            Op::Push { val } => Some(*val as u32),
+            Op::Reach { down } => Some(*down),
+            Op::System { id } => Some(*id),
+            Op::DsoConst { dso_id } => Some(*dso_id),
+            _ => None,
+        }
+    }
+
+    /// param, as stored in the compact encoding
+    fn compact_param(&self) -> Option<u32> {
+        match self {
+            Op::Push { val } => Some(zigzag(*val)),
+            op => op.param(),
+        }
+    }
+
+    /// encoded size in bytes, with the param as small as possible
+    pub fn size(&self, enc: OpEncoding) -> usize {
+        match (enc, self.compact_param()) {
+            (_, None) => 1,
+            (OpEncoding::Fixed, Some(_)) => 5,
+            (OpEncoding::Compact, Some(v)) => 1 + var_size(v),
+        }
+    }
+
+    /// encoded size in bytes, as written by [Op::write_relocatable]
+    pub fn relocatable_size(&self, enc: OpEncoding) -> usize {
+        if enc == OpEncoding::Compact && self.is_relocatable() {
+            1 + RELOCATABLE_PARAM_SIZE
+        } else {
+            self.size(enc)
+        }
+    }
+
+    /// encodes the op padded to exactly [size] bytes, which is only possible in the compact encoding.
+    /// fails with [ByteCodeError::ParamTooLarge] if it does not fit
+    pub fn encode_sized(&self, enc: OpEncoding, size: usize) -> Result<Vec<u8>, ByteCodeError> {
+        let ty: OpType = self.into();
+        let mut out = vec!(ty as u8);
+        match (enc, self.compact_param()) {
+            (_, None) if size == 1 => (),
+            (OpEncoding::Fixed, Some(_)) if size == 5 => out.extend_from_slice(&self.param().unwrap().to_le_bytes()),
+            (OpEncoding::Compact, Some(v)) if size > 1 => write_var(&mut out, v, size - 1)?,
+            _ => Err(ByteCodeError::ParamTooLarge)?,
+        }
+        Ok(out)
+    }
+
+    /// applies [f] to the data table offset of the op, if it has one
+    pub fn relocate<F: FnOnce(u32) -> u32>(self, f: F) -> Op {
+        match self {
+            Op::Unresolved { id } => Op::Unresolved { id: f(id) },
+            Op::Const { idx } => Op::Const { idx: f(idx) },
+            Op::ArrAt { ty, idx } => Op::ArrAt { ty, idx: f(idx) },
+            _ => self,
+        }
+    }
+
     pub fn offset(self, by: usize) -> Op {
         match self {
             Op::Unresolved { id } => Op::Unresolved { id: id + by as u32 },
@@ -183,20 +262,81 @@ impl Into<OpType> for &Op {
 }
 
 impl Op {
+    /// writes the op in the fixed encoding
     pub fn write<W: io::Write>(&self, to: &mut W) -> io::Result<()> {
-        let ty: OpType = self.into();
-        to.write_all(&[ty as u8])?;
-        match self {
-            Op::Unresolved { id } => to.write_all(&id.to_le_bytes())?,
-            Op::Const { idx } => to.write_all(&idx.to_le_bytes())?,
-            Op::ArrAt { idx, .. } => to.write_all(&idx.to_le_bytes())?,
-            Op::Push { val } => to.write_all(&val.to_le_bytes())?,
-            Op::Reach { down } => to.write_all(&down.to_le_bytes())?,
-            Op::System { id } => to.write_all(&id.to_le_bytes())?,
-            Op::DsoConst { dso_id } => to.write_all(&dso_id.to_le_bytes())?,
-            _ => (),
+        self.write_enc(to, OpEncoding::Fixed)
+    }
+
+    /// writes the op with the param as small as possible
+    pub fn write_enc<W: io::Write>(&self, to: &mut W, enc: OpEncoding) -> io::Result<()> {
+        to.write_all(self.encode_sized(enc, self.size(enc)).unwrap().as_slice())
+    }
+
+    /// writes the op, so that the linker can later change its param in place. see [RELOCATABLE_PARAM_SIZE]
+    pub fn write_relocatable<W: io::Write>(&self, to: &mut W, enc: OpEncoding) -> io::Result<()> {
+        to.write_all(self.encode_sized(enc, self.relocatable_size(enc)).unwrap().as_slice())
+    }
+}
+
+/// how the params of ops are encoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum OpEncoding {
+    /// u32_le, or i32_le for [Op::Push]
+    #[default]
+    Fixed,
+    /// since V5, if [FLAG_COMPACT_OPS] is set: unsigned LEB128, zigzag encoded for [Op::Push].
+    /// 1 to 5 bytes, padding with 0x80 bytes is allowed
+    Compact,
+}
+
+/// in the compact encoding, the compiler and linker write ops that reference the data table or the dso table
+/// with params of this size, so that they can be relocated in place.
+/// [encoding::relax] makes them as small as possible, after everything is linked
+pub const RELOCATABLE_PARAM_SIZE: usize = 5;
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn unzigzag(v: u32) -> i32 {
+    (v >> 1) as i32 ^ -((v & 1) as i32)
+}
+
+/// size of the LEB128 encoding of [v]
+fn var_size(v: u32) -> usize {
+    (32 - v.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+/// LEB128 encoding of [v], padded to [size] bytes
+fn write_var(out: &mut Vec<u8>, v: u32, size: usize) -> Result<(), ByteCodeError> {
+    if size > RELOCATABLE_PARAM_SIZE || var_size(v) > size {
+        Err(ByteCodeError::ParamTooLarge)?;
+    }
+    let mut v = v;
+    for i in 0..size {
+        let cont = if i + 1 < size { 0x80 } else { 0 };
+        out.push((v & 0x7f) as u8 | cont);
+        v >>= 7;
+    }
+    Ok(())
+}
+
+/// returns the value and the amount of bytes read
+fn read_var(bytes: &[u8]) -> Result<(u32, usize), ByteCodeError> {
+    let mut v = 0_u32;
+    for (i, by) in bytes.iter().enumerate().take(RELOCATABLE_PARAM_SIZE) {
+        if i == RELOCATABLE_PARAM_SIZE - 1 && *by > 0x0f {
+            Err(ByteCodeError::InvalidVarInt)?;
+        }
+        v |= ((by & 0x7f) as u32) << (i * 7);
+        if by & 0x80 == 0 {
+            return Ok((v, i + 1));
         }
-        Ok(())
+    }
+    if bytes.len() < RELOCATABLE_PARAM_SIZE {
+        Err(ByteCodeError::NotEnoughBytes)
+    } else {
+        Err(ByteCodeError::InvalidVarInt)
     }
 }
 
@@ -263,26 +403,49 @@ impl OpType {
         }
     }
 
-    /// input slice can be longer than required
-    /// returns weather or not had param
+    /// encoded size of the op in bytes, in the fixed encoding
+    pub fn size(&self) -> usize {
+        if self.has_param() { 5 } else { 1 }
+    }
+
+    /// input slice can be longer than required.
+    /// in the fixed encoding. returns weather or not had param
     pub fn read(bytes: &[u8]) -> Result<(bool, Op), ByteCodeError> {
+        Self::read_enc(bytes, OpEncoding::Fixed)
+            .map(|(size, op)| (size > 1, op))
+    }
+
+    /// input slice can be longer than required.
+    /// returns the encoded size of the op
+    pub fn read_enc(bytes: &[u8], enc: OpEncoding) -> Result<(usize, Op), ByteCodeError> {
         let opty = OpType::try_from(*bytes.get(0).ok_or(ByteCodeError::NotEnoughBytes)?)
             .map_err(|_| ByteCodeError::UnknownOpcode(bytes[0]))?;
 
-        let arg = bytes.get(1..5)
-            .map(|x| {
-                let mut a = [0_u8;4];
-                a.clone_from_slice(x);
-                a
-            });
-
-        Ok((opty.has_param(), match opty {
+        let (size, arg) = match (opty.has_param(), enc) {
+            (false, _) => (1, None),
+            (true, OpEncoding::Fixed) => {
+                let by = bytes.get(1..5).ok_or(ByteCodeError::NotEnoughBytes)?;
+                (5, Some(u32::from_le_bytes([by[0], by[1], by[2], by[3]])))
+            }
+            (true, OpEncoding::Compact) => {
+                let (v, len) = read_var(&bytes[1..])?;
+                (1 + len, Some(v))
+            }
+        };
+        // the compact encoding stores pushed numbers zigzag encoded
+        let num = |v: u32| match enc {
+            OpEncoding::Fixed => v as Num,
+            OpEncoding::Compact => unzigzag(v),
+        };
+        let arg = arg.map(|x| x.to_le_bytes());
+
+        Ok((size, match opty {
             OpType::Terminate => Op::Terminate,
             OpType::Unresolved => Op::Unresolved { id: u32::from_le_bytes(arg.ok_or(ByteCodeError::NotEnoughBytes)?) },
             OpType::Const => Op::Const { idx: u32::from_le_bytes(arg.ok_or(ByteCodeError::NotEnoughBytes)?) },
             OpType::U8ArrAt => Op::ArrAt { ty: PushConstArrType::U8, idx: u32::from_le_bytes(arg.ok_or(ByteCodeError::NotEnoughBytes)?) },
             OpType::I16ArrAt => Op::ArrAt { ty: PushConstArrType::I16, idx: u32::from_le_bytes(arg.ok_or(ByteCodeError::NotEnoughBytes)?) },
-            OpType::Push => Op::Push { val: Num::from_le_bytes(arg.ok_or(ByteCodeError::NotEnoughBytes)?) },
+            OpType::Push => Op::Push { val: num(u32::from_le_bytes(arg.ok_or(ByteCodeError::NotEnoughBytes)?)) },
             OpType::Add => Op::Add,
             OpType::Sub => Op::Sub,
             OpType::Mul => Op::Mul,
@@ -317,6 +480,34 @@ impl OpType {
     }
 }
 
+/// replaces every op in [code] with [f] of it, in place.
+/// the replacement is padded to the size of the old op, and fails with [ByteCodeError::ParamTooLarge] if it does not fit.
+/// [code] has to consist only of code sequences, each terminated by a Terminate op
+pub fn patch_ops<F: FnMut(Op) -> Op>(code: &mut [u8], enc: OpEncoding, f: F) -> Result<(), ByteCodeError> {
+    let mut f = f;
+    let mut pos = 0;
+    while pos < code.len() {
+        let (size, op) = OpType::read_enc(&code[pos..], enc)?;
+        if op != Op::Terminate {
+            let by = f(op).encode_sized(enc, size)?;
+            code[pos..pos + size].copy_from_slice(by.as_slice());
+        }
+        pos += size;
+    }
+    Ok(())
+}
+
+/// like [patch_ops], but only replaces the ops at the given [positions] in [code]
+pub fn patch_ops_at<F: FnMut(Op) -> Op, I: IntoIterator<Item = usize>>(code: &mut [u8], positions: I, enc: OpEncoding, f: F) -> Result<(), ByteCodeError> {
+    let mut f = f;
+    for pos in positions {
+        let (size, op) = OpType::read_enc(code.get(pos..).ok_or(ByteCodeError::NotEnoughBytes)?, enc)?;
+        let by = f(op).encode_sized(enc, size)?;
+        code[pos..pos + size].copy_from_slice(by.as_slice());
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Export {
     /// offset into str tab
@@ -324,12 +515,17 @@ pub struct Export {
 
     /// offset into const array
     pub const_id: u32,
+
+    /// since V5. only used by the linker if there is no other global with the same name.
+    /// stored as [WEAK_GLOBAL_BIT] of the name, which needs [FLAG_WEAK_GLOBALS]
+    pub weak: bool,
 }
 
 impl Export {
     pub fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let name = if self.weak { self.name | WEAK_GLOBAL_BIT } else { self.name };
         let mut bytes = [0_u8;8];
-        bytes[0..4].copy_from_slice(self.name.to_le_bytes().as_slice());
+        bytes[0..4].copy_from_slice(name.to_le_bytes().as_slice());
         bytes[4..8].copy_from_slice(self.const_id.to_le_bytes().as_slice());
         out.write_all(bytes.as_slice())?;
         Ok(())
@@ -337,8 +533,16 @@ impl Export {
 }
 
 pub const MIN_SUPPORTED_VERSION: u8 = 3;
-pub const MIN_READER_VERSION: u8 = 3;
-pub const VERSION: u8 = 3;
+pub const MIN_READER_VERSION: u8 = 4;
+pub const VERSION: u8 = 5;
+
+/// since V5. header flag: ops use [OpEncoding::Compact]. files with this flag need a reader of at least V5
+pub const FLAG_COMPACT_OPS: u16 = 1;
+/// since V5. header flag: the globals table can contain weak entries, see [Export::weak].
+/// files with this flag need a reader of at least V5
+pub const FLAG_WEAK_GLOBALS: u16 = 2;
+/// bit of the name offset of a globals table entry that marks it as weak
+pub const WEAK_GLOBAL_BIT: u32 = 1 << 31;
 
 
 #[derive(Clone, Debug)]
@@ -346,30 +550,98 @@ pub struct Header {
     pub min_reader_version: u8,
     pub writer_version: u8,
 
-    pub globals_tab_num: u16,
+    /// since V4. see [FLAG_COMPACT_OPS]. unknown flags have to be 0
+    pub flags: u16,
+
+    /// only 16 bits before V4
+    pub globals_tab_num: u32,
 
     /// relative to code/string/const table!!
     pub globals_tab_off: u32,
 
     pub _extended_header_off: u32,
+
+    /// since V4. size of the strings section, which is at the beginning of the data table
+    pub strings_len: u32,
+
+    /// since V4. size of the code section, which follows the strings section.
+    /// the rest of the data table is the constant pool
+    pub code_len: u32,
 }
 
 impl Header {
+    /// before V4, the data table is not split into sections
+    pub fn has_sections(&self) -> bool {
+        self.writer_version >= 4
+    }
+
+    /// size of the serialized header, which is also where the data table begins
+    pub fn size(&self) -> usize {
+        if self.has_sections() { 32 } else { 16 }
+    }
+
+    /// absolute position of the data table. op parameters are relative to this
+    pub fn data_begin(&self) -> usize {
+        self.size()
+    }
+
+    pub fn op_encoding(&self) -> OpEncoding {
+        if self.flags & FLAG_COMPACT_OPS != 0 {
+            OpEncoding::Compact
+        } else {
+            OpEncoding::Fixed
+        }
+    }
+
+    /// sets the flag and the versions needed for [enc]
+    pub fn set_op_encoding(&mut self, enc: OpEncoding) {
+        self.writer_version = VERSION;
+        match enc {
+            OpEncoding::Fixed => {
+                self.flags &= !FLAG_COMPACT_OPS;
+                self.min_reader_version = if self.flags & FLAG_WEAK_GLOBALS != 0 { 5 } else { MIN_READER_VERSION };
+            }
+            OpEncoding::Compact => {
+                self.flags |= FLAG_COMPACT_OPS;
+                self.min_reader_version = 5;
+            }
+        }
+    }
+
+    /// sets [FLAG_WEAK_GLOBALS] and the versions needed for it
+    pub fn set_weak_globals(&mut self) {
+        self.writer_version = VERSION;
+        self.flags |= FLAG_WEAK_GLOBALS;
+        self.min_reader_version = self.min_reader_version.max(5);
+    }
+
+    /// size of the length prefix of constant arrays in bytes
+    pub fn arr_len_size(&self) -> usize {
+        if self.has_sections() { 4 } else { 2 }
+    }
+
     pub fn main_ops_area_begin_idx(&self) -> usize {
-        16 + self.globals_tab_off as usize + self.globals_tab_num as usize * 8
+        self.data_begin() + self.globals_tab_off as usize + self.globals_tab_num as usize * 8
     }
 
-    pub fn serialize(&self) -> [u8;16] {
-        let mut header = [
-            'H' as u8, '6' as u8, 'H' as u8, '6' as u8,
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut header = vec!['H' as u8, '6' as u8, 'H' as u8, '6' as u8,
             self.min_reader_version,
-            self.writer_version,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0
-        ];
-
-        header[6..8].copy_from_slice(&self.globals_tab_num.to_le_bytes());
-        header[8..12].copy_from_slice(&self.globals_tab_off.to_le_bytes());
-        header[12..16].copy_from_slice(&self._extended_header_off.to_le_bytes());
+            self.writer_version];
+
+        if self.has_sections() {
+            header.extend_from_slice(&self.flags.to_le_bytes());
+            header.extend_from_slice(&self.globals_tab_num.to_le_bytes());
+            header.extend_from_slice(&self.globals_tab_off.to_le_bytes());
+            header.extend_from_slice(&self._extended_header_off.to_le_bytes());
+            header.extend_from_slice(&self.strings_len.to_le_bytes());
+            header.extend_from_slice(&self.code_len.to_le_bytes());
+            header.extend_from_slice(&[0_u8;4]);
+        } else {
+            header.extend_from_slice(&(self.globals_tab_num as u16).to_le_bytes());
+            header.extend_from_slice(&self.globals_tab_off.to_le_bytes());
+            header.extend_from_slice(&self._extended_header_off.to_le_bytes());
+        }
 
         header
     }
@@ -393,9 +665,12 @@ impl Default for Header {
         Header {
             min_reader_version: MIN_READER_VERSION,
             writer_version: VERSION,
+            flags: 0,
             globals_tab_off: 0,
             globals_tab_num: 0,
             _extended_header_off: 0,
+            strings_len: 0,
+            code_len: 0,
         }
     }
 }
@@ -423,17 +698,37 @@ impl<'asm> TryFrom<&'asm [u8]> for Header {
             Err(ByteCodeError::UnsupportedVersion)?;
         }
 
-        let globals_tab_num = u16::from_le_bytes(get_bytes(value, 6..8)?);
-        let globals_tab_off = u32::from_le_bytes(get_bytes(value, 8..12)?);
-        let _extended_header_off = u32::from_le_bytes(get_bytes(value, 12..16)?);
+        if writer_version < 4 {
+            return Ok(Self {
+                min_reader_version,
+                writer_version,
+                flags: 0,
+                globals_tab_num: u16::from_le_bytes(get_bytes(value, 6..8)?) as u32,
+                globals_tab_off: u32::from_le_bytes(get_bytes(value, 8..12)?),
+                _extended_header_off: u32::from_le_bytes(get_bytes(value, 12..16)?),
+                strings_len: 0,
+                code_len: 0,
+            });
+        }
 
-        Ok(Self {
+        let header = Self {
             min_reader_version,
             writer_version,
-            globals_tab_num,
-            globals_tab_off,
-            _extended_header_off
-        })
+            flags: u16::from_le_bytes(get_bytes(value, 6..8)?),
+            globals_tab_num: u32::from_le_bytes(get_bytes(value, 8..12)?),
+            globals_tab_off: u32::from_le_bytes(get_bytes(value, 12..16)?),
+            _extended_header_off: u32::from_le_bytes(get_bytes(value, 16..20)?),
+            strings_len: u32::from_le_bytes(get_bytes(value, 20..24)?),
+            code_len: u32::from_le_bytes(get_bytes(value, 24..28)?),
+        };
+        let known_flags = if writer_version >= 5 { FLAG_COMPACT_OPS | FLAG_WEAK_GLOBALS } else { 0 };
+        if header.flags & !known_flags != 0 {
+            Err(ByteCodeError::UnsupportedVersion)?;
+        }
+        if header.strings_len as u64 + header.code_len as u64 > header.globals_tab_off as u64 {
+            Err(ByteCodeError::InvalidSections)?;
+        }
+        Ok(header)
     }
 }
 
@@ -441,13 +736,17 @@ impl<'asm> TryFrom<&'asm [u8]> for Header {
 pub struct ExtendedHeader {
     pub length: usize,
     pub num_dso: u32,
+
+    /// amount of entries in the section registry, which follows the dso table
+    pub num_sections: u32,
 }
 
 impl ExtendedHeader {
     pub fn serialize(&self) -> Vec<u8> {
         let mut out = vec!();
-        out.extend_from_slice(&(6_u16).to_le_bytes());
+        out.extend_from_slice(&(10_u16).to_le_bytes());
         out.extend_from_slice(&self.num_dso.to_le_bytes());
+        out.extend_from_slice(&self.num_sections.to_le_bytes());
         out
     }
 
@@ -475,10 +774,16 @@ impl<'asm> TryFrom<&'asm [u8]> for ExtendedHeader {
         let value = &value[0..length];
 
         let num_dso = u32::from_le_bytes(get_bytes(value, 2..6)?);
+        let num_sections = if length >= 10 {
+            u32::from_le_bytes(get_bytes(value, 6..10)?)
+        } else {
+            0
+        };
 
         Ok(Self {
             length,
-            num_dso
+            num_dso,
+            num_sections,
         })
     }
 }
@@ -486,33 +791,114 @@ impl<'asm> TryFrom<&'asm [u8]> for ExtendedHeader {
 impl Default for ExtendedHeader {
     fn default() -> Self {
         Self {
-            length: 6,
+            length: 10,
             num_dso: 0,
+            num_sections: 0,
+        }
+    }
+}
+
+/// tag of a named section. usually 4 ASCII characters
+pub type SectionTag = [u8; 4];
+
+/// entry of the section registry
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamedSection {
+    pub tag: SectionTag,
+    /// relative to beginning of file
+    pub off: u32,
+    pub len: u32,
+}
+
+/// everything that the extended header refers to. written after the main ops
+#[derive(Clone, Debug, Default)]
+pub struct Extensions {
+    /// offsets of the dso names in the data table
+    pub dso: Vec<u32>,
+    /// written as [debug_info::DEBUG_INFO_TAG] section
+    pub debug_info: Option<debug_info::DebugInfo>,
+    /// contents of the other named sections. tags do not have to be unique
+    pub sections: Vec<(SectionTag, Vec<u8>)>,
+}
+
+impl Extensions {
+    pub fn add_section(&mut self, tag: SectionTag, data: Vec<u8>) {
+        self.sections.push((tag, data));
+    }
+
+    /// if no extended header is needed
+    pub fn is_empty(&self) -> bool {
+        self.dso.is_empty() && self.debug_info.is_none() && self.sections.is_empty()
+    }
+
+    /// appends the extended header and everything it refers to to [out].
+    /// [file_off] is the position in the file that [out] ends at.
+    /// returns the extended header offset for the main header, which is 0 if nothing was written
+    pub fn write(&self, file_off: usize, out: &mut Vec<u8>) -> u32 {
+        if self.is_empty() {
+            return 0;
+        }
+
+        let debug_info = self.debug_info.as_ref().map(|x| (debug_info::DEBUG_INFO_TAG, x.serialize()));
+        let sections = debug_info.iter().chain(self.sections.iter()).collect::<Vec<_>>();
+
+        let begin = file_off as u32;
+        let ex = ExtendedHeader {
+            num_dso: self.dso.len() as u32,
+            num_sections: sections.len() as u32,
+            ..Default::default()
+        };
+        ex.write(out).unwrap();
+        for dso in self.dso.iter() {
+            out.extend_from_slice(&dso.to_le_bytes());
+        }
+
+        // section contents follow the registry
+        let mut off = begin + ex.length as u32 + ex.num_dso * 4 + ex.num_sections * 12;
+        for (tag, data) in sections.iter() {
+            out.extend_from_slice(tag);
+            out.extend_from_slice(&off.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            off += data.len() as u32;
+        }
+        for (_, data) in sections.iter() {
+            out.extend_from_slice(data);
         }
+
+        begin
     }
 }
 
-/// header (16 bytes)
-///   + 0  magic:   4 * u8 = "H6H6"
-///   + 4  min_reader_version: u8     = 1
-///   + 5  writer_version: u8 = 2
-///   + 6  globals table num entries: u16_le
-///   + 8  offset to globals table in code tab: u32_le
-///   +12  extended header offset relative to file begin, or null: u32_le (this HAS TO be higher than
-///          the globals table)
+/// header (32 bytes)
+///   +  0  magic:   4 * u8 = "H6H6"
+///   +  4  min_reader_version: u8     = 4 (5 with compact ops or weak globals)
+///   +  5  writer_version: u8 = 5
+///   +  6  flags: u16_le (bit 0: [FLAG_COMPACT_OPS], bit 1: [FLAG_WEAK_GLOBALS], since V5)
+///   +  8  globals table num entries: u32_le
+///   + 12  offset to globals table in data table: u32_le (= size of data table)
+///   + 16  extended header offset relative to file begin, or null: u32_le (this HAS TO be higher than the globals table)
+///   + 20  strings section size: u32_le
+///   + 24  code section size: u32_le
+///   + 28  reserved: u32_le = 0
 ///
-/// code = string = const table:
-///   multiple of either:
-///     string:
+/// data table:
+///   strings section:
+///     multiple strings:
 ///       utf8, null terminated
-///     code / constant:
+///   code section:
+///     multiple code / constants:
 ///       multiple ops
 ///       "Terminate" op
+///   constant pool (rest of the data table):
+///     multiple constant arrays:
+///       length: u32_le
+///       elements: u8 or i16_le, depending on the op referencing it
 ///
 /// globals table:
 ///   multiple entries:
-///     name:  u32_le (byte offset into string table)
-///     value: u32_le (byte offset into const table)
+///     name:  u32_le (byte offset into data table, points into strings section.
+///              with [FLAG_WEAK_GLOBALS], the highest bit marks weak globals)
+///     value: u32_le (byte offset into data table, points into code section)
 ///
 /// executing code (kinda like main() function)
 ///   multiple ops
@@ -521,17 +907,36 @@ impl Default for ExtendedHeader {
 /// extended header (only present if offset to this is not null in main header):
 ///   length including this field: u16_le >= 6
 ///   dso table num entries: u32_le
+///   (if length >= 10) section registry num entries: u32_le
 ///   ...
 ///
 /// dso table:
 ///   multiple entries:
-///     name: u32_le (byte offset into string table)
+///     name: u32_le (byte offset into data table, points into strings section)
+///
+/// section registry:
+///   multiple entries:
+///     tag: 4 * u8
+///     offset relative to file begin: u32_le
+///     length: u32_le
+///   readers skip sections with tags they do not know. the linker concatenates sections with the same tag,
+///   so the contents of a section have to be a sequence of records.
+///   known tags: [debug_info::DEBUG_INFO_TAG], [metadata::METADATA_TAG], [dso::DSO_LIBRARIES_TAG],
+///   [reloc::RELOCATIONS_TAG], [init::INITIALIZERS_TAG]
 ///
 ///
 /// op:
 ///   id: u8
 ///   for specific ops:
-///     param: u32_le
+///     param: u32_le (offsets are relative to the data table)
+///            or LEB128 if the header has [FLAG_COMPACT_OPS], see [OpEncoding::Compact]
+///
+/// V3 differs in:
+///   - the header is 16 bytes: magic, versions, globals table num entries as u16_le at +6,
+///     globals table offset at +8, extended header offset at +12
+///   - the data table is not split into sections. strings, code and constant arrays are mixed
+///   - the length prefix of constant arrays is a u16_le
+///   - the extended header has no section registry, so there are no named sections
 ///
 pub struct Bytecode<'asm> {
     pub bytes: &'asm [u8],
@@ -541,13 +946,15 @@ pub struct Bytecode<'asm> {
 /// until terminate
 pub struct OpsIter<'asm> {
     pub base: usize,
+    enc: OpEncoding,
     bytes: Result<Option<&'asm [u8]>, ByteCodeError>
 }
 
 impl<'asm> OpsIter<'asm> {
-    pub fn new(base: usize, bytes: &'asm [u8]) -> Self {
+    pub fn new(base: usize, bytes: &'asm [u8], enc: OpEncoding) -> Self {
         Self {
             base,
+            enc,
             bytes: Ok(Some(bytes)),
         }
     }
@@ -562,20 +969,15 @@ impl<'asm> Iterator for OpsIter<'asm> {
                 None => None,
 
                 Some(bytes) => {
-                    match OpType::read(bytes) {
-                        Ok((had_param, op)) => {
+                    match OpType::read_enc(bytes, self.enc) {
+                        Ok((size, op)) => {
                             if op == Op::Terminate {
                                 self.bytes = Ok(None);
                                 None
                             } else {
                                 let p = self.base;
-                                if had_param {
-                                    self.bytes = Ok(Some(&bytes[5..]));
-                                    self.base += 5;
-                                } else {
-                                    self.bytes = Ok(Some(&bytes[1..]));
-                                    self.base += 1;
-                                }
+                                self.bytes = Ok(Some(&bytes[size..]));
+                                self.base += size;
                                 Some(Ok((p, op)))
                             }
                         }
@@ -601,18 +1003,21 @@ impl<'asm> Bytecode<'asm> {
         Self { bytes, header }
     }
 
+    /// stops early if the globals table is truncated. see [verify] for detecting that
     pub fn globals(&self) -> impl Iterator<Item = Export> {
         (0..self.header.globals_tab_num)
-            .map(move |idx| {
+            .map_while(move |idx| {
                 let offset = idx as usize * 8;
+                let entry = self.globals_table().get(offset..offset+8)?;
 
                 let mut bytes = [0_u8;4];
-                bytes.clone_from_slice(&self.globals_table()[offset..offset+4]);
+                bytes.clone_from_slice(&entry[0..4]);
                 let name = u32::from_le_bytes(bytes);
-                bytes.clone_from_slice(&self.globals_table()[offset+4..offset+8]);
+                bytes.clone_from_slice(&entry[4..8]);
                 let const_id = u32::from_le_bytes(bytes);
 
-                Export { name, const_id }
+                let weak = self.header.flags & FLAG_WEAK_GLOBALS != 0 && name & WEAK_GLOBAL_BIT != 0;
+                Some(Export { name: if weak { name & !WEAK_GLOBAL_BIT } else { name }, const_id, weak })
             })
     }
 
@@ -631,10 +1036,112 @@ impl<'asm> Bytecode<'asm> {
     pub fn extended_header(&self) -> Option<Result<ExtendedHeader, ByteCodeError>> {
         self.header.extended_header_off()
             .map(|off| {
-                ExtendedHeader::try_from(&self.bytes[off..])
+                ExtendedHeader::try_from(self.bytes.get(off..).ok_or(ByteCodeError::NotEnoughBytes)?)
             })
     }
 
+    /// debug info of the first [debug_info::DEBUG_INFO_TAG] section, None if there is none
+    pub fn debug_info(&self) -> Option<Result<debug_info::DebugInfo, ByteCodeError>> {
+        self.section(&debug_info::DEBUG_INFO_TAG)
+            .map(|x| x.and_then(debug_info::DebugInfo::try_from))
+    }
+
+    /// module metadata of all [metadata::METADATA_TAG] sections, None if there are none
+    pub fn metadata(&self) -> Option<Result<metadata::Metadata, ByteCodeError>> {
+        let sections = match self.named_sections() {
+            Ok(sections) => sections,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut found = false;
+        let mut out = metadata::Metadata::default();
+        for section in sections.iter().filter(|x| x.tag == metadata::METADATA_TAG) {
+            found = true;
+            let parsed = self.section_data(section).and_then(metadata::Metadata::try_from);
+            match parsed {
+                Ok(mut m) => out.modules.append(&mut m.modules),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        found.then_some(Ok(out))
+    }
+
+    /// dso import libraries of all [dso::DSO_LIBRARIES_TAG] sections, None if there are none
+    pub fn dso_libraries(&self) -> Option<Result<dso::DsoLibraries, ByteCodeError>> {
+        let sections = match self.named_sections() {
+            Ok(sections) => sections,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut found = false;
+        let mut out = dso::DsoLibraries::default();
+        for section in sections.iter().filter(|x| x.tag == dso::DSO_LIBRARIES_TAG) {
+            found = true;
+            match self.section_data(section).and_then(dso::DsoLibraries::try_from) {
+                Ok(mut libs) => out.imports.append(&mut libs.imports),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        found.then_some(Ok(out))
+    }
+
+    /// relocations of the first [reloc::RELOCATIONS_TAG] section, None if there is none
+    pub fn relocations(&self) -> Option<Result<reloc::Relocations, ByteCodeError>> {
+        self.section(&reloc::RELOCATIONS_TAG)
+            .map(|x| x.and_then(reloc::Relocations::try_from))
+    }
+
+    /// initializers of the first [init::INITIALIZERS_TAG] section, None if there is none
+    pub fn initializers(&self) -> Option<Result<init::Initializers, ByteCodeError>> {
+        self.section(&init::INITIALIZERS_TAG)
+            .map(|x| x.and_then(init::Initializers::try_from))
+    }
+
+    /// entries of the section registry, in file order. files before V4 have none
+    pub fn named_sections(&self) -> Result<Vec<NamedSection>, ByteCodeError> {
+        let mut out = vec!();
+        if let Some(ex) = self.extended_header().filter(|_| self.header.has_sections()) {
+            let ex = ex?;
+            let begin = self.header.extended_header_off().unwrap() + ex.length + ex.num_dso as usize * 4;
+            for idx in 0..ex.num_sections as usize {
+                let off = begin + idx * 12;
+                let by = self.bytes.get(off..off + 12).ok_or(ByteCodeError::NotEnoughBytes)?;
+                out.push(NamedSection {
+                    tag: [by[0], by[1], by[2], by[3]],
+                    off: u32::from_le_bytes([by[4], by[5], by[6], by[7]]),
+                    len: u32::from_le_bytes([by[8], by[9], by[10], by[11]]),
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn section_data(&self, section: &NamedSection) -> Result<&'asm [u8], ByteCodeError> {
+        let off = section.off as usize;
+        self.bytes.get(off..off + section.len as usize).ok_or(ByteCodeError::NotEnoughBytes)
+    }
+
+    /// contents of the first section with the given tag
+    pub fn section(&self, tag: &SectionTag) -> Option<Result<&'asm [u8], ByteCodeError>> {
+        match self.named_sections() {
+            Ok(sections) => sections.iter()
+                .find(|x| &x.tag == tag)
+                .map(|x| self.section_data(x)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// reads everything that the extended header refers to
+    pub fn extensions(&self) -> Result<Extensions, ByteCodeError> {
+        let mut sections = vec!();
+        for section in self.named_sections()?.iter().filter(|x| x.tag != debug_info::DEBUG_INFO_TAG) {
+            sections.push((section.tag, self.section_data(section)?.to_vec()));
+        }
+        Ok(Extensions {
+            dso: self.dso_names()?,
+            debug_info: self.debug_info().transpose()?,
+            sections,
+        })
+    }
+
     pub fn dso_names(&self) -> Result<Vec<u32>, ByteCodeError> {
         let mut out = vec!();
         if let Some(ex) = self.extended_header() {
@@ -644,7 +1151,7 @@ impl<'asm> Bytecode<'asm> {
                 let dso_begin = self.header.extended_header_off().unwrap() + ex.length;
                 let off = dso_begin + (idx as usize) * 4;
                 let mut bytes = [0_u8;4];
-                bytes.clone_from_slice(&self.bytes[off..off+4]);
+                bytes.clone_from_slice(self.bytes.get(off..off+4).ok_or(ByteCodeError::NotEnoughBytes)?);
                 let v = u32::from_le_bytes(bytes);
                 out.push(v);
             }
@@ -655,27 +1162,111 @@ impl<'asm> Bytecode<'asm> {
     pub fn const_ops(&self, off: u32) -> Result<OpsIter<'asm>, ByteCodeError> {
         let ops_slice = self.data_table().get((off as usize)..)
             .ok_or(ByteCodeError::ElementNotFound)?;
-        Ok(OpsIter::new(16 + off as usize, ops_slice))
+        Ok(OpsIter::new(self.header.data_begin() + off as usize, ops_slice, self.header.op_encoding()))
     }
 
     pub fn main_ops(&self) -> OpsIter<'asm> {
-        OpsIter::new(self.header.main_ops_area_begin_idx(), self.main_ops_area())
+        OpsIter::new(self.header.main_ops_area_begin_idx(), self.main_ops_area(), self.header.op_encoding())
     }
 
+    /// empty if out of bounds
     pub fn data_table(&self) -> &'asm [u8] {
-        &self.bytes[16..16+self.header.globals_tab_off as usize]
+        let begin = self.header.data_begin();
+        self.bytes.get(begin..begin+self.header.globals_tab_off as usize).unwrap_or(&[])
     }
 
+    /// empty if out of bounds
     pub fn globals_table(&self) -> &'asm [u8] {
-        &self.bytes[16+self.header.globals_tab_off as usize..]
+        self.bytes.get(self.header.data_begin()+self.header.globals_tab_off as usize..).unwrap_or(&[])
     }
 
+    /// empty if out of bounds
     pub fn main_ops_area(&self) -> &'asm [u8] {
-        &self.bytes[self.header.main_ops_area_begin_idx()..]
+        self.bytes.get(self.header.main_ops_area_begin_idx()..).unwrap_or(&[])
+    }
+
+    /// range of the strings section in the data table. None before V4
+    pub fn strings_section(&self) -> Option<Range<usize>> {
+        self.header.has_sections()
+            .then_some(0..self.header.strings_len as usize)
+    }
+
+    /// range of the code section in the data table. None before V4
+    pub fn code_section(&self) -> Option<Range<usize>> {
+        let begin = self.header.strings_len as usize;
+        self.header.has_sections()
+            .then_some(begin..begin + self.header.code_len as usize)
     }
 
-    /// output locations are relative to [self.data_table()]
+    /// range of the constant pool in the data table. None before V4
+    pub fn pool_section(&self) -> Option<Range<usize>> {
+        let begin = self.header.strings_len as usize + self.header.code_len as usize;
+        self.header.has_sections()
+            .then_some(begin..self.header.globals_tab_off as usize)
+    }
+
+    /// encoded bytes of the constant array at [idx], including the length prefix
+    pub fn const_arr_bytes(&self, ty: &PushConstArrType, idx: u32) -> Result<&'asm [u8], ByteCodeError> {
+        let idx = idx as usize;
+        let len_size = self.header.arr_len_size();
+        let len = self.data_table().get(idx..idx + len_size)
+            .ok_or(ByteCodeError::ElementNotFound)?;
+        let mut by = [0_u8;4];
+        by[..len_size].copy_from_slice(len);
+        let len = u32::from_le_bytes(by) as usize;
+        let elt_size = match ty {
+            PushConstArrType::U8 => 1,
+            PushConstArrType::I16 => 2,
+        };
+        len.checked_mul(elt_size)
+            .and_then(|size| self.data_table().get(idx..(idx + len_size).checked_add(size)?))
+            .ok_or(ByteCodeError::ElementNotFound)
+    }
+
+    /// elements of the constant array at [idx]
+    pub fn const_arr(&self, ty: &PushConstArrType, idx: u32) -> Result<Vec<Num>, ByteCodeError> {
+        let data = &self.const_arr_bytes(ty, idx)?[self.header.arr_len_size()..];
+        Ok(match ty {
+            PushConstArrType::U8 => data.iter().map(|x| *x as Num).collect(),
+            PushConstArrType::I16 => data.chunks(2)
+                .map(|x| i16::from_le_bytes([x[0], x[1]]) as Num)
+                .collect(),
+        })
+    }
+
+    /// beginnings of all code sequences in the code section, relative to [self.data_table()].
+    /// None before V4
+    pub fn codes_in_code_section(&self) -> Option<Result<Vec<usize>, ByteCodeError>> {
+        let range = self.code_section()?;
+        let code = match self.data_table().get(range.clone()) {
+            Some(c) => c,
+            None => return Some(Err(ByteCodeError::InvalidSections)),
+        };
+        let mut out = vec!();
+        let mut pos = 0;
+        while pos < code.len() {
+            out.push(range.start + pos);
+            let mut iter = OpsIter::new(pos, &code[pos..], self.header.op_encoding());
+            for op in iter.by_ref() {
+                if let Err(e) = op {
+                    return Some(Err(e));
+                }
+            }
+            if iter.base >= code.len() {
+                return Some(Err(ByteCodeError::NotEnoughBytes));
+            }
+            pos = iter.base + 1;
+        }
+        Some(Ok(out))
+    }
+
+    /// output locations are relative to [self.data_table()].
+    /// since V4, these are all codes in the code section, even if not referenced
     pub fn codes_in_data_table(&self) -> Result<HashSet<usize>, ByteCodeError> {
+        if let Some(codes) = self.codes_in_code_section() {
+            return Ok(codes?.into_iter().collect());
+        }
+
         fn rec<'a, I: Iterator<Item=Result<(usize, Op), ByteCodeError>>>(bc: &Bytecode, out: &mut HashSet<usize>, iter: I) -> Result<(),ByteCodeError> {
             for op in iter {
                 let op = op?.1;
@@ -701,9 +1292,25 @@ impl<'asm> Bytecode<'asm> {
         }
         Ok(out)
     }
+
+    /// names referenced by [Op::Unresolved] in the main ops or in any code of the data table.
+    /// in files that are not linked yet, this includes names that are declared in the same file
+    pub fn unresolved_symbols(&self) -> Result<BTreeSet<&'asm str>, ByteCodeError> {
+        let mut out = BTreeSet::new();
+        let mut ops = vec!(self.main_ops());
+        for code in self.codes_in_data_table()? {
+            ops.push(self.const_ops(code as u32)?);
+        }
+        for op in ops.into_iter().flatten() {
+            if let Op::Unresolved { id } = op?.1 {
+                out.insert(self.string(id)?);
+            }
+        }
+        Ok(out)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum ByteCodeError {
     InvalidMagic,
     UnsupportedVersion,
@@ -712,6 +1319,12 @@ pub enum ByteCodeError {
     InvalidStringEncoding,
     ArrEndMismatch,
     UnknownOpcode(u8),
+    /// the sections are larger than the data table
+    InvalidSections,
+    /// a compact param is longer than 5 bytes, or does not fit into 32 bits
+    InvalidVarInt,
+    /// a param does not fit into the space of the op it replaces
+    ParamTooLarge,
 }
 
 impl fmt::Debug for ByteCodeError {
@@ -724,6 +1337,9 @@ impl fmt::Debug for ByteCodeError {
             ByteCodeError::InvalidStringEncoding => write!(f, "Invalid string encoding"),
             ByteCodeError::UnknownOpcode(val) => write!(f, "Unknown opcode {:#x}", val),
             ByteCodeError::ArrEndMismatch => write!(f, "Different amount of ArrBegin compared to ArrEnd"),
+            ByteCodeError::InvalidSections => write!(f, "Sections larger than data table"),
+            ByteCodeError::InvalidVarInt => write!(f, "Invalid variable length param"),
+            ByteCodeError::ParamTooLarge => write!(f, "Param does not fit into the op"),
         }
     }
 }
@@ -1,24 +1,14 @@
 
 use nostd::prelude::*;
 use nostd::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet},
     io::{self, Seek, Write, Read, SeekFrom}
 };
 use crate::*;
-
-#[cfg(feature = "smallvec")]
-type SmallVec<T, const N: usize> = smallvec::SmallVec<T,N>;
-#[cfg(feature = "smallvec")]
-fn empty_smallvec<T, const N: usize>() -> SmallVec<T, N> {
-    smallvec::smallvec!()
-}
-
-#[cfg(not(feature = "smallvec"))]
-type SmallVec<T, const N: usize> = Vec<T>;
-#[cfg(not(feature = "smallvec"))]
-fn empty_smallvec<T, const N: usize>() -> SmallVec<T, N> {
-    vec!()
-}
+use crate::archive::Archive;
+use crate::debug_info::{DebugEntry, DebugInfo};
+use crate::reloc::{Relocations, RELOCATIONS_TAG};
+use crate::init::{Initializer, Initializers, INITIALIZERS_TAG};
 
 #[derive(Debug)]
 pub enum LinkError {
@@ -27,6 +17,14 @@ pub enum LinkError {
     VersionMismatch,
     SymbolDefinedTwice(String),
     SymbolNotFound(String),
+    /// every symbol that [self_link] could not resolve, ordered by name
+    UnresolvedSymbols(Vec<UnresolvedSymbol>),
+    /// dso imports that none of the libraries passed to [bind_dso] exports
+    DsoImportsNotFound(Vec<String>),
+    /// a module passed to [set_main] to drop the initializer of, that has none
+    InitializerNotFound(String),
+    /// `constAt!` reads code at offsets that are only known at runtime, so [self_gc] can not know what is reachable
+    GcWithConstAt,
 }
 
 impl From<io::Error> for LinkError {
@@ -41,183 +39,1085 @@ impl From<ByteCodeError> for LinkError {
     }
 }
 
-fn invert<T, E>(x: Option<Result<T, E>>) -> Result<Option<T>, E> {
-    x.map_or(Ok(None), |v| v.map(Some))
+/// new beginnings of the sections of one file in the concatenated data table
+struct Placement {
+    strings: u32,
+    code: u32,
+    pool: u32,
 }
 
-/// after concatenating all files into one, run self_link
-pub fn cat_together<W: Write + Seek + Read>(output: &mut W, input: &[u8]) -> Result<(), LinkError> {
-    let input = Bytecode::try_from(input)?;
+/// maps offsets into the data table of [bc] to offsets into the concatenated data table
+fn section_reloc(bc: &Bytecode, to: Placement) -> impl Fn(u32) -> u32 {
+    let code = bc.header.strings_len;
+    let pool = code + bc.header.code_len;
+    move |off| {
+        if off < code {
+            off + to.strings
+        } else if off < pool {
+            off - code + to.code
+        } else {
+            off - pool + to.pool
+        }
+    }
+}
 
-    let mut out_header = [0_u8; 16];
-    output.seek(SeekFrom::Start(0))?;
-    output.read_exact(&mut out_header)?;
-    let out_header = Header::try_from(out_header.as_slice())?;
-    if out_header.writer_version != input.header.writer_version {
-        return Err(LinkError::VersionMismatch)
-    }
-
-    // need to add this to every idx in second
-    let second_offset = out_header.globals_tab_off;
-
-    let out_rem_off = 16 + out_header.globals_tab_off as u64;
-    output.seek(SeekFrom::Start(out_rem_off))?;
-    let mut out_rem = vec!();
-    output.read_to_end(&mut out_rem)?;
-
-    let out_ex_header = invert(out_header.extended_header_off()
-        .map(|off| {
-            ExtendedHeader::try_from(&out_rem.as_slice()[(off - out_rem_off as usize)..])
-        }))?;
-
-    let mut new_data_tab = input.data_table().to_vec();
-    for code in input.codes_in_data_table()? {
-        for op in input.const_ops(code as u32)? {
-            let (pos, op) = op?;
-            let op = op.offset(second_offset as usize);
-            let pos = pos - 16;
-
-            let mut new_bytes = vec!();
-            op.write(&mut new_bytes)?;
-            new_data_tab[pos..pos + new_bytes.len()].copy_from_slice(new_bytes.as_slice());
-        }
-    }
-    output.seek(SeekFrom::Start(out_rem_off))?;
-    output.write_all(new_data_tab.as_slice())?;
-
-    let new_globals_begin = output.seek(SeekFrom::Current(0))?;
-    let new_globals_len = input.header.globals_tab_num + out_header.globals_tab_num;
-    output.write_all(&out_rem[..out_header.globals_tab_num as usize * 8])?;
-    for kv in input.globals() {
-        let kv = Export {
-            name: kv.name + second_offset,
-            const_id: kv.const_id + second_offset
+/// concatenates the sections of all [modules], which have to have the op encoding of [header] with relocatable ops.
+/// the versions and flags of [header] are combined with the ones of the modules.
+/// only the ops listed in the relocations section of a module are patched. modules without one are scanned for
+/// relocatable ops instead. the output gets a relocations section for all modules.
+/// the main ops of every module are recorded as its initializer, in the order of the modules.
+/// other named sections with the same tag are concatenated
+fn cat(header: &Header, modules: &[Bytecode]) -> Result<Vec<u8>, LinkError> {
+    let enc = header.op_encoding();
+    let mut sections = vec!();
+    for bc in modules.iter() {
+        let (Some(strings), Some(code), Some(pool)) = (bc.strings_section(), bc.code_section(), bc.pool_section()) else {
+            return Err(LinkError::VersionMismatch);
         };
-        kv.write(output)?;
+        sections.push((strings, code, pool));
+    }
+    let strings_len = sections.iter().map(|x| x.0.len()).sum::<usize>();
+    let code_len = sections.iter().map(|x| x.1.len()).sum::<usize>();
+
+    let mut relocs = vec!();
+    let (mut strings, mut code, mut pool) = (0, strings_len, strings_len + code_len);
+    for (bc, sect) in modules.iter().zip(sections.iter()) {
+        relocs.push(section_reloc(bc, Placement { strings: strings as u32, code: code as u32, pool: pool as u32 }));
+        strings += sect.0.len();
+        code += sect.1.len();
+        pool += sect.2.len();
+    }
+
+    let exts = modules.iter().map(|x| x.extensions()).collect::<Result<Vec<_>, _>>()?;
+    // dso imports with the same name are merged. maps the dso ids of every module to the new ones
+    let mut dso = vec!();
+    let mut dso_ids = BTreeMap::<&str, u32>::new();
+    let mut dso_remaps = vec!();
+    for (idx, (bc, ex)) in modules.iter().zip(exts.iter()).enumerate() {
+        let mut remap = vec!();
+        for off in ex.dso.iter() {
+            let id = match dso_ids.get(bc.string(*off)?) {
+                Some(id) => *id,
+                None => {
+                    dso.push(relocs[idx](*off));
+                    dso_ids.insert(bc.string(*off)?, dso.len() as u32 - 1);
+                    dso.len() as u32 - 1
+                }
+            };
+            remap.push(id);
+        }
+        dso_remaps.push(remap);
     }
+    let op_reloc = |idx: usize, op: Op| match op.relocate(&relocs[idx]) {
+        Op::DsoConst { dso_id } => Op::DsoConst {
+            dso_id: dso_remaps[idx].get(dso_id as usize).copied().unwrap_or(dso_id),
+        },
+        op => op,
+    };
+
+    let in_relocs = modules.iter()
+        .map(|bc| bc.relocations().unwrap_or_else(|| Relocations::scan(bc)))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    for op in OpsIter::new(0, &out_rem[out_header.globals_tab_num as usize * 8..]) {
-        let (_, op) = op?;
-        op.write(output)?;
+    let mut data = vec!();
+    for (bc, sect) in modules.iter().zip(sections.iter()) {
+        data.extend_from_slice(bc.data_table().get(sect.0.clone()).ok_or(ByteCodeError::InvalidSections)?);
     }
-    for op in input.main_ops() {
-        let op = op?.1.offset(second_offset as usize);
-        op.write(output)?;
+    for (idx, (bc, sect)) in modules.iter().zip(sections.iter()).enumerate() {
+        let begin = data.len();
+        data.extend_from_slice(bc.data_table().get(sect.1.clone()).ok_or(ByteCodeError::InvalidSections)?);
+        let positions = in_relocs[idx].positions.iter()
+            .map(|x| *x as usize)
+            .filter(|x| sect.1.contains(x))
+            .map(|x| x - sect.1.start);
+        patch_ops_at(&mut data[begin..], positions, enc, |op| op_reloc(idx, op))?;
+    }
+    for (bc, sect) in modules.iter().zip(sections.iter()) {
+        data.extend_from_slice(bc.data_table().get(sect.2.clone()).ok_or(ByteCodeError::InvalidSections)?);
     }
-    Op::Terminate.write(output)?;
 
-    let mut new_dso = input.dso_names()?;
-    if let Some(ex) = out_ex_header {
-        let dso_tab = out_header.extended_header_off().unwrap() - out_rem_off as usize + ex.length;
-        for idx in 0..ex.num_dso {
-            let off = dso_tab + idx as usize * 4;
-            let mut by = [0_u8;4];
-            by.copy_from_slice(&out_rem.as_slice()[off..off+4]);
-            let num = u32::from_le_bytes(by);
-            new_dso.push(num);
+    let globals_tab_off = data.len() as u32;
+    let globals_tab_num = modules.iter().map(|x| x.header.globals_tab_num).sum::<u32>();
+    for (bc, reloc) in modules.iter().zip(relocs.iter()) {
+        for kv in bc.globals() {
+            Export { name: reloc(kv.name), const_id: reloc(kv.const_id), weak: kv.weak }.write(&mut data)?;
         }
     }
 
-    let new_ex_header_begin = if new_dso.len() > 0 {
-        let b = output.seek(SeekFrom::Current(0))?;
-        ExtendedHeader {
-            num_dso: new_dso.len() as u32,
-            ..Default::default()
-        }.write(output)?;
-        for dso in new_dso {
-            output.write_all(&dso.to_le_bytes())?;
+    // main ops of all modules get moved behind the new globals table
+    let new_main = data.len() as u32;
+    let mut main_offs = vec!();
+    let mut main_lens = vec!();
+    for (idx, bc) in modules.iter().enumerate() {
+        let begin = data.len() as u32;
+        main_offs.push(begin - new_main);
+        for op in bc.main_ops() {
+            op_reloc(idx, op?.1).write_relocatable(&mut data, enc)?;
         }
-        Some(b)
-    } else {
-        None
+        main_lens.push(data.len() as u32 - begin);
+    }
+    Op::Terminate.write(&mut data)?;
+
+    let mut ex = Extensions { dso, ..Extensions::default() };
+    let mut debug_info = None::<DebugInfo>;
+    let mut out_relocs = Relocations::default();
+    let mut out_init = Initializers::default();
+    for (idx, ((bc, mut in_ex), in_reloc)) in modules.iter().zip(exts).zip(in_relocs).enumerate() {
+        let main_old = bc.header.globals_tab_off + bc.header.globals_tab_num * 8;
+        let reloc = |pos| {
+            if pos < bc.header.globals_tab_off {
+                relocs[idx](pos)
+            } else {
+                pos - main_old + new_main + main_offs[idx]
+            }
+        };
+        out_relocs.positions.extend(in_reloc.positions.into_iter().map(reloc));
+
+        // linked modules keep the initializers of the modules they consist of
+        match bc.initializers().transpose()? {
+            Some(in_init) => out_init.modules.extend(in_init.modules.into_iter()
+                .map(|x| Initializer { pos: reloc(x.pos), ..x })),
+            None if main_lens[idx] > 0 => out_init.modules.push(Initializer {
+                module: bc.metadata().transpose()?
+                    .and_then(|x| x.modules.into_iter().next())
+                    .map(|x| x.name)
+                    .unwrap_or_default(),
+                pos: new_main + main_offs[idx],
+                len: main_lens[idx],
+            }),
+            None => (),
+        }
+
+        if let Some(in_debug) = in_ex.debug_info {
+            // sorted once at the end instead of in every [DebugInfo::merge]
+            let dbg = debug_info.get_or_insert_with(DebugInfo::default);
+            let file_off = dbg.files.len() as u32;
+            dbg.files.extend(in_debug.files);
+            dbg.entries.extend(in_debug.entries.into_iter().map(|x| DebugEntry {
+                pos: reloc(x.pos),
+                file: x.file + file_off,
+                ..x
+            }));
+        }
+
+        // the other named sections are merged into one section per tag, by concatenating them in module order
+        in_ex.sections.retain(|(tag, _)| *tag != RELOCATIONS_TAG && *tag != INITIALIZERS_TAG);
+        for (tag, mut data) in in_ex.sections {
+            match ex.sections.iter_mut().find(|(x, _)| *x == tag) {
+                Some((_, merged)) => merged.append(&mut data),
+                None => ex.add_section(tag, data),
+            }
+        }
+    }
+    if let Some(dbg) = &mut debug_info {
+        dbg.entries.sort_by_key(|x| x.pos);
+    }
+    ex.debug_info = debug_info;
+    out_relocs.positions.sort();
+    ex.add_section(RELOCATIONS_TAG, out_relocs.serialize());
+    if !out_init.modules.is_empty() {
+        ex.add_section(INITIALIZERS_TAG, out_init.serialize());
+    }
+
+    let mut header = Header {
+        min_reader_version: modules.iter().map(|x| x.header.min_reader_version).fold(header.min_reader_version, u8::max),
+        writer_version: modules.iter().map(|x| x.header.writer_version).fold(header.writer_version, u8::max),
+        flags: modules.iter().fold(header.flags, |flags, x| flags | (x.header.flags & FLAG_WEAK_GLOBALS)),
+        globals_tab_num,
+        globals_tab_off,
+        strings_len: strings_len as u32,
+        code_len: code_len as u32,
+        ..header.clone()
     };
+    header._extended_header_off = ex.write(header.data_begin() + data.len(), &mut data);
+
+    let mut file = header.serialize();
+    file.append(&mut data);
+    Ok(file)
+}
 
+/// after concatenating all files into one, run self_link.
+/// both files have to be V4 or later. older files can be converted with [crate::upgrade].
+/// the input is converted to the op encoding of the output. in the compact encoding, the output is relocatable
+/// afterwards, even if it was relaxed before, see [crate::encoding]
+///
+/// this re-reads and rewrites the whole output, use [Linker] to link many files
+pub fn cat_together<W: Write + Seek + Read>(output: &mut W, input: &[u8]) -> Result<(), LinkError> {
+    let mut out_bytes = vec!();
     output.seek(SeekFrom::Start(0))?;
-    Header {
-        globals_tab_num: new_globals_len,
-        globals_tab_off: new_globals_begin as u32 - 16,
-        _extended_header_off: new_ex_header_begin.unwrap_or(0) as u32,
-        ..out_header
-    }.write(output)?;
+    output.read_to_end(&mut out_bytes)?;
+    let out = Bytecode::try_from(out_bytes.as_slice())?;
+
+    let mut linker = Linker::new();
+    linker.header = out.header.clone();
+    linker.add(out)?;
+    linker.add(Bytecode::try_from(input)?)?;
+    let new = linker.cat()?;
+    output.seek(SeekFrom::Start(0))?;
+    output.write_all(new.as_slice())?;
     Ok(())
 }
 
+/// names that are referenced, but neither declared as global nor as dso import
+pub fn undefined_symbols<'asm>(bc: &Bytecode<'asm>) -> Result<BTreeSet<&'asm str>, LinkError> {
+    let mut out = bc.unresolved_symbols()?;
+    for global in bc.named_globals() {
+        out.remove(global?.0);
+    }
+    for dso in bc.dso_names()? {
+        out.remove(bc.string(dso)?);
+    }
+    Ok(out)
+}
+
+/// links any number of modules into one file in a single pass:
+/// ```ignore
+/// let mut linker = Linker::new();
+/// linker.add(Bytecode::try_from(a)?)?.add(Bytecode::try_from(b)?)?;
+/// let bytes = linker.link(&target)?;
+/// ```
+/// all modules have to be V4 or later. older files can be converted with [crate::upgrade].
+/// the modules are converted to the op encoding of the linker
+#[derive(Default)]
+pub struct Linker<'asm> {
+    header: Header,
+    modules: Vec<Bytecode<'asm>>,
+}
+
+impl<'asm> Linker<'asm> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// [OpEncoding::Fixed] by default
+    pub fn set_op_encoding(&mut self, enc: OpEncoding) -> &mut Self {
+        self.header.set_op_encoding(enc);
+        self
+    }
+
+    /// modules are concatenated in the order they are added
+    pub fn add(&mut self, module: Bytecode<'asm>) -> Result<&mut Self, LinkError> {
+        if !module.header.has_sections() {
+            return Err(LinkError::VersionMismatch);
+        }
+        self.modules.push(module);
+        Ok(self)
+    }
+
+    pub fn modules(&self) -> &[Bytecode<'asm>] {
+        self.modules.as_slice()
+    }
+
+    /// names that are referenced, but neither declared as global nor as dso import in any module
+    pub fn undefined_symbols(&self) -> Result<BTreeSet<&'asm str>, LinkError> {
+        let mut out = BTreeSet::new();
+        for bc in self.modules.iter() {
+            out.extend(bc.unresolved_symbols()?);
+        }
+        for bc in self.modules.iter() {
+            for global in bc.named_globals() {
+                out.remove(global?.0);
+            }
+            for dso in bc.dso_names()? {
+                out.remove(bc.string(dso)?);
+            }
+        }
+        Ok(out)
+    }
+
+    /// adds the members of [archives] that define symbols which are still undefined, until no new member is needed.
+    /// if multiple archives define a symbol, the first one is used.
+    /// returns (archive index, member index) of the added members, in the order they were added
+    pub fn add_archives(&mut self, archives: &'asm [Archive]) -> Result<Vec<(usize, usize)>, LinkError> {
+        let mut added = vec!();
+        loop {
+            let mut new = vec!();
+            for sym in self.undefined_symbols()? {
+                let found = archives.iter().enumerate()
+                    .find_map(|(a, ar)| ar.symbols.get(sym).map(|m| (a, *m as usize)));
+                if let Some(found) = found.filter(|x| !added.contains(x) && !new.contains(x)) {
+                    new.push(found);
+                }
+            }
+            if new.is_empty() {
+                return Ok(added);
+            }
+
+            for (a, m) in new {
+                self.add(Bytecode::try_from(archives[a].members[m].bytes.as_slice())?)?;
+                added.push((a, m));
+            }
+        }
+    }
+
+    /// concatenates all modules without resolving any symbols. in the compact encoding, the output is relocatable,
+    /// see [crate::encoding]
+    pub fn cat(&self) -> Result<Vec<u8>, LinkError> {
+        let enc = self.header.op_encoding();
+        let reencoded = self.modules.iter()
+            .map(|bc| (enc == OpEncoding::Compact || bc.header.op_encoding() != enc)
+                .then(|| encoding::reencode(bc.bytes, enc))
+                .transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let modules = self.modules.iter().zip(reencoded.iter())
+            .map(|(bc, re)| match re {
+                Some(re) => Bytecode::try_from(re.as_slice()),
+                None => Ok(Bytecode::from_header(bc.bytes, bc.header.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        cat(&self.header, modules.as_slice())
+    }
+
+    /// concatenates all modules and resolves the symbols with [self_link]
+    pub fn link<T: Target>(&self, target: &T) -> Result<Vec<u8>, LinkError> {
+        let mut bytes = self.cat()?;
+        self_link(&mut bytes, target)?;
+        Ok(bytes)
+    }
+}
+
+/// moves the globals of [bin] whose names are in [names] into the namespace [prefix], by renaming them to
+/// `prefix::name`. references to these names are renamed too, so that they still resolve after linking.
+/// [names] should contain the globals of every file that gets linked with the same prefix.
+///
+/// the file has to be V4 or later. in the compact encoding, the output is relocatable
+pub fn namespace(bin: &[u8], prefix: &str, names: &BTreeSet<&str>) -> Result<Vec<u8>, LinkError> {
+    let header = Header::try_from(bin)?;
+    if !header.has_sections() {
+        return Err(LinkError::VersionMismatch);
+    }
+    let enc = header.op_encoding();
+    let bytes;
+    let bin = if enc == OpEncoding::Compact {
+        bytes = encoding::reencode(bin, enc)?;
+        bytes.as_slice()
+    } else {
+        bin
+    };
+    let bc = Bytecode::try_from(bin)?;
+    let (strings, code, pool) = (bc.strings_section().unwrap(), bc.code_section().unwrap(), bc.pool_section().unwrap());
+    let data = bc.data_table();
+    let mut ex = bc.extensions()?;
+
+    // old string begin -> new string begin, for every string in the strings section
+    let mut str_map = BTreeMap::new();
+    let mut new_strings = vec!();
+    let mut off = 0;
+    for str in data.get(strings.clone()).ok_or(ByteCodeError::InvalidSections)?.split_inclusive(|x| *x == 0) {
+        str_map.insert(off as u32, new_strings.len() as u32);
+        let name = str.strip_suffix(&[0]).unwrap_or(str);
+        let rename = !ex.dso.contains(&(off as u32)) && core::str::from_utf8(name).is_ok_and(|x| names.contains(x));
+        if rename {
+            new_strings.extend_from_slice(prefix.as_bytes());
+            new_strings.extend_from_slice(b"::");
+        }
+        new_strings.extend_from_slice(str);
+        off += str.len();
+    }
+    let shift = (new_strings.len() - strings.len()) as u32;
+    let reloc = |off: u32| {
+        if off < code.start as u32 {
+            let (old, new) = str_map.range(..=off).next_back().unwrap_or((&0, &0));
+            off - old + new
+        } else {
+            off + shift
+        }
+    };
+
+    let mut out = new_strings;
+    let code_begin = out.len();
+    for range in [code.clone(), pool] {
+        out.extend_from_slice(data.get(range).ok_or(ByteCodeError::InvalidSections)?);
+    }
+    patch_ops(&mut out[code_begin..code_begin + code.len()], enc, |op| op.relocate(reloc))?;
+
+    let globals_tab_off = out.len() as u32;
+    for kv in bc.globals() {
+        Export { name: reloc(kv.name), const_id: reloc(kv.const_id), weak: kv.weak }.write(&mut out)?;
+    }
+    for op in bc.main_ops() {
+        op?.1.relocate(reloc).write_relocatable(&mut out, enc)?;
+    }
+    Op::Terminate.write(&mut out)?;
+
+    ex.dso.iter_mut().for_each(|x| *x = reloc(*x));
+    // debug positions are never in the strings section
+    ex.debug_info.iter_mut().for_each(|dbg| dbg.relocate(|pos| pos + shift));
+    for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == RELOCATIONS_TAG) {
+        let mut relocs = Relocations::try_from(section.as_slice())?;
+        relocs.relocate(|pos| Some(pos + shift));
+        *section = relocs.serialize();
+    }
+    for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == INITIALIZERS_TAG) {
+        let mut init = Initializers::try_from(section.as_slice())?;
+        init.modules.iter_mut().for_each(|x| x.pos += shift);
+        *section = init.serialize();
+    }
+    for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == metadata::METADATA_TAG) {
+        if let Ok(mut meta) = metadata::Metadata::try_from(section.as_slice()) {
+            for (name, _) in meta.modules.iter_mut().flat_map(|m| m.docs.iter_mut()) {
+                if names.contains(name.as_str()) {
+                    *name = format!("{}::{}", prefix, name);
+                }
+            }
+            *section = meta.serialize();
+        }
+    }
+
+    let mut header = Header {
+        globals_tab_off,
+        strings_len: code_begin as u32,
+        ..bc.header.clone()
+    };
+    header._extended_header_off = ex.write(header.data_begin() + out.len(), &mut out);
+
+    let mut file = header.serialize();
+    file.append(&mut out);
+    Ok(file)
+}
+
+/// checks that every dso import of the linked file [bin] is exported by one of the [libraries], as
+/// (file name, linked library), and records the library of every import in a [dso::DSO_LIBRARIES_TAG] section,
+/// which replaces the old ones. if multiple libraries export a name, the first one is used
+pub fn bind_dso(bin: &[u8], libraries: &[(&str, &[u8])]) -> Result<Vec<u8>, LinkError> {
+    let bc = Bytecode::try_from(bin)?;
+    let mut exports = vec!();
+    for (name, lib) in libraries.iter() {
+        let names = Bytecode::try_from(*lib)?.named_globals()
+            .map(|x| x.map(|x| x.0))
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        exports.push((*name, names));
+    }
+
+    let mut imports = vec!();
+    let mut missing = vec!();
+    for off in bc.dso_names()? {
+        let import = bc.string(off)?;
+        match exports.iter().find(|(_, names)| names.contains(import)) {
+            Some((lib, _)) => imports.push((import.to_string(), lib.to_string())),
+            None => missing.push(import.to_string()),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(LinkError::DsoImportsNotFound(missing));
+    }
+
+    let mut ex = bc.extensions()?;
+    ex.sections.retain(|(tag, _)| *tag != dso::DSO_LIBRARIES_TAG);
+    if !imports.is_empty() {
+        ex.add_section(dso::DSO_LIBRARIES_TAG, dso::DsoLibraries { imports }.serialize());
+    }
+
+    // everything after the main ops belongs to the extended header
+    let mut ops = bc.main_ops();
+    for op in ops.by_ref() {
+        op?;
+    }
+    let main_end = ops.base + 1;
+    let mut out = bin.get(bc.header.size()..main_end).ok_or(ByteCodeError::NotEnoughBytes)?.to_vec();
+    let mut header = bc.header.clone();
+    header._extended_header_off = ex.write(main_end, &mut out);
+
+    let mut file = header.serialize();
+    file.append(&mut out);
+    Ok(file)
+}
+
+/// rebuilds the main ops of the linked file [bin] from its initializers, in their order, without the ones of the
+/// modules in [drop]. if [entry] is given, the global with that name is executed after all initializers.
+/// files without initializers section have a single initializer without module name.
+///
+/// should run after [self_link], because the entry has to be defined in the file. the file has to be V4 or later
+pub fn set_main(bin: &[u8], entry: Option<&str>, drop: &[&str]) -> Result<Vec<u8>, LinkError> {
+    let bc = Bytecode::try_from(bin)?;
+    if !bc.header.has_sections() {
+        return Err(LinkError::VersionMismatch);
+    }
+    let enc = bc.header.op_encoding();
+    let data_begin = bc.header.data_begin();
+    // positions are relative to the data table, but the main ops are behind it
+    let data = bin.get(data_begin..).ok_or(ByteCodeError::NotEnoughBytes)?;
+    let main_begin = bc.header.main_ops_area_begin_idx() - data_begin;
+    let mut ops = bc.main_ops();
+    for op in ops.by_ref() {
+        op?;
+    }
+    let main_end = ops.base - data_begin;
+
+    let init = match bc.initializers().transpose()? {
+        Some(init) => init,
+        None => Initializers {
+            modules: (main_end > main_begin).then(|| Initializer {
+                module: String::new(),
+                pos: main_begin as u32,
+                len: (main_end - main_begin) as u32,
+            }).into_iter().collect(),
+        },
+    };
+
+    if let Some(name) = drop.iter().find(|name| !init.modules.iter().any(|x| x.module == **name)) {
+        return Err(LinkError::InitializerNotFound(name.to_string()));
+    }
+
+    // (old begin, old end, new begin) of every initializer that is kept
+    let mut out = data.get(..main_begin).ok_or(ByteCodeError::NotEnoughBytes)?.to_vec();
+    let mut blocks = vec!();
+    let mut new_init = Initializers::default();
+    for x in init.modules.into_iter().filter(|x| !drop.contains(&x.module.as_str())) {
+        let (begin, end) = (x.pos, x.pos + x.len);
+        blocks.push((begin, end, out.len() as u32));
+        new_init.modules.push(Initializer { pos: out.len() as u32, ..x });
+        out.extend_from_slice(data.get(begin as usize..end as usize).ok_or(ByteCodeError::NotEnoughBytes)?);
+    }
+    let main_reloc = |pos: u32| if (pos as usize) < main_begin {
+        Some(pos)
+    } else {
+        blocks.iter()
+            .find(|(begin, end, _)| pos >= *begin && pos < *end)
+            .map(|(begin, _, new)| pos - begin + new)
+    };
+
+    let mut ex = bc.extensions()?;
+    let mut relocs = bc.relocations().transpose()?;
+    relocs.iter_mut().for_each(|x| x.relocate(main_reloc));
+
+    if let Some(entry) = entry {
+        // weak globals are only used if there is no other one
+        let global = bc.globals()
+            .filter(|g| bc.string(g.name).is_ok_and(|name| name == entry))
+            .min_by_key(|g| g.weak)
+            .ok_or(LinkError::SymbolNotFound(entry.to_string()))?;
+        relocs.iter_mut().for_each(|x| x.positions.push(out.len() as u32));
+        Op::Const { idx: global.const_id }.write_relocatable(&mut out, enc)?;
+        Op::Exec.write_enc(&mut out, enc)?;
+    }
+    Op::Terminate.write(&mut out)?;
+
+    if let Some(debug) = &mut ex.debug_info {
+        debug.entries = debug.entries.drain(..)
+            .filter_map(|ent| main_reloc(ent.pos).map(|pos| DebugEntry { pos, ..ent }))
+            .collect();
+    }
+    ex.sections.retain(|(tag, _)| *tag != RELOCATIONS_TAG && *tag != INITIALIZERS_TAG);
+    if let Some(relocs) = relocs {
+        ex.add_section(RELOCATIONS_TAG, relocs.serialize());
+    }
+    if !new_init.modules.is_empty() {
+        ex.add_section(INITIALIZERS_TAG, new_init.serialize());
+    }
+
+    let mut header = bc.header.clone();
+    header._extended_header_off = ex.write(data_begin + out.len(), &mut out);
+
+    let mut file = header.serialize();
+    file.append(&mut out);
+    Ok(file)
+}
+
+/// code that contains a reference, see [UnresolvedSymbol]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Referrer {
+    /// the code of a global
+    Global(String),
+    /// data table offset of code that no global refers to, like private bindings
+    Code { begin: u32 },
+    /// index of the op in the main ops that contains the reference, or that leads to the code containing it
+    Main { op: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnresolvedSymbol {
+    pub name: String,
+    /// every referrer with the data table offset of its first reference
+    pub refs: Vec<(Referrer, u32)>,
+    /// defined globals and dso imports with a similar name, best match first
+    pub suggestions: Vec<String>,
+}
+
+/// levenshtein distance, in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec!(i + 1);
+        for (j, cb) in b.iter().enumerate() {
+            let subst = prev[j] + (ca != *cb) as usize;
+            cur.push(subst.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// the (at most 3) names of [candidates] that are closest to [name]
+fn suggestions<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let max = (name.chars().count() / 3).max(1);
+    let mut close = candidates
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(dist, _)| *dist <= max)
+        .collect::<Vec<_>>();
+    close.sort();
+    close.into_iter().take(3).map(|(_, x)| x.to_string()).collect()
+}
+
 pub trait Target {
     fn allow_undeclared_symbol(&self, sym: &str) -> bool;
+
+    /// name that unresolved references to [sym] get resolved to instead, like with `--wrap` or `--defsym`.
+    /// only applies to references that are not resolved yet
+    fn redirect(&self, _sym: &str) -> Option<String> {
+        None
+    }
 }
 
+/// resolves the [Op::Unresolved] ops listed in the relocations section of [bin] to the globals and dso imports of
+/// the file, in place. files without relocations section are scanned for them instead.
+/// the file has to be V4 or later
 pub fn self_link<T: Target>(bin: &mut [u8], target: &T) -> Result<(), LinkError> {
-    let header = Header::try_from(bin.as_ref())?;
-
-    let mut decls = HashMap::new();
-    for decl in Bytecode::from_header(bin, header.clone()).named_globals() {
-        let (name,val) = decl?;
-        if decls.contains_key(name) {
-            Err(LinkError::SymbolDefinedTwice(name.to_string()))?;
-        }
-        decls.insert(unsafe{ &*(name as *const str) }, val);
-    }
-
-    let mut dso = HashMap::new();
-    for (id, name_idx) in Bytecode::from_header(bin, header.clone()).dso_names()?.into_iter().enumerate() {
-        let name = Bytecode::from_header(bin, header.clone()).string(name_idx)?;
-        dso.insert(unsafe{ &*(name as *const str) }, id);
-    }
-
-    let mut done = Vec::<usize>::new();
-
-    let mut todo = vec!(
-        Bytecode::from_header(bin, header.clone()).main_ops_area().as_ptr().addr() - bin.as_ptr().addr() - 16);
-    todo.extend(decls.iter().map(|x| (*x.1) as usize));
-
-    while let Some(off) = todo.pop() {
-        let mut to_write = empty_smallvec::<(usize,Op), 16>();
-        for op in OpsIter::new(off, &bin[16+off..]) {
-            let (pos, op) = op?;
-            done.push(pos);
-            match op {
-                Op::Unresolved { id } => {
-                    let str = Bytecode::from_header(bin, header.clone()).string(id)?;
-                    match decls.get(str) {
-                        Some(decl_pos) => {
-                            to_write.push((pos, Op::Const { idx: *decl_pos }));
-                        }
-
-                        None => {
-                            match dso.get(str) {
-                                Some(id) => {
-                                    to_write.push((pos, Op::DsoConst { dso_id: *id as u32 }));
-                                }
-
-                                None => {
-                                    if !target.allow_undeclared_symbol(str) {
-                                        Err(LinkError::SymbolNotFound(str.to_string()))?;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    // everything that is needed is copied out of [bin] first, because it gets patched at the end
+    let bc = Bytecode::try_from(&*bin)?;
+    let Some(code) = bc.code_section() else {
+        return Err(LinkError::VersionMismatch);
+    };
+    let enc = bc.header.op_encoding();
+    let data_begin = bc.header.data_begin();
+    let globals = bc.globals()
+        .map(|g| Ok((bc.string(g.name)?.to_string(), g.const_id, g.weak)))
+        .collect::<Result<Vec<_>, LinkError>>()?;
 
-                Op::Const { idx } => {
-                    if !done.contains(&(idx as usize)) {
-                        todo.push(idx as usize);
-                    }
-                }
+    // name -> (value, weak). a weak global is replaced by a strong one, and the first weak one is used otherwise
+    let mut decls = BTreeMap::<&str, (u32, bool)>::new();
+    for (name, const_id, weak) in globals.iter() {
+        match decls.get(name.as_str()) {
+            Some((_, false)) if !weak => Err(LinkError::SymbolDefinedTwice(name.clone()))?,
+            Some(_) if *weak => (),
+            _ => { decls.insert(name, (*const_id, *weak)); },
+        }
+    }
 
-                _ => ()
+    let mut dso = BTreeMap::new();
+    for (id, name) in bc.dso_names()?.into_iter().enumerate() {
+        dso.insert(bc.string(name)?.to_string(), id as u32);
+    }
+
+    // begin of every code sequence in the code section, and the name of a global that refers to it
+    let mut code_begins = BTreeMap::<u32, Option<&str>>::new();
+    let mut pos = code.start;
+    while pos < code.end {
+        code_begins.entry(pos as u32).or_default();
+        loop {
+            let (size, op) = OpType::read_enc(bc.data_table().get(pos..).ok_or(ByteCodeError::InvalidSections)?, enc)?;
+            pos += size;
+            if op == Op::Terminate || pos >= code.end {
+                break;
             }
         }
+    }
+    for (name, const_id, _) in globals.iter() {
+        if let Some(owner) = code_begins.get_mut(const_id) {
+            owner.get_or_insert(name);
+        }
+    }
+    let main_begin = bc.header.main_ops_area_begin_idx() - data_begin;
+    let main_idx = bc.main_ops()
+        .enumerate()
+        .map(|(idx, op)| Ok(((op?.0 - data_begin) as u32, idx as u32)))
+        .collect::<Result<BTreeMap<_, _>, LinkError>>()?;
+    let referrer = |pos: u32| if pos as usize >= main_begin {
+        Referrer::Main { op: main_idx.get(&pos).copied().unwrap_or_default() }
+    } else {
+        match code_begins.range(..=pos).next_back() {
+            Some((_, Some(name))) => Referrer::Global(name.to_string()),
+            Some((begin, None)) => Referrer::Code { begin: *begin },
+            None => Referrer::Code { begin: pos },
+        }
+    };
 
-        for (pos,val) in to_write {
-            let mut v = vec!();
-            val.write(&mut v)?;
-            bin[16+pos..16+pos+v.len()].copy_from_slice(v.as_slice());
+    let relocs = match bc.relocations() {
+        Some(relocs) => relocs?,
+        None => Relocations::scan(&bc)?,
+    };
+    // (position, new op) of every resolved op
+    let mut to_write = vec!();
+    // name -> referrer -> position of the first reference
+    let mut missing = BTreeMap::<String, BTreeMap<Referrer, u32>>::new();
+    for pos in relocs.positions {
+        let at = data_begin + pos as usize;
+        let (_, op) = OpType::read_enc(bin.get(at..).ok_or(ByteCodeError::NotEnoughBytes)?, enc)?;
+        let Op::Unresolved { id } = op else {
+            continue;
+        };
+        let name = bc.string(id)?;
+        let redirect = target.redirect(name);
+        let str = redirect.as_deref().unwrap_or(name);
+        match (decls.get(str), dso.get(str)) {
+            (Some((decl_pos, _)), _) => to_write.push((at, Op::Const { idx: *decl_pos })),
+            (None, Some(id)) => to_write.push((at, Op::DsoConst { dso_id: *id })),
+            (None, None) if target.allow_undeclared_symbol(str) => (),
+            (None, None) => {
+                missing.entry(str.to_string()).or_default()
+                    .entry(referrer(pos)).or_insert(pos);
+            }
         }
     }
 
+    if !missing.is_empty() {
+        let unresolved = missing.into_iter()
+            .map(|(name, refs)| UnresolvedSymbol {
+                suggestions: suggestions(&name, decls.keys().copied().chain(dso.keys().map(|x| x.as_str()))),
+                name,
+                refs: refs.into_iter().collect(),
+            })
+            .collect();
+        return Err(LinkError::UnresolvedSymbols(unresolved));
+    }
+
+    // weak entries that are not used point to the used definition, so that every reader sees the same value
+    let globals_tab = data_begin + bc.header.globals_tab_off as usize;
+    let weak_entries = globals.iter().enumerate()
+        .filter(|(_, (name, const_id, weak))| *weak && decls[name.as_str()].0 != *const_id)
+        .map(|(idx, (name, _, _))| (globals_tab + idx * 8 + 4, decls[name.as_str()].0))
+        .collect::<Vec<_>>();
+
+    for (pos, val) in weak_entries {
+        bin[pos..pos + 4].copy_from_slice(&val.to_le_bytes());
+    }
+    // the new op is padded to the size of the old one
+    for (pos, op) in to_write {
+        let (size, _) = OpType::read_enc(&bin[pos..], enc)?;
+        let v = op.encode_sized(enc, size)?;
+        bin[pos..pos + v.len()].copy_from_slice(v.as_slice());
+    }
+
     Ok(())
 }
 
-// TODO: add self_gc() to opt binsize: remove unreferenced code sequences, strip symbol table,
-//   remove unused dso entries, dedup dso entries, ...
+/// what [self_gc] removed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    /// bytes removed from the strings section
+    pub strings: usize,
+    /// bytes removed from the code section
+    pub code: usize,
+    /// bytes removed from the constant pool
+    pub pool: usize,
+    /// amount of removed globals table entries
+    pub globals: usize,
+    /// amount of removed dso entries
+    pub dso: usize,
+}
+
+/// removes everything that is not reachable from the main ops or the globals named in [roots]:
+/// code sequences, strings, constant arrays, globals table entries and dso entries. dso entries and strings with the
+/// same content are merged. the data table is compacted afterwards, and the debug info and metadata are updated.
+///
+/// should run after [self_link], because unresolved references are kept as they are. the file has to be V4 or later
+pub fn self_gc(bin: &[u8], roots: &[&str]) -> Result<(Vec<u8>, GcReport), LinkError> {
+    let bc = Bytecode::try_from(bin)?;
+    let (Some(strings), Some(code), Some(pool)) = (bc.strings_section(), bc.code_section(), bc.pool_section()) else {
+        return Err(LinkError::VersionMismatch);
+    };
+    let enc = bc.header.op_encoding();
+    let data_begin = bc.header.data_begin();
+    let data = bc.data_table();
+    let dso_names = bc.dso_names()?;
+
+    let mut globals = vec!();
+    for root in roots {
+        // weak globals are only used if there is no other one
+        let global = bc.globals()
+            .filter(|g| bc.string(g.name).is_ok_and(|name| name == *root))
+            .min_by_key(|g| g.weak)
+            .ok_or(LinkError::SymbolNotFound(root.to_string()))?;
+        globals.push(global);
+    }
+
+    // everything that is reachable, as offsets into the data table.
+    // code sequences are stored as begin -> end (after Terminate)
+    let mut codes = BTreeMap::<u32, u32>::new();
+    let mut strs = globals.iter().map(|g| g.name).collect::<BTreeSet<u32>>();
+    let mut arrs = BTreeMap::<u32, PushConstArrType>::new();
+    let mut dsos = BTreeSet::<u32>::new();
+    let mut todo = globals.iter().map(|g| g.const_id).collect::<Vec<_>>();
+
+    let mut visit = |op: Op, todo: &mut Vec<u32>| -> Result<(), LinkError> {
+        match op {
+            Op::Const { idx } => todo.push(idx),
+            Op::Unresolved { id } => { strs.insert(id); },
+            Op::ArrAt { ty, idx } => { arrs.insert(idx, ty); },
+            Op::DsoConst { dso_id } => { dsos.insert(dso_id); },
+            Op::ConstAt => Err(LinkError::GcWithConstAt)?,
+            _ => (),
+        }
+        Ok(())
+    };
+
+    let mut main = bc.main_ops();
+    for op in main.by_ref() {
+        visit(op?.1, &mut todo)?;
+    }
+    let (main_begin, main_end) = (bc.header.main_ops_area_begin_idx(), main.base + 1);
+
+    while let Some(idx) = todo.pop() {
+        if codes.contains_key(&idx) {
+            continue;
+        }
+        let mut iter = bc.const_ops(idx)?;
+        for op in iter.by_ref() {
+            visit(op?.1, &mut todo)?;
+        }
+        codes.insert(idx, (iter.base - data_begin + 1) as u32);
+    }
+
+    // dso entries with the same name get merged
+    let mut dso_map = BTreeMap::new();
+    let mut dso_by_name = BTreeMap::<&str, u32>::new();
+    let mut new_dso = vec!();
+    for id in dsos.iter() {
+        let name = *dso_names.get(*id as usize).ok_or(ByteCodeError::ElementNotFound)?;
+        let next = new_dso.len() as u32;
+        let new = *dso_by_name.entry(bc.string(name)?).or_insert_with(|| {
+            new_dso.push(name);
+            next
+        });
+        dso_map.insert(*id, new);
+    }
+
+    let mut new_strings = vec!();
+    let mut str_map = BTreeMap::new();
+    let mut known_strs = BTreeMap::<&str, u32>::new();
+    for off in strs.iter().chain(new_dso.iter()) {
+        let str = bc.string(*off)?;
+        let new = *known_strs.entry(str).or_insert_with(|| {
+            let p = new_strings.len() as u32;
+            new_strings.extend_from_slice(str.as_bytes());
+            new_strings.push(0);
+            p
+        });
+        str_map.insert(*off, new);
+    }
+
+    // code sequences can overlap, so they get merged into blocks of (old begin, old end, new begin)
+    let code_begin = new_strings.len() as u32;
+    let mut blocks = Vec::<(u32, u32, u32)>::new();
+    for (begin, end) in codes.iter() {
+        match blocks.last_mut() {
+            Some(last) if *begin < last.1 => last.1 = last.1.max(*end),
+            _ => blocks.push((*begin, *end, 0)),
+        }
+    }
+    let mut new_code = vec!();
+    for block in blocks.iter_mut() {
+        block.2 = code_begin + new_code.len() as u32;
+        new_code.extend_from_slice(data.get(block.0 as usize..block.1 as usize).ok_or(ByteCodeError::ElementNotFound)?);
+    }
+    let code_reloc = |off: u32| blocks.iter()
+        .find(|(begin, end, _)| off >= *begin && off < *end)
+        .map(|(begin, _, new)| off - begin + new);
+
+    let pool_begin = code_begin + new_code.len() as u32;
+    let mut new_pool = vec!();
+    let mut arr_map = BTreeMap::new();
+    let mut known_arrs = BTreeMap::<&[u8], u32>::new();
+    for (idx, ty) in arrs.iter() {
+        let bytes = bc.const_arr_bytes(ty, *idx)?;
+        let new = *known_arrs.entry(bytes).or_insert_with(|| {
+            let p = pool_begin + new_pool.len() as u32;
+            new_pool.extend_from_slice(bytes);
+            p
+        });
+        arr_map.insert(*idx, new);
+    }
+
+    let mut err = None;
+    let mut reloc = |op: Op| {
+        let new = match &op {
+            Op::Const { idx } => code_reloc(*idx).map(|idx| Op::Const { idx }),
+            Op::Unresolved { id } => str_map.get(id).map(|id| Op::Unresolved { id: *id }),
+            Op::ArrAt { ty, idx } => arr_map.get(idx).map(|idx| Op::ArrAt { ty: ty.clone(), idx: *idx }),
+            Op::DsoConst { dso_id } => dso_map.get(dso_id).map(|dso_id| Op::DsoConst { dso_id: *dso_id }),
+            op => Some(op.clone()),
+        };
+        new.unwrap_or_else(|| {
+            err = Some(ByteCodeError::ElementNotFound);
+            op
+        })
+    };
+
+    let mut out = new_strings;
+    out.append(&mut new_code);
+    patch_ops(&mut out[code_begin as usize..], enc, &mut reloc)?;
+    out.append(&mut new_pool);
+
+    let globals_tab_off = out.len() as u32;
+    for global in globals.iter() {
+        let const_id = code_reloc(global.const_id).ok_or(ByteCodeError::ElementNotFound)?;
+        Export { name: str_map[&global.name], const_id, weak: global.weak }.write(&mut out)?;
+    }
+
+    let new_main = out.len() as u32;
+    let mut main = bin.get(main_begin..main_end).ok_or(ByteCodeError::NotEnoughBytes)?.to_vec();
+    patch_ops(&mut main, enc, &mut reloc)?;
+    out.append(&mut main);
+    if let Some(e) = err {
+        return Err(e.into());
+    }
+
+    let mut ex = bc.extensions()?;
+    ex.dso = new_dso.iter().map(|x| str_map[x]).collect();
+    let old_globals_tab = bc.header.globals_tab_off;
+    let old_main = (main_begin - data_begin) as u32;
+    if let Some(debug) = &mut ex.debug_info {
+        debug.entries = debug.entries.drain(..)
+            .filter_map(|ent| {
+                let pos = if ent.pos >= old_globals_tab {
+                    Some(ent.pos - old_main + new_main)
+                } else {
+                    code_reloc(ent.pos)
+                };
+                pos.map(|pos| debug_info::DebugEntry { pos, ..ent })
+            })
+            .collect();
+    }
+    // relocations of removed code are dropped
+    for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == RELOCATIONS_TAG) {
+        let mut relocs = Relocations::try_from(section.as_slice())?;
+        relocs.relocate(|pos| if pos >= old_globals_tab {
+            Some(pos - old_main + new_main)
+        } else {
+            code_reloc(pos)
+        });
+        *section = relocs.serialize();
+    }
+    // the main ops are kept as they are
+    for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == INITIALIZERS_TAG) {
+        let mut init = Initializers::try_from(section.as_slice())?;
+        init.modules.iter_mut().for_each(|x| x.pos = x.pos - old_main + new_main);
+        *section = init.serialize();
+    }
+    // docs of removed globals are not needed anymore
+    for (_, section) in ex.sections.iter_mut().filter(|(tag, _)| *tag == metadata::METADATA_TAG) {
+        if let Ok(mut meta) = metadata::Metadata::try_from(section.as_slice()) {
+            for module in meta.modules.iter_mut() {
+                module.docs.retain(|(name, _)| roots.contains(&name.as_str()));
+            }
+            *section = meta.serialize();
+        }
+    }
+
+    let report = GcReport {
+        strings: strings.len() - code_begin as usize,
+        code: code.len() - (pool_begin - code_begin) as usize,
+        pool: pool.len() - (globals_tab_off - pool_begin) as usize,
+        globals: bc.header.globals_tab_num as usize - globals.len(),
+        dso: dso_names.len() - new_dso.len(),
+    };
+
+    let mut header = Header {
+        globals_tab_num: globals.len() as u32,
+        globals_tab_off,
+        strings_len: code_begin,
+        code_len: pool_begin - code_begin,
+        ..bc.header.clone()
+    };
+    header._extended_header_off = ex.write(header.data_begin() + out.len(), &mut out);
+
+    let mut file = header.serialize();
+    file.append(&mut out);
+    Ok((file, report))
+}
+
+/// one code sequence of the code section, see [link_map]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapSymbol {
+    /// names in the globals table that refer to this code. empty for code that is not exported, like private bindings
+    pub names: Vec<String>,
+    /// offset into the data table
+    pub offset: u32,
+    /// in bytes, including the Terminate
+    pub size: u32,
+    /// offsets of the code sequences referenced with [Op::Const]
+    pub refs: BTreeSet<u32>,
+}
+
+/// where everything ended up in a linked file
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkMap {
+    /// ordered by offset
+    pub symbols: Vec<MapSymbol>,
+    /// offsets of the code sequences referenced by the main ops
+    pub main_refs: BTreeSet<u32>,
+    /// file offset of the data table, to turn the offsets of the symbols into file offsets
+    pub data_begin: u32,
+    pub strings_len: u32,
+    pub code_len: u32,
+    pub pool_len: u32,
+    pub globals_len: u32,
+    /// including the Terminate
+    pub main_len: u32,
+    /// everything after the main ops, like the extended header and debug info
+    pub extensions_len: u32,
+}
+
+impl LinkMap {
+    pub fn symbol(&self, offset: u32) -> Option<&MapSymbol> {
+        self.symbols.binary_search_by_key(&offset, |x| x.offset).ok().map(|idx| &self.symbols[idx])
+    }
+
+    /// code sequences that reference [offset]
+    pub fn referrers(&self, offset: u32) -> impl Iterator<Item = &MapSymbol> {
+        self.symbols.iter().filter(move |x| x.refs.contains(&offset))
+    }
+}
+
+/// builds the [LinkMap] of a V4 or later file
+pub fn link_map(bin: &[u8]) -> Result<LinkMap, LinkError> {
+    let bc = Bytecode::try_from(bin)?;
+    let (Some(strings), Some(code), Some(pool)) = (bc.strings_section(), bc.code_section(), bc.pool_section()) else {
+        return Err(LinkError::VersionMismatch);
+    };
+    let data_begin = bc.header.data_begin();
+
+    let refs_of = |ops: OpsIter| -> Result<(BTreeSet<u32>, usize), LinkError> {
+        let mut refs = BTreeSet::new();
+        let mut ops = ops;
+        for op in ops.by_ref() {
+            if let Op::Const { idx } = op?.1 {
+                refs.insert(idx);
+            }
+        }
+        Ok((refs, ops.base))
+    };
+
+    let mut symbols = vec!();
+    for offset in bc.codes_in_code_section().unwrap()? {
+        let (refs, end) = refs_of(bc.const_ops(offset as u32)?)?;
+        symbols.push(MapSymbol {
+            names: vec!(),
+            offset: offset as u32,
+            size: (end - data_begin + 1 - offset) as u32,
+            refs,
+        });
+    }
+    for global in bc.named_globals() {
+        let (name, offset) = global?;
+        let idx = symbols.binary_search_by_key(&offset, |x| x.offset)
+            .map_err(|_| ByteCodeError::ElementNotFound)?;
+        symbols[idx].names.push(name.to_string());
+    }
+
+    let (main_refs, main_end) = refs_of(bc.main_ops())?;
+    let main_len = (main_end + 1 - bc.header.main_ops_area_begin_idx()) as u32;
+    let globals_len = bc.header.globals_tab_num * 8;
+    Ok(LinkMap {
+        symbols,
+        main_refs,
+        data_begin: data_begin as u32,
+        strings_len: strings.len() as u32,
+        code_len: code.len() as u32,
+        pool_len: pool.len() as u32,
+        globals_len,
+        main_len,
+        extensions_len: (bin.len() - (main_end + 1)) as u32,
+    })
+}
@@ -0,0 +1,120 @@
+use nostd::prelude::*;
+use nostd::str;
+use crate::{ByteCodeError, SectionTag};
+
+/// tag of the named section that contains the [Metadata]
+pub const METADATA_TAG: SectionTag = *b"META";
+
+/// information about one compiled module
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ModuleInfo {
+    pub name: String,
+    /// empty if unknown
+    pub version: String,
+    /// doc comment at the beginning of the module. empty if there is none
+    pub doc: String,
+    /// source file names
+    pub sources: Vec<String>,
+    /// doc comments of the globals, as (name, doc)
+    pub docs: Vec<(String, String)>,
+}
+
+/// metadata section:
+///   any number of module records:
+///     len: u32_le, of the remaining record
+///     name, version, doc: utf8, null terminated
+///     num sources: u32_le
+///     sources: num sources * (utf8, null terminated)
+///     num docs: u32_le
+///     docs: num docs * { name: utf8, null terminated; doc: utf8, null terminated }
+///
+/// because the section is only a sequence of records, the linker merges it by concatenation like any other
+/// named section, and a linked file keeps the records of every module.
+/// unknown trailing bytes in a record are skipped, so that records can get more fields in the future
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Metadata {
+    pub modules: Vec<ModuleInfo>,
+}
+
+impl Metadata {
+    /// doc comment of the global [name], from the first module that documents it
+    pub fn doc(&self, name: &str) -> Option<&str> {
+        self.modules.iter()
+            .flat_map(|m| m.docs.iter())
+            .find(|(n, _)| n == name)
+            .map(|(_, doc)| doc.as_str())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        fn put_str(out: &mut Vec<u8>, s: &str) {
+            out.extend_from_slice(s.as_bytes());
+            out.push(0);
+        }
+
+        let mut out = vec!();
+        for module in self.modules.iter() {
+            let mut rec = vec!();
+            put_str(&mut rec, &module.name);
+            put_str(&mut rec, &module.version);
+            put_str(&mut rec, &module.doc);
+            rec.extend_from_slice(&(module.sources.len() as u32).to_le_bytes());
+            for src in module.sources.iter() {
+                put_str(&mut rec, src);
+            }
+            rec.extend_from_slice(&(module.docs.len() as u32).to_le_bytes());
+            for (name, doc) in module.docs.iter() {
+                put_str(&mut rec, name);
+                put_str(&mut rec, doc);
+            }
+            out.extend_from_slice(&(rec.len() as u32).to_le_bytes());
+            out.append(&mut rec);
+        }
+        out
+    }
+}
+
+impl<'asm> TryFrom<&'asm [u8]> for Metadata {
+    type Error = ByteCodeError;
+
+    fn try_from(value: &'asm [u8]) -> Result<Self, Self::Error> {
+        fn get_u32(from: &[u8], at: &mut usize) -> Result<u32, ByteCodeError> {
+            let slice = from.get(*at..*at + 4).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let mut bytes = [0_u8;4];
+            bytes.copy_from_slice(slice);
+            *at += 4;
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        fn get_str(from: &[u8], at: &mut usize) -> Result<String, ByteCodeError> {
+            let sl = from.get(*at..).ok_or(ByteCodeError::NotEnoughBytes)?;
+            let term = sl.iter().position(|&b| b == 0).ok_or(ByteCodeError::InvalidStringEncoding)?;
+            let s = str::from_utf8(&sl[0..term]).map_err(|_| ByteCodeError::InvalidStringEncoding)?;
+            *at += term + 1;
+            Ok(s.to_string())
+        }
+
+        let mut modules = vec!();
+        let mut at = 0;
+        while at < value.len() {
+            let len = get_u32(value, &mut at)? as usize;
+            let rec = value.get(at..at + len).ok_or(ByteCodeError::NotEnoughBytes)?;
+            at += len;
+
+            let mut pos = 0;
+            let name = get_str(rec, &mut pos)?;
+            let version = get_str(rec, &mut pos)?;
+            let doc = get_str(rec, &mut pos)?;
+            let mut sources = vec!();
+            for _ in 0..get_u32(rec, &mut pos)? {
+                sources.push(get_str(rec, &mut pos)?);
+            }
+            let mut docs = vec!();
+            for _ in 0..get_u32(rec, &mut pos)? {
+                docs.push((get_str(rec, &mut pos)?, get_str(rec, &mut pos)?));
+            }
+            modules.push(ModuleInfo { name, version, doc, sources, docs });
+        }
+
+        Ok(Self { modules })
+    }
+}
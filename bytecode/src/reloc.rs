@@ -0,0 +1,73 @@
+//! list of all ops that the linker has to relocate, see [crate::linker::Linker::cat]
+
+use nostd::prelude::*;
+use crate::{ByteCodeError, Bytecode, OpType, SectionTag};
+
+/// tag of the named section that contains the [Relocations]
+pub const RELOCATIONS_TAG: SectionTag = *b"RELO";
+
+/// relocations section:
+///   any number of entries:
+///     pos: u32_le
+///
+/// every entry is the position of a relocatable op (see [crate::Op::is_relocatable]), relative to the data table.
+/// positions at or after the globals table are in the main ops area, like in the debug info.
+/// the entries are sorted, and every relocatable op in the code section and in the main ops has one
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Relocations {
+    pub positions: Vec<u32>,
+}
+
+impl Relocations {
+    /// finds all relocatable ops by decoding the code section and the main ops. only since V4
+    pub fn scan(bc: &Bytecode) -> Result<Self, ByteCodeError> {
+        let code = bc.code_section().ok_or(ByteCodeError::UnsupportedVersion)?;
+        let enc = bc.header.op_encoding();
+        let mut positions = vec!();
+
+        let bytes = bc.data_table().get(code.clone()).ok_or(ByteCodeError::InvalidSections)?;
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let (size, op) = OpType::read_enc(&bytes[pos..], enc)?;
+            if op.is_relocatable() {
+                positions.push((code.start + pos) as u32);
+            }
+            pos += size;
+        }
+
+        let data_begin = bc.header.data_begin();
+        for op in bc.main_ops() {
+            let (pos, op) = op?;
+            if op.is_relocatable() {
+                positions.push((pos - data_begin) as u32);
+            }
+        }
+        Ok(Self { positions })
+    }
+
+    /// applies [f] to every position, and drops the ones where it returns None
+    pub fn relocate<F: FnMut(u32) -> Option<u32>>(&mut self, f: F) {
+        self.positions = self.positions.drain(..).filter_map(f).collect();
+        self.positions.sort();
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.positions.iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect()
+    }
+}
+
+impl<'asm> TryFrom<&'asm [u8]> for Relocations {
+    type Error = ByteCodeError;
+
+    fn try_from(value: &'asm [u8]) -> Result<Self, Self::Error> {
+        if !value.len().is_multiple_of(4) {
+            return Err(ByteCodeError::NotEnoughBytes);
+        }
+        let positions = value.chunks_exact(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        Ok(Self { positions })
+    }
+}
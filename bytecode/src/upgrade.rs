@@ -0,0 +1,129 @@
+use nostd::prelude::*;
+use nostd::collections::{BTreeMap, BTreeSet};
+use crate::*;
+
+/// a V3 data table entry, which gets copied into one of the V4 sections
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Entry {
+    Str(u32),
+    Arr(u32, bool),
+}
+
+impl Entry {
+    fn arr(ty: &PushConstArrType, idx: u32) -> Self {
+        Entry::Arr(idx, *ty == PushConstArrType::I16)
+    }
+
+    fn arr_ty(i16: bool) -> PushConstArrType {
+        if i16 { PushConstArrType::I16 } else { PushConstArrType::U8 }
+    }
+}
+
+/// rewrites a V3 bytecode file as V4. V4 files are returned unchanged.
+///
+/// every code sequence, string and constant array that is referenced from the globals table, the main ops,
+/// the dso table or other code is copied into its V4 section. unreferenced parts of the data table are dropped.
+/// numbers that are only used as data table offsets at runtime (`constAt!`) can not be upgraded.
+pub fn upgrade(bytes: &[u8]) -> Result<Vec<u8>, ByteCodeError> {
+    let bc = Bytecode::try_from(bytes)?;
+    if bc.header.has_sections() {
+        return Ok(bytes.to_vec());
+    }
+    let data_begin = bc.header.data_begin();
+
+    // code sequences: begin -> end (after Terminate), both relative to the data table
+    let mut codes = BTreeMap::new();
+    let mut entries = BTreeSet::new();
+    let note = |entries: &mut BTreeSet<Entry>, op: &Op| match op {
+        Op::Unresolved { id } => { entries.insert(Entry::Str(*id)); },
+        Op::ArrAt { ty, idx } => { entries.insert(Entry::arr(ty, *idx)); },
+        _ => (),
+    };
+
+    for begin in bc.codes_in_data_table()? {
+        let mut iter = bc.const_ops(begin as u32)?;
+        for op in &mut iter {
+            note(&mut entries, &op?.1);
+        }
+        codes.insert(begin as u32, (iter.base - data_begin + 1) as u32);
+    }
+    for op in bc.main_ops() {
+        note(&mut entries, &op?.1);
+    }
+    for global in bc.globals() {
+        entries.insert(Entry::Str(global.name));
+    }
+    let dso = bc.dso_names()?;
+    entries.extend(dso.iter().map(|x| Entry::Str(*x)));
+
+    let mut strings = vec!();
+    let mut pool = vec!();
+    let mut map = BTreeMap::new();
+    for ent in entries {
+        match ent {
+            Entry::Str(off) => {
+                map.insert(ent, strings.len() as u32);
+                strings.extend_from_slice(bc.string(off)?.as_bytes());
+                strings.push(0);
+            }
+
+            Entry::Arr(idx, i16) => {
+                let old = bc.const_arr_bytes(&Entry::arr_ty(i16), idx)?;
+                let old_len_size = bc.header.arr_len_size();
+                map.insert(ent, pool.len() as u32);
+                let len = (old.len() - old_len_size) / if i16 { 2 } else { 1 };
+                pool.extend_from_slice(&(len as u32).to_le_bytes());
+                pool.extend_from_slice(&old[old_len_size..]);
+            }
+        }
+    }
+
+    let code_begin = strings.len() as u32;
+    let mut code = vec!();
+    let mut code_map = BTreeMap::new();
+    for (begin, end) in codes.iter() {
+        code_map.insert(*begin, code_begin + code.len() as u32);
+        code.extend_from_slice(&bc.data_table()[*begin as usize..*end as usize]);
+    }
+    let pool_begin = code_begin + code.len() as u32;
+
+    let str_reloc = |off: u32| map[&Entry::Str(off)];
+    let op_reloc = |op: Op| match op {
+        Op::Unresolved { id } => Op::Unresolved { id: str_reloc(id) },
+        Op::Const { idx } => Op::Const { idx: code_map[&idx] },
+        Op::ArrAt { ty, idx } => Op::ArrAt { idx: pool_begin + map[&Entry::arr(&ty, idx)], ty },
+        op => op,
+    };
+    patch_ops(&mut code, OpEncoding::Fixed, op_reloc)?;
+
+    let mut data = strings;
+    data.append(&mut code);
+    data.append(&mut pool);
+
+    let globals_tab_off = data.len() as u32;
+    for global in bc.globals() {
+        Export { name: str_reloc(global.name), const_id: code_map[&global.const_id], weak: false }.write(&mut data).unwrap();
+    }
+
+    for op in bc.main_ops() {
+        op_reloc(op?.1).write(&mut data).unwrap();
+    }
+    Op::Terminate.write(&mut data).unwrap();
+
+    let mut header = Header {
+        globals_tab_num: bc.header.globals_tab_num,
+        globals_tab_off,
+        strings_len: code_begin,
+        code_len: pool_begin - code_begin,
+        ..Default::default()
+    };
+    let ex = Extensions {
+        dso: dso.into_iter().map(str_reloc).collect(),
+        ..Extensions::default()
+    };
+    header._extended_header_off = ex.write(header.data_begin() + data.len(), &mut data);
+
+    let mut file = header.serialize();
+    file.append(&mut data);
+    Ok(file)
+}
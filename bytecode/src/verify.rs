@@ -0,0 +1,294 @@
+use nostd::prelude::*;
+use nostd::{fmt, ops::Range, collections::{BTreeSet, HashSet}};
+use crate::*;
+use crate::debug_info::{DebugInfo, DEBUG_INFO_TAG};
+use crate::metadata::{Metadata, METADATA_TAG};
+use crate::reloc::{Relocations, RELOCATIONS_TAG};
+
+#[derive(Clone, PartialEq)]
+pub enum Problem {
+    /// the header could not be decoded. nothing else is checked in that case
+    Header(ByteCodeError),
+    DataTableOutOfBounds,
+    GlobalsTableOutOfBounds,
+    /// the extended header is outside of the file, or overlaps with the globals table
+    ExtendedHeaderOutOfBounds,
+    ExtendedHeader(ByteCodeError),
+    DsoTableOutOfBounds,
+    DebugInfo(ByteCodeError),
+    Metadata(ByteCodeError),
+    /// not inside the data table, not null terminated, or not utf8
+    InvalidString { off: u32 },
+    /// an op could not be decoded
+    Op(ByteCodeError),
+    /// a code sequence runs past the end of its area without a Terminate op
+    MissingTerminate,
+    ConstOutOfBounds { idx: u32 },
+    ArrAtOutOfBounds { idx: u32 },
+    UnbalancedArr,
+    DsoIdOutOfBounds { dso_id: u32 },
+    SectionRegistryOutOfBounds,
+    SectionOutOfBounds { tag: SectionTag },
+    /// since V4: a reference into the data table points into the wrong section
+    WrongSection { idx: u32, expected: &'static str },
+    Relocations(ByteCodeError),
+    /// a relocatable op that is not listed in the relocations section
+    MissingRelocation { pos: u32 },
+    /// an entry of the relocations section that is not the position of a relocatable op
+    InvalidRelocation { pos: u32 },
+}
+
+impl fmt::Debug for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Header(e) => write!(f, "Invalid header: {:?}", e),
+            Problem::DataTableOutOfBounds => write!(f, "Data table out of bounds"),
+            Problem::GlobalsTableOutOfBounds => write!(f, "Globals table out of bounds"),
+            Problem::ExtendedHeaderOutOfBounds => write!(f, "Extended header out of bounds"),
+            Problem::ExtendedHeader(e) => write!(f, "Invalid extended header: {:?}", e),
+            Problem::DsoTableOutOfBounds => write!(f, "Dso table out of bounds"),
+            Problem::DebugInfo(e) => write!(f, "Invalid debug info: {:?}", e),
+            Problem::Metadata(e) => write!(f, "Invalid metadata: {:?}", e),
+            Problem::InvalidString { off } => write!(f, "Invalid string at data+{}", off),
+            Problem::Op(e) => write!(f, "Invalid op: {:?}", e),
+            Problem::MissingTerminate => write!(f, "Missing Terminate op"),
+            Problem::ConstOutOfBounds { idx } => write!(f, "Constant data+{} out of bounds", idx),
+            Problem::ArrAtOutOfBounds { idx } => write!(f, "Constant array data+{} out of bounds", idx),
+            Problem::UnbalancedArr => write!(f, "{:?}", ByteCodeError::ArrEndMismatch),
+            Problem::DsoIdOutOfBounds { dso_id } => write!(f, "Dso id {} out of bounds", dso_id),
+            Problem::SectionRegistryOutOfBounds => write!(f, "Section registry out of bounds"),
+            Problem::SectionOutOfBounds { tag } => write!(f, "Section {:?} out of bounds", String::from_utf8_lossy(tag)),
+            Problem::WrongSection { idx, expected } => write!(f, "data+{} is not in the {} section", idx, expected),
+            Problem::Relocations(e) => write!(f, "Invalid relocations: {:?}", e),
+            Problem::MissingRelocation { pos } => write!(f, "Relocatable op at data+{} is not in the relocations", pos),
+            Problem::InvalidRelocation { pos } => write!(f, "Relocation data+{} is not a relocatable op", pos),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// absolute byte position in the file, if known
+    pub pos: Option<usize>,
+    pub problem: Problem,
+}
+
+struct Verifier<'asm> {
+    bc: Bytecode<'asm>,
+    num_dso: u32,
+    out: Vec<Diagnostic>,
+}
+
+impl<'asm> Verifier<'asm> {
+    fn report(&mut self, pos: Option<usize>, problem: Problem) {
+        self.out.push(Diagnostic { pos, problem });
+    }
+
+    /// since V4, checks that [idx] is inside of [section]
+    fn check_section(&mut self, pos: Option<usize>, idx: u32, section: Option<Range<usize>>, expected: &'static str) {
+        if section.is_some_and(|s| !s.contains(&(idx as usize))) {
+            self.report(pos, Problem::WrongSection { idx, expected });
+        }
+    }
+
+    fn check_string(&mut self, pos: Option<usize>, off: u32) {
+        // since V4, a string that begins in the strings section also has to end in it
+        let runs_out = |sec: Range<usize>, len: usize| sec.contains(&(off as usize)) && off as usize + len >= sec.end;
+        let valid = match self.bc.string(off) {
+            Ok(s) => !self.bc.strings_section().is_some_and(|sec| runs_out(sec, s.len())),
+            Err(_) => false,
+        };
+        if !valid {
+            self.report(pos, Problem::InvalidString { off });
+        }
+        self.check_section(pos, off, self.bc.strings_section(), "strings");
+    }
+
+    /// reports all problems of the ops at [begin] (absolute), which have to terminate inside [bytes].
+    /// returns the found constant references, and the position after the Terminate op if there is one
+    fn check_code(&mut self, begin: usize, bytes: &'asm [u8]) -> (Vec<u32>, Option<usize>) {
+        let mut consts = vec!();
+        let mut depth = 0_usize;
+        let mut iter = OpsIter::new(begin, bytes, self.bc.header.op_encoding());
+        loop {
+            let pos = iter.base;
+            let (pos, op) = match iter.next() {
+                None => break,
+                Some(Ok(v)) => v,
+                Some(Err(ByteCodeError::NotEnoughBytes)) if pos >= begin + bytes.len() => {
+                    self.report(Some(begin), Problem::MissingTerminate);
+                    return (consts, None);
+                }
+                Some(Err(e)) => {
+                    self.report(Some(pos), Problem::Op(e));
+                    return (consts, None);
+                }
+            };
+
+            match op {
+                Op::Unresolved { id } => self.check_string(Some(pos), id),
+
+                Op::Const { idx } => {
+                    if (idx as usize) < self.bc.data_table().len() {
+                        consts.push(idx);
+                    } else {
+                        self.report(Some(pos), Problem::ConstOutOfBounds { idx });
+                    }
+                    self.check_section(Some(pos), idx, self.bc.code_section(), "code");
+                }
+
+                Op::ArrAt { ty, idx } => {
+                    if self.bc.const_arr_bytes(&ty, idx).is_err() {
+                        self.report(Some(pos), Problem::ArrAtOutOfBounds { idx });
+                    }
+                    self.check_section(Some(pos), idx, self.bc.pool_section(), "pool");
+                }
+
+                Op::DsoConst { dso_id } if dso_id >= self.num_dso => {
+                    self.report(Some(pos), Problem::DsoIdOutOfBounds { dso_id });
+                }
+
+                Op::ArrBegin => depth += 1,
+
+                Op::ArrEnd => match depth.checked_sub(1) {
+                    Some(d) => depth = d,
+                    None => self.report(Some(pos), Problem::UnbalancedArr),
+                },
+
+                _ => ()
+            }
+        }
+
+        if depth != 0 {
+            self.report(Some(begin), Problem::UnbalancedArr);
+        }
+        (consts, Some(iter.base + 1))
+    }
+
+    fn run(&mut self) {
+        let header = self.bc.header.clone();
+        let len = self.bc.bytes.len();
+        let data_begin = header.data_begin();
+        // positions of the header fields
+        let (globals_tab_off_field, ex_header_off_field) = if header.has_sections() { (12, 16) } else { (8, 12) };
+
+        if data_begin + header.globals_tab_off as usize > len {
+            self.report(Some(globals_tab_off_field), Problem::DataTableOutOfBounds);
+            return;
+        }
+        let main_begin = header.main_ops_area_begin_idx();
+        if main_begin > len {
+            self.report(Some(data_begin + header.globals_tab_off as usize), Problem::GlobalsTableOutOfBounds);
+            return;
+        }
+
+        if let Some(off) = header.extended_header_off() {
+            if off < main_begin || off > len {
+                self.report(Some(ex_header_off_field), Problem::ExtendedHeaderOutOfBounds);
+            } else {
+                match self.bc.extended_header().unwrap() {
+                    Err(e) => self.report(Some(off), Problem::ExtendedHeader(e)),
+
+                    Ok(ex) => {
+                        if off + ex.length + ex.num_dso as usize * 4 > len {
+                            self.report(Some(off + ex.length), Problem::DsoTableOutOfBounds);
+                        } else {
+                            self.num_dso = ex.num_dso;
+                            for (idx, name) in self.bc.dso_names().unwrap_or_default().into_iter().enumerate() {
+                                self.check_string(Some(off + ex.length + idx * 4), name);
+                            }
+                        }
+
+                        let registry = off + ex.length + ex.num_dso as usize * 4;
+                        match self.bc.named_sections() {
+                            Err(_) => self.report(Some(registry), Problem::SectionRegistryOutOfBounds),
+                            Ok(sections) => for (idx, section) in sections.iter().enumerate() {
+                                match self.bc.section_data(section) {
+                                    Err(_) => self.report(Some(registry + idx * 12), Problem::SectionOutOfBounds { tag: section.tag }),
+                                    Ok(data) if section.tag == DEBUG_INFO_TAG => if let Err(e) = DebugInfo::try_from(data) {
+                                        self.report(Some(section.off as usize), Problem::DebugInfo(e));
+                                    },
+                                    Ok(data) if section.tag == METADATA_TAG => if let Err(e) = Metadata::try_from(data) {
+                                        self.report(Some(section.off as usize), Problem::Metadata(e));
+                                    },
+                                    Ok(data) if section.tag == RELOCATIONS_TAG => if let Err(e) = Relocations::try_from(data) {
+                                        self.report(Some(section.off as usize), Problem::Relocations(e));
+                                    },
+                                    Ok(_) => (),
+                                }
+                            },
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut todo = vec!();
+        for (idx, global) in self.bc.globals().collect::<Vec<_>>().into_iter().enumerate() {
+            let pos = Some(data_begin + header.globals_tab_off as usize + idx * 8);
+            self.check_string(pos, global.name);
+            if (global.const_id as usize) < self.bc.data_table().len() {
+                todo.push(global.const_id);
+            } else {
+                self.report(pos, Problem::ConstOutOfBounds { idx: global.const_id });
+            }
+            self.check_section(pos, global.const_id, self.bc.code_section(), "code");
+        }
+
+        todo.extend(self.check_code(main_begin, self.bc.main_ops_area()).0);
+
+        let mut done = HashSet::new();
+
+        // since V4, the whole code section is checked, even unreferenced code
+        if let Some(code) = self.bc.code_section() {
+            let mut pos = code.start;
+            while pos < code.end {
+                done.insert(pos as u32);
+                let (consts, end) = self.check_code(data_begin + pos, &self.bc.data_table()[pos..code.end]);
+                todo.extend(consts);
+                match end {
+                    Some(end) => pos = end - data_begin,
+                    None => break,
+                }
+            }
+        }
+
+        // code has to terminate inside of the code section since V4
+        let code_end = self.bc.code_section().map_or(self.bc.data_table().len(), |c| c.end);
+        while let Some(idx) = todo.pop() {
+            if done.insert(idx) {
+                let bytes = self.bc.data_table().get(idx as usize..code_end).unwrap_or(&[]);
+                todo.extend(self.check_code(data_begin + idx as usize, bytes).0);
+            }
+        }
+
+        // the linker only patches the listed ops. ops that can not be decoded are already reported
+        if let (Some(Ok(relocs)), Ok(expected)) = (self.bc.relocations(), Relocations::scan(&self.bc)) {
+            let listed = relocs.positions.into_iter().collect::<BTreeSet<_>>();
+            let expected = expected.positions.into_iter().collect::<BTreeSet<_>>();
+            for pos in expected.difference(&listed) {
+                self.report(Some(data_begin + *pos as usize), Problem::MissingRelocation { pos: *pos });
+            }
+            for pos in listed.difference(&expected) {
+                self.report(Some(data_begin + *pos as usize), Problem::InvalidRelocation { pos: *pos });
+            }
+        }
+    }
+}
+
+/// checks the whole module, and returns every found problem.
+/// if this returns no problems, the accessors of [Bytecode] and the runtime will not panic on it
+pub fn verify(bytes: &[u8]) -> Vec<Diagnostic> {
+    let bc = match Bytecode::try_from(bytes) {
+        Ok(bc) => bc,
+        Err(e) => return vec!(Diagnostic { pos: None, problem: Problem::Header(e) }),
+    };
+
+    let mut v = Verifier {
+        bc,
+        num_dso: 0,
+        out: vec!(),
+    };
+    v.run();
+    v.out
+}
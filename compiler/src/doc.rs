@@ -0,0 +1,76 @@
+use crate::lex::{Spanned, Tok};
+use crate::parse::Expr;
+use h6_bytecode::metadata::ModuleInfo;
+
+/// text of a comment block, without the `#` and one space after it
+fn comment_text(lines: &[&str]) -> String {
+    lines.iter()
+        .map(|x| {
+            let x = x.strip_prefix('#').unwrap_or(x);
+            x.strip_prefix(' ').unwrap_or(x).trim_end()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// comment tokens directly above the token [tok], each on its own line, with no blank line in between
+fn comment_block_above<'src>(src: &'src str, toks: &[Spanned<Tok<'src>>], tok: usize) -> Vec<&'src str> {
+    let mut lines = vec!();
+    let mut end = match toks.get(tok) {
+        Some(t) => t.1.start,
+        None => return lines,
+    };
+    for idx in (0..tok).rev() {
+        let (Tok::Comment(text), span) = &toks[idx] else {
+            break;
+        };
+        if src[span.end..end].matches('\n').count() != 1 {
+            break;
+        }
+        // comments behind code document that code, and not the next binding
+        let line_begin = src[..span.start].rfind('\n').map_or(0, |x| x + 1);
+        if !src[line_begin..span.start].trim().is_empty() {
+            break;
+        }
+        lines.push(*text);
+        end = span.start;
+    }
+    lines.reverse();
+    lines
+}
+
+/// collects the doc comments of a module.
+///
+/// the doc comment of a top-level binding are the comment lines directly above it.
+/// the doc comment of the module is the comment block at the beginning of the file, if it is followed by a blank line
+pub fn module_info<'src>(name: &str, file: &str, src: &'src str, toks: &[Spanned<Tok<'src>>], exprs: &[Expr<'src>]) -> ModuleInfo {
+    // the leading comment block, up to the first blank line
+    let mut leading = vec!();
+    let mut blank = false;
+    while let Some((Tok::Comment(text), span)) = toks.get(leading.len()) {
+        leading.push(*text);
+        let next = toks.get(leading.len()).map_or(src.len(), |x| x.1.start);
+        if src[span.end..next].matches('\n').count() > 1 {
+            blank = true;
+            break;
+        }
+    }
+    let doc = if blank { comment_text(&leading) } else { String::new() };
+
+    let docs = exprs.iter()
+        .filter(|x| !x.dso_extern && !x.private)
+        .filter_map(|x| {
+            let name = x.binding.as_ref()?;
+            let block = comment_block_above(src, toks, x.tok_span.start);
+            (!block.is_empty()).then(|| (name.to_string(), comment_text(&block)))
+        })
+        .collect();
+
+    ModuleInfo {
+        name: name.to_string(),
+        doc,
+        sources: vec!(file.to_string()),
+        docs,
+        ..Default::default()
+    }
+}
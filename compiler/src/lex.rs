@@ -46,6 +46,8 @@ pub enum Tok<'src> {
     OpsOf,
     ConstAt,
     DsoExtern,
+    Private,
+    Weak,
 }
 
 #[derive(Clone, Copy)]
@@ -100,6 +102,8 @@ impl<'src> Into<TokStr<'src>> for &Tok<'src> {
             Tok::SquareOpen => "[".into(),
             Tok::SquareClose => "]".into(),
             Tok::DsoExtern => "<dso_extern>".into(),
+            Tok::Private => "<private>".into(),
+            Tok::Weak => "<weak>".into(),
         }
     }
 }
@@ -124,6 +128,8 @@ impl<'src> Into<TokType> for &Tok<'src> {
             Tok::SquareOpen  |
             Tok::SquareClose |
             Tok::DsoExtern   |
+            Tok::Private     |
+            Tok::Weak        |
             Tok::Colon => TokType::Point,
 
             Tok::Dot |
@@ -358,6 +364,8 @@ pub fn lexer<'src>() ->
         text::keyword("l").to(Tok::L),
         text::keyword("r").to(Tok::R),
         text::keyword("dso_extern").to(Tok::DsoExtern),
+        text::keyword("private").to(Tok::Private),
+        text::keyword("weak").to(Tok::Weak),
     )).or(choice([
         just(":").to(Tok::Colon),
         just(".").to(Tok::Dot),
@@ -385,13 +393,25 @@ pub fn lexer<'src>() ->
         just("@<").to(Tok::AtLeft),
     ])).boxed();
 
+    // qualified identifiers like `std::map` refer to globals linked with a namespace.
+    // `::` is not valid anywhere else, so existing code like `a.b` (`a`, dup, `b`) keeps its meaning
+    let ident = text::ident()
+        .then(just("::").then(text::ident()).repeated())
+        .to_slice();
+
+    // `_` is pack, but identifiers starting with `__` are used for linker generated names like `__real_readln`
+    let reserved_ident = just("__")
+        .then(ident)
+        .to_slice();
+
     let tok: Boxed<_, Tok, extra::Err<Cheap>> = choice([
         ref_planet.boxed(),
         num.boxed(),
         str.boxed(),
         comment.boxed(),
+        reserved_ident.map(|x: &str| Tok::Ident(x.into())).boxed(),
         op.boxed(),
-        text::ident().map(|x: &str| Tok::Ident(x.into())).boxed(),
+        ident.map(|x: &str| Tok::Ident(x.into())).boxed(),
         char.boxed(),
     ]).boxed();
 
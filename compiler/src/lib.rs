@@ -1,6 +1,7 @@
 pub mod lex;
 pub mod parse;
 pub mod lower;
+pub mod doc;
 
 use lex::{Spanned, Tok};
 
@@ -1,8 +1,10 @@
-use std::collections::HashMap;
-use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use crate::lex::TokStr;
 use crate::parse::Expr;
 use h6_bytecode::*;
+use h6_bytecode::debug_info::DebugInfo;
+use h6_bytecode::metadata::{Metadata, ModuleInfo, METADATA_TAG};
+use h6_bytecode::reloc::{Relocations, RELOCATIONS_TAG};
 
 pub trait Position {
     fn pos(&self) -> usize;
@@ -41,9 +43,36 @@ impl<W: std::io::Write> Position for PosWriter<'_, W> {
     }
 }
 
+/// source information used to emit a debug info section
+pub struct SrcInfo<'a> {
+    pub file: &'a str,
+    /// byte range in the source of every token. indexed by the token spans in [Expr::spans]
+    pub tok_spans: Vec<std::ops::Range<usize>>,
+    line_starts: Vec<usize>,
+    src: &'a str,
+}
+
+impl<'a> SrcInfo<'a> {
+    pub fn new(file: &'a str, src: &'a str, tok_spans: Vec<std::ops::Range<usize>>) -> Self {
+        let mut line_starts = vec!(0);
+        line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+        Self { file, tok_spans, line_starts, src }
+    }
+
+    /// 1-based line and column of the given token
+    pub fn line_col(&self, tok: usize) -> Option<(u32, u32)> {
+        let byte = self.tok_spans.get(tok)?.start;
+        let line = self.line_starts.partition_point(|x| *x <= byte) - 1;
+        let col = self.src.get(self.line_starts[line]..byte)?.chars().count();
+        Some((line as u32 + 1, col as u32 + 1))
+    }
+}
+
 #[derive(Debug)]
 pub enum SrcError {
-    NotSupported
+    NotSupported,
+    /// a private binding is defined more than once in the file
+    SymbolDefinedTwice(String),
 }
 
 impl SrcError {
@@ -75,131 +104,301 @@ impl From<std::io::Error> for LoweringError {
     }
 }
 
-pub fn lower_full<'src: 'l, 'l, W, I>(sink: &mut W, exprs: I, pic: bool) -> Result<(), LoweringError>
+/// the constant pool section, built with offsets relative to it
+#[derive(Default)]
+struct Pool {
+    data: Vec<u8>,
+    /// encoded array (with length prefix) -> offset, so that equal literals are only stored once
+    known: HashMap<Vec<u8>, u32>,
+}
+
+impl Pool {
+    /// places [vals] as constant array, if that is smaller than pushing them one by one
+    fn pack(&mut self, vals: &[Num], enc: OpEncoding) -> Option<Op> {
+        let (ty, elt_size) = if vals.iter().all(|x| u8::try_from(*x).is_ok()) {
+            (PushConstArrType::U8, 1)
+        } else if vals.iter().all(|x| i16::try_from(*x).is_ok()) {
+            (PushConstArrType::I16, 2)
+        } else {
+            return None;
+        };
+
+        let packed = Op::ArrAt { ty: ty.clone(), idx: 0 }.relocatable_size(enc) + 4 + vals.len() * elt_size;
+        let pushed = 2 + vals.iter().map(|val| Op::Push { val: *val }.size(enc)).sum::<usize>();
+        if packed >= pushed {
+            return None;
+        }
+
+        let mut ent = (vals.len() as u32).to_le_bytes().to_vec();
+        for val in vals {
+            match elt_size {
+                1 => ent.push(*val as u8),
+                _ => ent.extend_from_slice(&(*val as i16).to_le_bytes()),
+            }
+        }
+        let idx = *self.known.entry(ent).or_insert_with_key(|ent| {
+            let p = self.data.len() as u32;
+            self.data.extend_from_slice(ent);
+            p
+        });
+        Some(Op::ArrAt { ty, idx })
+    }
+}
+
+/// replaces array literals that only contain numbers with [Op::ArrAt] into the [pool].
+/// arrays nested into other arrays are kept, because their ops can be observed, for example with `@*`
+fn pack_arrays(pool: &mut Pool, enc: OpEncoding, ops: Vec<Op>, spans: &[std::ops::Range<usize>]) -> (Vec<Op>, Vec<Option<std::ops::Range<usize>>>) {
+    let mut out = vec!();
+    let mut out_spans = vec!();
+    let mut i = 0;
+    while i < ops.len() {
+        if ops[i] == Op::ArrBegin {
+            let vals = ops[i + 1..].iter()
+                .map_while(|x| match x {
+                    Op::Push { val } => Some(*val),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let end = i + 1 + vals.len();
+            let packed = (ops.get(end) == Some(&Op::ArrEnd))
+                .then(|| pool.pack(&vals, enc))
+                .flatten();
+            if let Some(op) = packed {
+                out.push(op);
+                out_spans.push(spans.get(i).cloned());
+                i = end + 1;
+                continue;
+            }
+
+            let mut depth = 0;
+            while i < ops.len() {
+                match ops[i] {
+                    Op::ArrBegin => depth += 1,
+                    Op::ArrEnd => depth -= 1,
+                    _ => (),
+                }
+                out.push(ops[i].clone());
+                out_spans.push(spans.get(i).cloned());
+                i += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        out.push(ops[i].clone());
+        out_spans.push(spans.get(i).cloned());
+        i += 1;
+    }
+    (out, out_spans)
+}
+
+pub fn lower_full<'src: 'l, 'l, W, I>(sink: &mut W, exprs: I, pic: bool, enc: OpEncoding, src: Option<&SrcInfo>, meta: Option<&ModuleInfo>) -> Result<(), LoweringError>
 where W: std::io::Write + std::io::Seek,
       I: Iterator<Item = &'l Expr<'src>>,
 {
     let begin = sink.stream_position()?;
-    sink.write_all(&[0_u8;16])?;
-    // the Position getter should NOT INCLUDE THE 16B HEADER
-    let header = lower(&mut PosWriter::new(0, sink), exprs, pic)?;
+    sink.write_all(&vec![0_u8; Header::default().size()])?;
+    // the Position getter should NOT INCLUDE THE HEADER
+    let header = lower(&mut PosWriter::new(0, sink), exprs, pic, enc, src, meta)?;
     sink.seek(std::io::SeekFrom::Start(begin))?;
     sink.write_all(&header)?;
     Ok(())
 }
 
 /// writes a bytecode assembly WITHOUT THE HEADER
-/// after calling this, the HEADER HAS TO BE PREPENDED to the generated bytes
-/// the Position getter should NOT INCLUDE THE 16B HEADER
-pub fn lower<'src: 'l, 'l, W, I>(sink: &mut W, exprs: I, pic: bool) -> Result<[u8;16], LoweringError>
+/// after calling this, the returned HEADER HAS TO BE PREPENDED to the generated bytes
+/// the Position getter should NOT INCLUDE THE HEADER
+/// if [src] is given, a debug info section is emitted
+/// if [meta] is given, a metadata section is emitted
+/// relocatable ops are written with [Op::write_relocatable], so the linker can patch them in place.
+/// their positions are listed in a relocations section, so that the linker does not have to search for them
+pub fn lower<'src: 'l, 'l, W, I>(sink: &mut W, exprs: I, pic: bool, enc: OpEncoding, src: Option<&SrcInfo>, meta: Option<&ModuleInfo>) -> Result<Vec<u8>, LoweringError>
 where W: std::io::Write + Position,
       I: Iterator<Item = &'l Expr<'src>>
 {
     // we keep track of all previously defined bindings.
     // this will keep references to later-defined bindings as Unresolved, which the runtime's linker will resolve
 
-    let mut globals = HashMap::<TokStr, u32>::new();
+    // the strings, code and pool sections are built separately, with offsets relative to their section.
+    // code and pool offsets get moved behind the strings section at the end
+    let mut strings = Vec::<u8>::new();
+    let mut code = Vec::<u8>::new();
+    let mut pool = Pool::default();
+
+    // code offsets of all bindings, relative to the code section. [Op::Const] refers to an index into this until
+    // the code gets relocated at the end, because private bindings can be referenced before they are written
+    let mut bindings = Vec::<u32>::new();
+    // ordered, so that the globals table does not depend on hash iteration order. name -> index into [bindings]
+    let mut globals = BTreeMap::<TokStr, u32>::new();
+    let mut weak_globals = HashSet::<TokStr>::new();
+    // private bindings are always resolved in the file, even forward references and with [pic]
+    let exprs = exprs.collect::<Vec<_>>();
+    // references to weak bindings are always left to the linker, because another file can override them
+    let weak = exprs.iter()
+        .filter(|x| x.weak)
+        .filter_map(|x| x.binding.clone())
+        .collect::<HashSet<_>>();
+    // a second definition would silently change what the earlier references resolve to
+    let mut privates = HashMap::<TokStr, u32>::new();
+    for expr in exprs.iter().filter(|x| x.private) {
+        if let Some(name) = &expr.binding {
+            if privates.contains_key(name) {
+                return Err(SrcError::SymbolDefinedTwice(name.to_string()).at(expr.tok_span.clone()));
+            }
+            bindings.push(0);
+            privates.insert(name.clone(), bindings.len() as u32 - 1);
+        }
+    }
     let mut main_ops = Vec::<Op>::new();
+    let mut main_spans = Vec::<Option<std::ops::Range<usize>>>::new();
     let mut dso_extern = Vec::<u32>::new();
+    let mut debug = src.map(|src| DebugInfo {
+        files: vec!(src.file.to_string()),
+        ..Default::default()
+    });
+
+    let mut relocs = Relocations::default();
+
+    let mut write_op = |out: &mut Vec<u8>, base: usize, op: &Op, span: Option<&std::ops::Range<usize>>| -> std::io::Result<()> {
+        let p = out.len();
+        op.write_relocatable(out, enc)?;
+        if op.is_relocatable() {
+            relocs.positions.push((base + p) as u32);
+        }
+        let loc = src.zip(span).and_then(|(src, span)| src.line_col(span.start));
+        if let (Some(debug), Some((line, col))) = (&mut debug, loc) {
+            debug.add((base + p) as u32, (out.len() - p) as u32, 0, line, col);
+        }
+        Ok(())
+    };
 
-    let resolve = |sink: &mut W, globals: &HashMap<TokStr, u32>, str: &str| -> std::io::Result<Op> {
-        let resv = if pic {
+    let add_string = |strings: &mut Vec<u8>, str: &str| -> u32 {
+        let p = strings.len() as u32;
+        strings.extend_from_slice(str.as_bytes());
+        strings.push(0);
+        p
+    };
+
+    let resolve = |strings: &mut Vec<u8>, globals: &BTreeMap<TokStr, u32>, str: &str| -> Op {
+        let resv = if let Some(id) = privates.get(str) {
+            Some(id)
+        } else if pic || weak.contains(str) {
             None
         } else {
             globals.get(str)
         };
         match resv {
-            Some(pos) => Ok(Op::Const { idx: *pos }),
-
-            None => {
-                let p = sink.pos() as u32;
-                sink.write_all(str.as_bytes())
-                    .and_then(|_| sink.write_all(&[0_u8]))?;
-                Ok(Op::Unresolved { id: p })
-            }
+            Some(id) => Op::Const { idx: *id },
+            None => Op::Unresolved { id: add_string(strings, str) },
         }
     };
 
     for expr in exprs {
-        let mut write_ops = expr.val.iter()
+        let write_ops = expr.val.iter()
             .map(|x| {
                 match x {
-                    Op::Frontend(FrontendOp::Unresolved(id)) => resolve(sink, &globals, id.as_str()),
-                    x => Ok(x.clone())
+                    Op::Frontend(FrontendOp::Unresolved(id)) => resolve(&mut strings, &globals, id.as_str()),
+                    x => x.clone()
                 }
-            }).collect::<std::io::Result<Vec<_>>>()?;
+            }).collect::<Vec<_>>();
+        let (mut write_ops, mut spans) = pack_arrays(&mut pool, enc, write_ops, &expr.spans);
 
         match &expr.binding {
             Some(name) => {
-                let p = sink.pos() as u32;
-
                 if expr.dso_extern {
-                    sink.write_all(name.as_bytes())
-                        .and_then(|_| sink.write_all(&[0_u8]))
-                        ?;
-                    dso_extern.push(p);
+                    dso_extern.push(add_string(&mut strings, name));
                 } else {
-                    for op in write_ops {
-                        op.write(sink)?;
+                    let p = code.len() as u32;
+                    for (i, op) in write_ops.iter().enumerate() {
+                        write_op(&mut code, 0, op, spans[i].as_ref())?;
                     }
-                    Op::Terminate.write(sink)?;
+                    Op::Terminate.write(&mut code)?;
 
-                    globals.insert(name.clone(), p);
+                    if expr.private {
+                        bindings[privates[name] as usize] = p;
+                    } else {
+                        globals.insert(name.clone(), bindings.len() as u32);
+                        bindings.push(p);
+                        if expr.weak {
+                            weak_globals.insert(name.clone());
+                        } else {
+                            weak_globals.remove(name);
+                        }
+                    }
                 }
             }
 
             None => {
+                main_spans.append(&mut spans);
                 main_ops.append(&mut write_ops);
             }
         }
     }
 
-    let globals_tab_num = globals.len();
-
     let globals = globals
         .into_iter()
-        .map(|(k,v)| {
-            let p = sink.pos();
-            sink.write_all(k.as_bytes())
-                .and_then(|_| sink.write_all(&[0_u8]))
-                .map(|_| {
-                    let mut o = [0_u8; 8];
-                    o[0..4].copy_from_slice((p as u32).to_le_bytes().as_slice());
-                    o[4..8].copy_from_slice(&v.to_le_bytes().as_slice());
-                    o
-                })
-        })
-        .process_results(|x|
-            x.flatten().collect::<Vec<_>>())?;
-
-    let globals_tab_off = sink.pos();
-    sink.write_all(&globals)?;
-
-    for op in main_ops {
-        op.write(sink)?;
-    }
-    Op::Terminate.write(sink)?;
-
-    let ex_header_off = if dso_extern.len() > 0 {
-        let b = (sink.pos() as u32) + 16;
-        ExtendedHeader {
-            num_dso: dso_extern.len() as u32,
-            ..Default::default()
-        }.write(sink)?;
-        for dso in dso_extern.into_iter() {
-            sink.write_all(&dso.to_le_bytes())?;
-        }
+        .map(|(k,v)| (add_string(&mut strings, &k), bindings[v as usize], weak_globals.contains(&k)))
+        .collect::<Vec<_>>();
 
-        b
-    } else {
-        0
+    // now that the size of the strings section is known, move the code and the pool behind it
+    let code_begin = strings.len() as u32;
+    let pool_begin = code_begin + code.len() as u32;
+    let code_reloc = |op: Op| match op {
+        Op::Const { idx } => Op::Const { idx: bindings[idx as usize] + code_begin },
+        Op::ArrAt { ty, idx } => Op::ArrAt { ty, idx: idx + pool_begin },
+        op => op,
     };
+    patch_ops(&mut code, enc, code_reloc)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+    let mut data = strings;
+    data.extend_from_slice(&code);
+    data.extend_from_slice(&pool.data);
+
+    let globals_tab_off = data.len();
+    for (name, const_id, weak) in globals.iter() {
+        Export { name: *name, const_id: const_id + code_begin, weak: *weak }.write(&mut data)?;
+    }
+
+    let main_begin = data.len();
+    for (op, span) in main_ops.into_iter().zip(main_spans.iter()) {
+        write_op(&mut data, 0, &code_reloc(op), span.as_ref())?;
+    }
+    Op::Terminate.write(&mut data)?;
 
-    let header = Header {
-        globals_tab_num: globals_tab_num as u16,
+    // debug and relocation positions of the code section are still relative to it
+    let pos_reloc = |pos: u32| if (pos as usize) < main_begin { pos + code_begin } else { pos };
+    if let Some(debug) = &mut debug {
+        debug.relocate(pos_reloc);
+    }
+    relocs.relocate(|pos| Some(pos_reloc(pos)));
+
+    let mut ex = Extensions {
+        dso: dso_extern,
+        debug_info: debug,
+        ..Default::default()
+    };
+    if let Some(meta) = meta {
+        ex.add_section(METADATA_TAG, Metadata { modules: vec!(meta.clone()) }.serialize());
+    }
+    ex.add_section(RELOCATIONS_TAG, relocs.serialize());
+    let ex_header_off = ex.write(Header::default().size() + data.len(), &mut data);
+
+    sink.write_all(&data)?;
+
+    let mut header = Header {
+        globals_tab_num: globals.len() as u32,
         globals_tab_off: globals_tab_off as u32,
         _extended_header_off: ex_header_off,
+        strings_len: code_begin,
+        code_len: code.len() as u32,
         ..Default::default()
     };
+    header.set_op_encoding(enc);
+    if !weak_globals.is_empty() {
+        header.set_weak_globals();
+    }
     Ok(header.serialize())
 }
@@ -8,13 +8,20 @@ use crate::lex::{Tok, TokStr};
 use h6_bytecode::{Num, Op};
 
 pub type SomeOps = SmallVec<Op, 8>;
+pub type SomeSpans = SmallVec<Range<usize>, 8>;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Expr<'src> {
     pub tok_span: Range<usize>,
     pub binding: Option<TokStr<'src>>,
     pub val: SomeOps,
+    /// token span of every op in [Expr::val]. can be empty if unknown
+    pub spans: SomeSpans,
     pub dso_extern: bool,
+    /// the binding is only visible in its own file, and not exported
+    pub private: bool,
+    /// the binding is only used if no other file defines it
+    pub weak: bool,
 }
 
 impl<'src> Default for Expr<'src> {
@@ -23,31 +30,39 @@ impl<'src> Default for Expr<'src> {
             tok_span: 0..0,
             binding: None,
             val: smallvec!(),
+            spans: SomeSpans::new(),
             dso_extern: false,
+            private: false,
+            weak: false,
         }
     }
 }
 
-struct ArrayCollector(SomeOps);
+struct ArrayCollector(SomeOps, SomeSpans);
 
 impl Default for ArrayCollector {
     fn default() -> Self {
-        ArrayCollector(smallvec!(Op::ArrBegin))
+        ArrayCollector(smallvec!(Op::ArrBegin), SomeSpans::new())
     }
 }
 
-impl Container<SomeOps> for ArrayCollector {
-    fn push(&mut self, item: SomeOps) {
+impl<'src> Container<Expr<'src>> for ArrayCollector {
+    fn push(&mut self, item: Expr<'src>) {
         let mut item = item;
-        self.0.append(&mut item);
+        self.0.append(&mut item.val);
+        self.1.append(&mut item.spans);
     }
 }
 
 impl ArrayCollector {
-    fn finish(self) -> SomeOps {
-        let mut out = self.0;
-        out.push(Op::ArrEnd);
-        out
+    /// [span] is used for the ArrBegin and ArrEnd ops
+    fn finish(self, span: Range<usize>) -> (SomeOps, SomeSpans) {
+        let (mut ops, inner) = (self.0, self.1);
+        ops.push(Op::ArrEnd);
+        let mut spans = SomeSpans::from([span.clone()]);
+        spans.extend(inner);
+        spans.push(span);
+        (ops, spans)
     }
 }
 
@@ -64,9 +79,26 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
                 tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
                 binding: Some(name),
                 val: expr.val,
+                spans: expr.spans,
                 ..Default::default()
             });
 
+        let private = just(Tok::Private)
+            .ignore_then(bind.clone())
+            .map_with(|expr: Expr, ctx| Expr {
+                tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
+                private: true,
+                ..expr
+            });
+
+        let weak = just(Tok::Weak)
+            .ignore_then(bind.clone())
+            .map_with(|expr: Expr, ctx| Expr {
+                tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
+                weak: true,
+                ..expr
+            });
+
         let dso_extern = just(Tok::DsoExtern)
             .ignore_then(select! { Tok::Ident(str) => str })
             .map_with(|name: TokStr, ctx| Expr {
@@ -105,20 +137,24 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
         ]).map_with(|op, ctx| Expr {
             tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
             val: smallvec!(op),
+            spans: SomeSpans::from([SimpleSpan::<usize>::into_range(ctx.span())]),
             ..Default::default()
         });
 
         let arr = just(Tok::CurlyOpen)
             .ignore_then(expr.clone()
-                .map(|x| x.val)
                 .repeated()
-                .collect::<ArrayCollector>()
-                .map(|x| x.finish()))
+                .collect::<ArrayCollector>())
             .then_ignore(just(Tok::CurlyClose))
-            .map_with(|ops, ctx| Expr {
-                tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
-                val: ops,
-                ..Default::default()
+            .map_with(|coll, ctx| {
+                let tok_span = SimpleSpan::<usize>::into_range(ctx.span());
+                let (val, spans) = coll.finish(tok_span.clone());
+                Expr {
+                    tok_span,
+                    val,
+                    spans,
+                    ..Default::default()
+                }
             });
 
         let ident = select! { Tok::Ident(str) => str }
@@ -127,6 +163,7 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
                 val: smallvec!(Op::Frontend(h6_bytecode::FrontendOp::Unresolved(
                     str.to_string()
                 ))),
+                spans: SomeSpans::from([SimpleSpan::<usize>::into_range(ctx.span())]),
                 ..Default::default()
             });
 
@@ -134,6 +171,7 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
             .map_with(|val, ctx| Expr {
                 tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
                 val: smallvec!(Op::Push { val }),
+                spans: SomeSpans::from([SimpleSpan::<usize>::into_range(ctx.span())]),
                 ..Default::default()
             });
 
@@ -144,8 +182,10 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
                     .map(|x| Op::Push { val: Num::from(*x) }));
                 val.push(Op::ArrEnd);
 
+                let tok_span = SimpleSpan::<usize>::into_range(ctx.span());
                 Expr {
-                    tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
+                    spans: val.iter().map(|_| tok_span.clone()).collect(),
+                    tok_span,
                     val,
                     ..Default::default()
                 }
@@ -155,6 +195,7 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
             .map_with(|val, ctx| Expr {
                 tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
                 val: smallvec!(Op::Push { val: (val as i16).into() }),
+                spans: SomeSpans::from([SimpleSpan::<usize>::into_range(ctx.span())]),
                 ..Default::default()
             });
 
@@ -169,6 +210,7 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
                 Expr {
                     tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
                     val: smallvec!(Op::System { id: val.unwrap() as u32 }),
+                    spans: SomeSpans::from([SimpleSpan::<usize>::into_range(ctx.span())]),
                     ..Default::default()
                 }
             });
@@ -184,8 +226,10 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
                     }
                 }
 
+                let tok_span = SimpleSpan::<usize>::into_range(ctx.span());
                 Expr {
-                    tok_span: SimpleSpan::<usize>::into_range(ctx.span()),
+                    spans: ops.iter().map(|_| tok_span.clone()).collect(),
+                    tok_span,
                     val: ops,
                     ..Default::default()
                 }
@@ -197,13 +241,14 @@ pub fn parser<'src, I: Iterator<Item = Tok<'src>> + 'src>() ->
             .map_with(|_, ctx| {
                 let tok_span = SimpleSpan::<usize>::into_range(ctx.span());
                 Expr {
+                    spans: SomeSpans::from([tok_span.clone()]),
                     tok_span,
                     val: smallvec!(Op::Materialize),
                     ..Default::default()
                 }
             });
 
-        choice((dso_extern, collect, planet, syscall, bind, op, arr, ident, num, str, char))
+        choice((private, weak, dso_extern, collect, planet, syscall, bind, op, arr, ident, num, str, char))
             .padded_by(select! { Tok::Comment(_) => () }.repeated())
             .boxed()
     });
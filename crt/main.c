@@ -46,6 +46,7 @@ int main(int argc, char** argv)
 {
     char const* arg_inp_file = NULL;
     char const* dso_file = NULL;
+    h6_arith_t arith = H6_ARITH_TRAPPING;
 
     ++ argv;
     for (; *argv; ++argv)
@@ -54,10 +55,19 @@ int main(int argc, char** argv)
             ++argv;
             dso_file = *argv;
         }
+        else if (!strcmp(*argv, "--arith")) {
+            ++argv;
+            assert(*argv);
+            if (!strcmp(*argv, "trapping")) arith = H6_ARITH_TRAPPING;
+            else if (!strcmp(*argv, "wrapping")) arith = H6_ARITH_WRAPPING;
+            else if (!strcmp(*argv, "saturating")) arith = H6_ARITH_SATURATING;
+            else assert(0 && "unknown arithmetic mode");
+        }
         else if (!strcmp(*argv, "--help")) {
             printf("h6crt [input h6b file]\n");
             printf(" options:\n");
             printf("   --dso [path] \tload dso bytecode\n");
+            printf("   --arith [trapping|wrapping|saturating] \toverflow behaviour of arithmetic ops\n");
             printf("   --help\n");
             return 0;
         }
@@ -70,6 +80,7 @@ int main(int argc, char** argv)
     char* bytecode = read_bytecode(arg_inp_file);
 
     h6_rt_t rt = h6_mk_rt(bytecode, syscallback, NULL);
+    rt.arith = arith;
 
     if (dso_file) {
         char* by = read_bytecode(dso_file);
@@ -176,8 +176,20 @@ op heap_arr_popfront(heap_arr* arr) {
     return v;
 }
 
+/* unsigned LEB128, as used by the compact op encoding */
+static uint32_t read_var(char** pp) {
+    uint32_t v = 0;
+    for (int shift = 0; shift < 35; shift += 7) {
+        uint8_t by = *(uint8_t*) (*pp)++;
+        v |= (uint32_t) (by & 0x7f) << shift;
+        if (!(by & 0x80))
+            break;
+    }
+    return v;
+}
+
 static
-heap_arr* read_const(char* opp) {
+heap_arr* read_const(char* opp, int compact) {
     heap_arr* out = h6_heap_arr_mk();
 
     for (;;) {
@@ -187,8 +199,17 @@ heap_arr* read_const(char* opp) {
         opp ++;
         found.kind = kind;
         if ( op_has_arg(kind) ) {
-            memcpy(&found.arg, opp, 4);
-            opp += 4;
+            if (compact) {
+                uint32_t v = read_var(&opp);
+                /* pushed numbers are zigzag encoded */
+                if (kind == Push)
+                    found.arg.num = (int32_t) ((v >> 1) ^ -(v & 1));
+                else
+                    found.arg.uint = v;
+            } else {
+                memcpy(&found.arg, opp, 4);
+                opp += 4;
+            }
         }
 
         if (kind == Terminate)
@@ -253,8 +274,84 @@ static int32_t as_int(op o) {
     return o.arg.num;
 }
 
+/* the same results as Arithmetic::apply of the Rust runtime */
+static int32_t arith(h6_arith_t mode, enum op_kind kind, int32_t a, int32_t b) {
+    int32_t res = 0;
+    int overflow = 0;
+    /* direction of the overflow, if there is one */
+    int positive = 0;
+    switch (kind) {
+        case Add:
+            overflow = __builtin_add_overflow(a, b, &res);
+            positive = b > 0;
+            break;
+        case Sub:
+            overflow = __builtin_sub_overflow(a, b, &res);
+            positive = b < 0;
+            break;
+        case Mul:
+            overflow = __builtin_mul_overflow(a, b, &res);
+            positive = (a < 0) == (b < 0);
+            break;
+        case Div: case Mod:
+            if (b == 0) {
+                fprintf(stderr, "runtime error: division by zero\n");
+                exit(1);
+            }
+            /* the only overflow is INT32_MIN / -1. INT32_MIN % -1 is 0 when not trapping */
+            if (a == INT32_MIN && b == -1) {
+                overflow = 1;
+                positive = kind == Div;
+                res = kind == Div ? INT32_MIN : 0;
+            } else {
+                res = kind == Div ? a / b : a % b;
+            }
+            break;
+        default: break;
+    }
+
+    if (!overflow) return res;
+    switch (mode) {
+        case H6_ARITH_WRAPPING:
+            return res;
+        case H6_ARITH_SATURATING:
+            if (kind == Mod) return 0;
+            return positive ? INT32_MAX : INT32_MIN;
+        default:
+            fprintf(stderr, "runtime error: overflow\n");
+            exit(1);
+    }
+}
+
 static void run_arr(h6_rt_t* rt, heap_arr* ops);
 
+/* V4 has a bigger header, and the data table is split into sections */
+static int bc_has_sections(char* bc) {
+    return (uint8_t) bc[5] >= 4;
+}
+
+/* absolute offset of the data table */
+static size_t bc_data_begin(char* bc) {
+    return bc_has_sections(bc) ? 32 : 16;
+}
+
+static size_t bc_gtab_nent(char* bc) {
+    return bc_has_sections(bc) ? *(uint32_t*) &bc[8] : *(uint16_t*) &bc[6];
+}
+
+static size_t bc_gtab_off(char* bc) {
+    return *(uint32_t*) &bc[bc_has_sections(bc) ? 12 : 8];
+}
+
+static size_t bc_ex_header_off(char* bc) {
+    return *(uint32_t*) &bc[bc_has_sections(bc) ? 16 : 12];
+}
+
+/* the header flags are only present since V4 */
+static int bc_compact(char* bc) {
+    return bc_has_sections(bc) && (*(uint16_t*) &bc[6] & 1);
+}
+
 static void run_op(h6_rt_t* rt, op o) {
     if (o.kind == ArrBegin) {
         if (rt->ind == 0) {
@@ -292,29 +389,36 @@ static void run_op(h6_rt_t* rt, op o) {
             break;
 
         case Const: {
-            heap_arr* arr = read_const(&rt->bytecode[16 + o.arg.uint]);
+            heap_arr* arr = read_const(&rt->bytecode[bc_data_begin(rt->bytecode) + o.arg.uint], bc_compact(rt->bytecode));
             run_arr(rt, arr);
             h6_heap_arr_destr(arr);
         } break;
 
         case U8ArrAt:
         case I16ArrAt: {
-            char* bptr = &rt->bytecode[16 + o.arg.uint];
-            uint16_t len = *(uint16_t*)bptr;
-            char* arrp = bptr + 2;
+            char* bptr = &rt->bytecode[bc_data_begin(rt->bytecode) + o.arg.uint];
+            uint32_t len;
+            char* arrp;
+            if (bc_has_sections(rt->bytecode)) {
+                len = *(uint32_t*)bptr;
+                arrp = bptr + 4;
+            } else {
+                len = *(uint16_t*)bptr;
+                arrp = bptr + 2;
+            }
             heap_arr* out = h6_heap_arr_mk_opt_u8();
 
             switch (o.kind) {
                 case U8ArrAt: {
-                    for (uint16_t i = 0; i < len; i ++) {
+                    for (uint32_t i = 0; i < len; i ++) {
                         int32_t v = ((uint8_t*)arrp)[i];
                         h6_heap_arr_push_num(out, v);
                     }
                 } break;
 
                 case I16ArrAt: {
-                    for (uint16_t i = 0; i < len; i ++) {
-                        int32_t v = ((uint16_t*)arrp)[i];
+                    for (uint32_t i = 0; i < len; i ++) {
+                        int32_t v = ((int16_t*)arrp)[i];
                         h6_heap_arr_push_num(out, v);
                     }
                 } break;
@@ -329,7 +433,7 @@ static void run_op(h6_rt_t* rt, op o) {
         case ConstDso: {
             assert(o.arg.uint < rt->resolved_dso_len);
             char* ptr = &rt->dso_by[rt->resolved_dso_abs_off[o.arg.uint]];
-            heap_arr* arr = read_const(ptr);
+            heap_arr* arr = read_const(ptr, bc_compact(rt->dso_by));
             run_arr(rt, arr);
             h6_heap_arr_destr(arr);
         } break;
@@ -348,11 +452,8 @@ static void run_op(h6_rt_t* rt, op o) {
 
             int32_t res;
             switch (o.kind) {
-                case Add: res = a + b; break;
-                case Sub: res = a - b; break;
-                case Mul: res = a * b; break;
-                case Div: res = a / b; break;
-                case Mod: res = ((int)a) % (int)b; break;
+                case Add: case Sub: case Mul: case Div: case Mod:
+                    res = arith(rt->arith, o.kind, a, b); break;
                 case Lt: res = a < b; break;
                 case Gt: res = a > b; break;
                 case Eq: res = a == b; break;
@@ -532,11 +633,11 @@ static void run_arr(h6_rt_t* rt, heap_arr* ops) {
 }
 
 void h6_run_bytecode(h6_rt_t* rt, char* bytecode) {
-    size_t gtab_nent = *(uint16_t*) &bytecode[6];
-    size_t gtab_off = *(uint32_t*) &bytecode[8];
-    size_t main_off = gtab_off + gtab_nent * 8 + 16;
+    size_t gtab_nent = bc_gtab_nent(bytecode);
+    size_t gtab_off = bc_gtab_off(bytecode);
+    size_t main_off = gtab_off + gtab_nent * 8 + bc_data_begin(bytecode);
 
-    heap_arr* main_ops = read_const(&bytecode[main_off]);
+    heap_arr* main_ops = read_const(&bytecode[main_off], bc_compact(bytecode));
     run_arr(rt, main_ops);
 }
 
@@ -547,6 +648,7 @@ h6_rt_t h6_mk_rt(char* bytecode, h6_rt_syscallback_t opt_syscallback, void* opt_
     rt.bytecode = bytecode;
     rt.syscall = opt_syscallback;
     rt.syscall_userptr = opt_syscallback_userptr;
+    rt.arith = H6_ARITH_TRAPPING;
     rt.dso_by = NULL;
     rt.resolved_dso_len = 0;
     rt.resolved_dso_abs_off = NULL;
@@ -592,7 +694,7 @@ void h6_set_dso(h6_rt_t* rt, char* /** MOVED */ dso_bytecode) {
     assert(!rt->dso_by);
     rt->dso_by = dso_bytecode;
 
-    size_t ex_header_off = *(uint32_t*) &rt->bytecode[12];
+    size_t ex_header_off = bc_ex_header_off(rt->bytecode);
     if (!ex_header_off)
         return;
 
@@ -611,17 +713,19 @@ void h6_set_dso(h6_rt_t* rt, char* /** MOVED */ dso_bytecode) {
         uint32_t value;
     } __attribute__((packed));
 
-    uint16_t dso_globals_nent = *(uint16_t*) &dso_bytecode[6];
-    struct global_kv* globals = (struct global_kv*) &dso_bytecode[16 + *(uint32_t*) &dso_bytecode[8]];
+    size_t dso_data_begin = bc_data_begin(dso_bytecode);
+    size_t dso_globals_nent = bc_gtab_nent(dso_bytecode);
+    struct global_kv* globals = (struct global_kv*) &dso_bytecode[dso_data_begin + bc_gtab_off(dso_bytecode)];
 
     for (size_t i = 0; i < num_dso_ent; i ++) {
-        char* name = &rt->bytecode[16 + dso_tab[i]];
+        char* name = &rt->bytecode[bc_data_begin(rt->bytecode) + dso_tab[i]];
 
         int found = 0;
-        for (uint16_t g = 0; g < dso_globals_nent; g ++) {
-            char* gname = &dso_bytecode[16 + globals[g].name];
+        for (size_t g = 0; g < dso_globals_nent; g ++) {
+            /* the highest bit marks weak globals */
+            char* gname = &dso_bytecode[dso_data_begin + (globals[g].name & 0x7fffffff)];
             if (!strcmp(name, gname)) {
-                rt->resolved_dso_abs_off[i] = 16 + globals[g].value;
+                rt->resolved_dso_abs_off[i] = dso_data_begin + globals[g].value;
                 found = 1;
                 break;
             }
@@ -28,6 +28,15 @@ h6_heap_arr* h6_heap_arr_get_arr(h6_heap_arr* arr, size_t idx);
 
 h6_op* h6_heap_arr_get_op(h6_heap_arr* arr, size_t idx);
 
+/** what Add, Sub, Mul, Div and Mod do if the result does not fit into 32 bits, like the Rust runtime.
+ *  division by zero always prints an error and exits */
+typedef enum {
+    /** print an error and exit */
+    H6_ARITH_TRAPPING = 0,
+    H6_ARITH_WRAPPING,
+    H6_ARITH_SATURATING,
+} h6_arith_t;
+
 typedef struct h6_rt_t h6_rt_t;
 typedef void (*h6_rt_syscallback_t)(h6_rt_t* rt, uint32_t id, void* userptr);
 struct h6_rt_t {
@@ -37,6 +46,9 @@ struct h6_rt_t {
     h6_rt_syscallback_t syscall;
     void* syscall_userptr;
 
+    /** H6_ARITH_TRAPPING by default */
+    h6_arith_t arith;
+
 /*private:*/
     size_t ind;
     h6_heap_arr* building_arr;
@@ -3,6 +3,7 @@
 use h6_bytecode::{Num, Op, Bytecode, ByteCodeError, OpsIter};
 use nostd::collections::{HashMap, VecDeque};
 use nostd::prelude::*;
+use nostd::sync::{Arc, atomic::{AtomicBool, Ordering}};
 
 #[cfg(feature = "smallvec")]
 pub type SmallVec<T, const N: usize> = smallvec::SmallVec<T,N>;
@@ -10,13 +11,70 @@ pub type SmallVec<T, const N: usize> = smallvec::SmallVec<T,N>;
 #[cfg(not(feature = "smallvec"))]
 pub type SmallVec<T, const N: usize> = Vec<T>;
 
-pub type ArrTy = SmallVec<Op, 4>;
+/// array value.
+/// remembers the byte position of its first op in the bytecode, as long as it is not modified
+#[derive(Debug, Clone, Default)]
+pub struct ArrTy {
+    ops: SmallVec<Op, 4>,
+    origin: Option<usize>,
+}
+
+impl ArrTy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// absolute byte position of the first op, if this array is an unmodified copy of bytecode
+    pub fn origin(&self) -> Option<usize> {
+        self.origin
+    }
+}
+
+impl nostd::ops::Deref for ArrTy {
+    type Target = SmallVec<Op, 4>;
 
-/// these won't ever leak into arrays
+    fn deref(&self) -> &Self::Target {
+        &self.ops
+    }
+}
+
+impl nostd::ops::DerefMut for ArrTy {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.origin = None;
+        &mut self.ops
+    }
+}
+
+impl PartialEq for ArrTy {
+    fn eq(&self, other: &Self) -> bool {
+        self.ops == other.ops
+    }
+}
+
+impl FromIterator<Op> for ArrTy {
+    fn from_iter<T: IntoIterator<Item = Op>>(iter: T) -> Self {
+        Self { ops: iter.into_iter().collect(), origin: None }
+    }
+}
+
+impl IntoIterator for ArrTy {
+    type Item = Op;
+    type IntoIter = <SmallVec<Op, 4> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
+
+/// only the dso ops leak into arrays, when code of a shared object contains arrays
 #[derive(Debug)]
 enum SpecialOp {
     Push(Value),
     Collect(usize),
+    /// [Op::Const] in the code of the shared object with the index [dso]
+    DsoConst { dso: usize, idx: u32 },
+    /// [Op::ConstAt] in the code of the shared object with the index [dso]
+    DsoConstAt { dso: usize },
 }
 
 impl h6_bytecode::RuntimeOp for SpecialOp {
@@ -24,6 +82,8 @@ impl h6_bytecode::RuntimeOp for SpecialOp {
         match self {
             SpecialOp::Push(_) => 0,
             SpecialOp::Collect(_) => 1,
+            SpecialOp::DsoConst { .. } => 2,
+            SpecialOp::DsoConstAt { .. } => 3,
         }
     }
 
@@ -120,6 +180,19 @@ pub enum RuntimeErrType {
     SystemFnNotFound(u32),
     SystemFnErr(String),
     CapturedTooMuch,
+    /// no loaded shared object exports the dso import with this name
+    DsoNotFound(String),
+    DsoIdOutOfBounds(u32),
+    /// shared objects can not import from other shared objects
+    DsoConstInDso,
+    /// see [Runtime::set_limits]
+    LimitExceeded(Limit),
+    /// see [CancelHandle]
+    Cancelled,
+    /// [Op::Div] or [Op::Mod] by zero, in every [Arithmetic]
+    DivByZero,
+    /// the result of an arithmetic op does not fit into a [Num], with [Arithmetic::Trapping]
+    Overflow,
 }
 
 #[derive(Debug, Clone)]
@@ -165,9 +238,39 @@ impl<V, E: nostd::fmt::Debug> InSystemFn<V> for Result<V,E> {
     }
 }
 
+/// number of ops that a stack item holds, see [Limits::arr_ops]
+pub trait Weight {
+    fn weight(&self) -> usize;
+}
+
+/// arrays in arrays are usually flat ops between [Op::ArrBegin] and [Op::ArrEnd], but arrays in the code of a
+/// shared object are held by a [SpecialOp::Push]
+impl Weight for Value {
+    fn weight(&self) -> usize {
+        match self {
+            Value::Num(_) => 0,
+            Value::Arr(a) => a.iter().map(|op| 1 + op.weight()).sum(),
+        }
+    }
+}
+
+impl Weight for Op {
+    fn weight(&self) -> usize {
+        match self {
+            Op::Runtime(rt) => match rt.0.as_ref().as_any().downcast_ref::<SpecialOp>() {
+                Some(SpecialOp::Push(v)) => v.weight(),
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Stack<T> {
     backing: Vec<T>,
+    /// sum of the [Weight] of all items
+    weight: usize,
 }
 
 impl<T> Into<Vec<T>> for Stack<T> {
@@ -176,7 +279,7 @@ impl<T> Into<Vec<T>> for Stack<T> {
     }
 }
 
-impl<Ty> Extend<Ty> for Stack<Ty> {
+impl<Ty: Weight> Extend<Ty> for Stack<Ty> {
     fn extend<T: IntoIterator<Item = Ty>>(&mut self, iter: T) {
         for item in iter {
             self.push(item);
@@ -184,14 +287,16 @@ impl<Ty> Extend<Ty> for Stack<Ty> {
     }
 }
 
-impl<T> Stack<T> {
+impl<T: Weight> Stack<T> {
     pub fn new() -> Self {
         Self {
             backing: Vec::new(),
+            weight: 0,
         }
     }
 
     pub fn push(&mut self, val: T) {
+        self.weight += val.weight();
         self.backing.push(val);
     }
 
@@ -199,12 +304,14 @@ impl<T> Stack<T> {
         match self.backing.pop() {
             None => None,
             Some(v) => {
+                self.weight -= v.weight();
                 Some(v)
             }
         }
     }
 
     pub fn drain_after(&mut self, after: usize) -> impl Iterator<Item = T> {
+        self.weight -= self.backing[after..].iter().map(|x| x.weight()).sum::<usize>();
         self.backing.drain(after..)
     }
 
@@ -212,6 +319,11 @@ impl<T> Stack<T> {
         self.backing.len()
     }
 
+    /// sum of the [Weight] of all items
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
     pub fn reach(&self, down: usize) -> Option<&T> {
         self.backing.len()
             .checked_sub(1)
@@ -220,27 +332,231 @@ impl<T> Stack<T> {
     }
 }
 
+/// queue of the ops that run next, see [Runtime::todo]
+#[derive(Debug)]
+pub struct Queue<T> {
+    backing: VecDeque<T>,
+    /// sum of the [Weight] of all items
+    weight: usize,
+}
+
+impl<T: Weight> Queue<T> {
+    pub fn new() -> Self {
+        Self {
+            backing: VecDeque::new(),
+            weight: 0,
+        }
+    }
+
+    pub fn push_front(&mut self, val: T) {
+        self.weight += val.weight();
+        self.backing.push_front(val);
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        let v = self.backing.pop_front()?;
+        self.weight -= v.weight();
+        Some(v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.backing.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.backing.iter()
+    }
+
+    /// sum of the [Weight] of all items
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl<T: Weight> Weight for (usize, T) {
+    fn weight(&self) -> usize {
+        self.1.weight()
+    }
+}
+
+/// what [Op::Add], [Op::Sub], [Op::Mul], [Op::Div] and [Op::Mod] do if the result does not fit into a [Num],
+/// see [Runtime::set_arithmetic]. the crt runtime does the same
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Arithmetic {
+    /// fail with [RuntimeErrType::Overflow]
+    #[default]
+    Trapping,
+    /// two's complement wrap around
+    Wrapping,
+    /// clamp to the smallest or largest [Num]
+    Saturating,
+}
+
+impl Arithmetic {
+    /// [op] applied to [a] and [b], where [b] is the top of the stack.
+    /// fails with [RuntimeErrType::OpNotSupportType] if [op] is not an arithmetic op
+    pub fn apply(self, op: &Op, a: Num, b: Num) -> Result<Num, RuntimeErr> {
+        if matches!(op, Op::Div | Op::Mod) && b == 0 {
+            Err(RuntimeErr::from(RuntimeErrType::DivByZero))?;
+        }
+        let (checked, wrapped, saturated) = match op {
+            Op::Add => (a.checked_add(b), a.wrapping_add(b), a.saturating_add(b)),
+            Op::Sub => (a.checked_sub(b), a.wrapping_sub(b), a.saturating_sub(b)),
+            Op::Mul => (a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b)),
+            Op::Div => (a.checked_div(b), a.wrapping_div(b), a.saturating_div(b)),
+            // the only overflow is MIN % -1, where the exact result is 0
+            Op::Mod => (a.checked_rem(b), a.wrapping_rem(b), a.wrapping_rem(b)),
+            _ => Err(RuntimeErr::from(RuntimeErrType::OpNotSupportType))?,
+        };
+        match self {
+            Arithmetic::Trapping => checked.ok_or(RuntimeErr::from(RuntimeErrType::Overflow)),
+            Arithmetic::Wrapping => Ok(wrapped),
+            Arithmetic::Saturating => Ok(saturated),
+        }
+    }
+}
+
+/// limits of a [Runtime], see [Runtime::set_limits]. None is unlimited
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// ops executed by [Runtime::step], including the ops of the main code
+    pub ops: Option<u64>,
+    /// values on the stack
+    pub stack: Option<usize>,
+    /// ops in [Runtime::todo]
+    pub todo: Option<usize>,
+    /// ops in all arrays on the stack and in [Runtime::todo] together, including the ops of nested arrays
+    pub arr_ops: Option<usize>,
+}
+
+/// which of the [Limits] got exceeded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Ops,
+    Stack,
+    Todo,
+    ArrOps,
+}
+
+/// stops a [Runtime] from another thread, for example after a timeout.
+/// the next [Runtime::step] after [CancelHandle::cancel] fails with [RuntimeErrType::Cancelled]
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 pub struct Runtime<'asm> {
     pub bc: Bytecode<'asm>,
+    /// already linked shared objects, see [Runtime::with_dsos]
+    pub dsos: Vec<Bytecode<'asm>>,
+    /// (index into [Runtime::dsos], offset into its data table) for every dso table entry of [Runtime::bc].
+    /// None if no shared object exports it
+    dso_table: Vec<Option<(usize, u32)>>,
     pub stack: Stack<Value>,
-    pub todo: VecDeque<Op>,
+    /// ops paired with their absolute byte position in [Runtime::bc], or 0 if unknown
+    pub todo: Queue<(usize, Op)>,
+    limits: Limits,
+    arithmetic: Arithmetic,
+    /// number of ops executed by [Runtime::step] so far
+    executed: u64,
+    cancel: CancelHandle,
 
     system: HashMap<u32, (usize, Box<dyn Fn(SmallVec<Value,4>) -> Result<SmallVec<Value,4>,RuntimeErr>>)>
 }
 
 impl<'asm, 'sysfp> Runtime<'asm> {
     pub fn new(bc: Bytecode<'asm>) -> Result<Self, RuntimeErr> {
+        Self::new_unchecked(bc, vec!())
+    }
+
+    /// resolves the dso table of [bc] by name against the globals of the already linked shared objects [dsos].
+    /// if multiple shared objects export a name, the first one is used
+    pub fn with_dsos(bc: Bytecode<'asm>, dsos: Vec<Bytecode<'asm>>) -> Result<Self, RuntimeErr> {
+        let o = Self::new_unchecked(bc, dsos)?;
+        if let Some(id) = o.dso_table.iter().position(|x| x.is_none()) {
+            Err(o.dso_not_found(id as u32))?;
+        }
+        Ok(o)
+    }
+
+    fn new_unchecked(bc: Bytecode<'asm>, dsos: Vec<Bytecode<'asm>>) -> Result<Self, RuntimeErr> {
+        let mut dso_table = vec!();
+        for name in bc.dso_names()? {
+            let name = bc.string(name)?;
+            let mut found = None;
+            for (idx, dso) in dsos.iter().enumerate() {
+                for global in dso.named_globals() {
+                    let (global, off) = global?;
+                    if global == name {
+                        found = Some((idx, off));
+                        break;
+                    }
+                }
+                if found.is_some() {
+                    break;
+                }
+            }
+            dso_table.push(found);
+        }
+
         let begin = bc.header.main_ops_area_begin_idx();
         let mut o = Self {
             bc,
+            dsos,
+            dso_table,
             stack: Stack::new(),
-            todo: VecDeque::new(),
+            todo: Queue::new(),
+            limits: Limits::default(),
+            arithmetic: Arithmetic::default(),
+            executed: 0,
+            cancel: CancelHandle::default(),
             system: HashMap::new(),
         };
         o.exec_ops(begin)?;
         Ok(o)
     }
 
+    fn dso_not_found(&self, dso_id: u32) -> RuntimeErr {
+        let name = self.bc.dso_names().ok()
+            .and_then(|x| x.get(dso_id as usize).copied())
+            .and_then(|x| self.bc.string(x).ok());
+        match name {
+            Some(name) => RuntimeErr::from(RuntimeErrType::DsoNotFound(name.to_string())),
+            None => RuntimeErr::from(RuntimeErrType::DsoIdOutOfBounds(dso_id)),
+        }
+    }
+
+    /// the limits are checked by every [Runtime::step], which fails with [RuntimeErrType::LimitExceeded] if one
+    /// is exceeded
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// [Arithmetic::Trapping] by default
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) -> &mut Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// number of ops executed by [Runtime::step] so far
+    pub fn executed_ops(&self) -> u64 {
+        self.executed
+    }
+
+    /// handle that can cancel the execution from another thread
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
     pub fn register(&mut self, name: u32, num_ins: usize, fp: Box<dyn Fn(SmallVec<Value,4>) -> Result<SmallVec<Value,4>,RuntimeErr>>) -> &mut Self {
         self.system.insert(name, (num_ins, fp));
         self
@@ -252,9 +568,9 @@ impl<'asm, 'sysfp> Runtime<'asm> {
         let mut todo = vec!();
         let mut iter = iter;
         while let Some(op) = iter.next() {
-            let (_, op) = op?;
+            let (pos, op) = op?;
             if op == Op::ArrBegin {
-                let mut arr = SmallVec::new();
+                let mut arr = ArrTy::new();
                 let mut ind = 1;
                 while ind > 0 {
                     let op = match iter.next() {
@@ -271,9 +587,11 @@ impl<'asm, 'sysfp> Runtime<'asm> {
                         arr.push(op);
                     }
                 }
-                todo.push(SpecialOp::Push(Value::Arr(arr)).into());
+                // ArrBegin is a single byte
+                arr.origin = (pos != 0 && !arr.is_empty()).then(|| pos + 1);
+                todo.push((pos, SpecialOp::Push(Value::Arr(arr)).into()));
             } else {
-                todo.push(op);
+                todo.push((pos, op));
             }
         }
         for x in todo.into_iter().rev() {
@@ -283,11 +601,69 @@ impl<'asm, 'sysfp> Runtime<'asm> {
     }
 
     fn exec_ops(&mut self, at: usize) -> Result<(), RuntimeErr> {
-        self.exec_iter(OpsIter::new(at, &self.bc.bytes[at..]))
+        let bytes = self.bc.bytes.get(at..).ok_or(ByteCodeError::ElementNotFound)?;
+        self.exec_iter(OpsIter::new(at, bytes, self.bc.header.op_encoding()))
+    }
+
+    /// executes code of the shared object [dso]. the ops that refer to its data table are replaced with [SpecialOp]s,
+    /// so that they still refer to it when they are moved into arrays
+    fn exec_dso_ops(&mut self, dso: usize, idx: u32) -> Result<(), RuntimeErr> {
+        let bc = Bytecode::from_header(self.dsos[dso].bytes, self.dsos[dso].header.clone());
+        let at = idx as usize + bc.header.data_begin();
+        let bytes = bc.bytes.get(at..).ok_or(ByteCodeError::ElementNotFound)?;
+        let ops = OpsIter::new(at, bytes, bc.header.op_encoding())
+            .map(|op| {
+                let op = match op?.1 {
+                    Op::Const { idx } => SpecialOp::DsoConst { dso, idx }.into(),
+                    Op::ConstAt => SpecialOp::DsoConstAt { dso }.into(),
+                    Op::ArrAt { ty, idx } => {
+                        let arr = bc.const_arr(&ty, idx)?
+                            .into_iter()
+                            .map(|val| Op::Push { val })
+                            .collect::<ArrTy>();
+                        SpecialOp::Push(Value::Arr(arr)).into()
+                    }
+                    Op::DsoConst { .. } => Err(RuntimeErr::from(RuntimeErrType::DsoConstInDso))?,
+                    op => op,
+                };
+                // positions are only known in [Runtime::bc]
+                Ok::<(usize, Op), RuntimeErr>((0, op))
+            });
+        self.exec_iter(ops)
+    }
+
+    /// pushes the bytes of the ops at [idx] in the data table of [bc]
+    fn const_at(&mut self, bc: &Bytecode, idx: u32) -> Result<(), RuntimeErr> {
+        let a = bc.const_ops(idx)?;
+        let mut bytes = vec!();
+        for op in a.into_iter() {
+            let op = op?;
+            let _ = op.1.write(&mut bytes);
+        }
+        let o = bytes.into_iter()
+            .map(|x| Op::Push { val: x.into() })
+            .collect::<ArrTy>();
+        self.stack.push(Value::Arr(o));
+        Ok(())
     }
 
     fn exec_arr(&mut self, arr: ArrTy) -> Result<(), RuntimeErr> {
-        self.exec_iter(arr.into_iter().map(|x| Ok::<(usize,Op),RuntimeErr>((0,x))))
+        // the positions are decoded again, because ops in the compact encoding can be larger than [Op::size].
+        // they are unknown after the first op that differs from the bytecode
+        let bytes = self.bc.bytes;
+        let enc = self.bc.header.op_encoding();
+        let mut origin = arr.origin
+            .and_then(|at| bytes.get(at..).map(|b| OpsIter::new(at, b, enc)));
+        self.exec_iter(arr.into_iter().map(|x| {
+            let p = match origin.as_mut().and_then(|o| o.next()) {
+                Some(Ok((p, op))) if op == x => p,
+                _ => {
+                    origin = None;
+                    0
+                }
+            };
+            Ok::<(usize,Op),RuntimeErr>((p,x))
+        }))
     }
 
     fn arr_first_elem_len<I: Iterator<Item = Op>>(arr: I) -> Result<usize, RuntimeErr> {
@@ -315,27 +691,29 @@ impl<'asm, 'sysfp> Runtime<'asm> {
         }
     }
 
-    fn exec_op(&mut self, op: (usize, Op)) -> Result<(), RuntimeErr> {
-        let (byte_pos, op) = op;
+    fn exec_op(&mut self, op: Op) -> Result<(), RuntimeErr> {
 
         macro_rules! pop {
             () => {
-                self.stack.pop().ok_or(RuntimeErr::from(RuntimeErrType::StackUnderflow).at(byte_pos))?
+                self.stack.pop().ok_or(RuntimeErr::from(RuntimeErrType::StackUnderflow))?
             };
         }
 
         macro_rules! num_bin {
             ($do:expr) => { {
-                let a = pop!().as_num().map_err(|x| x.at(byte_pos))?;
-                let b = pop!().as_num().map_err(|x| x.at(byte_pos))?;
+                let a = pop!().as_num()?;
+                let b = pop!().as_num()?;
                 let v = $do(b,a);
                 self.stack.push(v);
             } };
         }
 
         match op {
-            Op::DsoConst { .. } => {
-                panic!("unimplemented");
+            Op::DsoConst { dso_id } => {
+                let (dso, idx) = self.dso_table.get(dso_id as usize)
+                    .ok_or(RuntimeErr::from(RuntimeErrType::DsoIdOutOfBounds(dso_id)))?
+                    .ok_or_else(|| self.dso_not_found(dso_id))?;
+                return self.exec_dso_ops(dso, idx);
             }
 
             Op::Runtime(rt) => {
@@ -357,6 +735,16 @@ impl<'asm, 'sysfp> Runtime<'asm> {
 
                         self.stack.push(Value::Arr(ops));
                     }
+
+                    SpecialOp::DsoConst { dso, idx } => {
+                        return self.exec_dso_ops(*dso, *idx);
+                    }
+
+                    SpecialOp::DsoConstAt { dso } => {
+                        let bc = Bytecode::from_header(self.dsos[*dso].bytes, self.dsos[*dso].header.clone());
+                        let idx = pop!().as_num()? as u32;
+                        self.const_at(&bc, idx)?;
+                    }
                 }
             }
 
@@ -375,68 +763,39 @@ impl<'asm, 'sysfp> Runtime<'asm> {
             }
 
             Op::ConstAt => {
-                let a = self.bc.const_ops(pop!().as_num()? as u32)?;
-                let mut bytes = vec!();
-                for op in a.into_iter() {
-                    let op = op?;
-                    let _ = op.1.write(&mut bytes);
-                }
-                let o = bytes.into_iter()
-                    .map(|x| Op::Push { val: x.into() })
-                    .collect::<ArrTy>();
-                self.stack.push(Value::Arr(o));
+                let bc = Bytecode::from_header(self.bc.bytes, self.bc.header.clone());
+                let idx = pop!().as_num()? as u32;
+                self.const_at(&bc, idx)?;
             }
 
             Op::ArrAt { ty, idx } => {
-                let mut len = [0_u8;2];
-                len.copy_from_slice(&self.bc.data_table()[idx as usize..idx as usize+2]);
-                let len = u16::from_le_bytes(len);
-
-                let elt_size: usize = match ty {
-                    h6_bytecode::PushConstArrType::U8 => 1,
-                    h6_bytecode::PushConstArrType::I16 => 2,
-                };
-
-                let data = &self.bc.data_table()[(2 + idx as usize)..(2 + idx as usize + len as usize * elt_size)];
-
                 let mut arr = ArrTy::new();
-
-                match ty {
-                    h6_bytecode::PushConstArrType::U8 => {
-                        for by in data {
-                            arr.push(Op::Push { val: *by as Num });
-                        }
-                    },
-
-                    h6_bytecode::PushConstArrType::I16 => {
-                        for byb in data.chunks(2) {
-                            let mut by = [0_u8;2];
-                            by.copy_from_slice(byb);
-                            arr.push(Op::Push { val: i16::from_le_bytes(by) as Num });
-                        }
-                    },
+                for val in self.bc.const_arr(&ty, idx)? {
+                    arr.push(Op::Push { val });
                 }
+                self.stack.push(Value::Arr(arr));
             }
 
             Op::Terminate => {},
-            Op::Unresolved { id } => Err(RuntimeErr::from(RuntimeErrType::UnlinkedSym(id)).at(byte_pos))?,
+            Op::Unresolved { id } => Err(RuntimeErr::from(RuntimeErrType::UnlinkedSym(id)))?,
             Op::Const { idx } => {
-                return self.exec_ops(idx as usize + 16);
+                return self.exec_ops(idx as usize + self.bc.header.data_begin());
             }
             
             Op::Push { val } => {
                 self.stack.push(Value::Num(val))
             },
 
-            Op::Add => num_bin!(|a,b| Value::Num(a + b)),
-            Op::Sub => num_bin!(|a,b| Value::Num(a - b)),
-            Op::Mul => num_bin!(|a,b| Value::Num(a * b)),
-            Op::Div => num_bin!(|a,b| Value::Num(a / b)),
-            Op::Mod => num_bin!(|a,b| Value::Num(a % b)),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => {
+                let b = pop!().as_num()?;
+                let a = pop!().as_num()?;
+                let v = self.arithmetic.apply(&op, a, b)?;
+                self.stack.push(Value::Num(v));
+            }
 
             Op::Materialize => {
                 let ops = pop!().as_arr()?;
-                self.todo.push_front(SpecialOp::Collect(self.stack.len()).into());
+                self.todo.push_front((0, SpecialOp::Collect(self.stack.len()).into()));
                 self.exec_arr(ops)?;
             }
 
@@ -526,7 +885,8 @@ impl<'asm, 'sysfp> Runtime<'asm> {
                 if len == 0 {
                     Err(RuntimeErr::from(RuntimeErrType::ArrIdxOutOfBounds))?;
                 }
-                a.truncate(len);
+                // keeps the origin, because the remaining ops do not move
+                a.ops.truncate(len);
                 return self.exec_arr(a);
             }
 
@@ -564,11 +924,35 @@ impl<'asm, 'sysfp> Runtime<'asm> {
         return Ok(());
     }
 
+    fn check_limits(&self) -> Result<(), RuntimeErr> {
+        let exceeded = |limit: Option<usize>, val: usize| limit.is_some_and(|x| val > x);
+        let limit = if self.limits.ops.is_some_and(|x| self.executed > x) {
+            Limit::Ops
+        } else if exceeded(self.limits.stack, self.stack.len()) {
+            Limit::Stack
+        } else if exceeded(self.limits.todo, self.todo.len()) {
+            Limit::Todo
+        } else if exceeded(self.limits.arr_ops, self.stack.weight() + self.todo.weight()) {
+            Limit::ArrOps
+        } else {
+            return Ok(());
+        };
+        Err(RuntimeErr::from(RuntimeErrType::LimitExceeded(limit)))
+    }
+
     /// always executes one instruction at a time
     pub fn step(&mut self) -> Result<Option<()>, RuntimeErr> {
+        if self.cancel.is_cancelled() {
+            Err(RuntimeErr::from(RuntimeErrType::Cancelled))?;
+        }
         match self.todo.pop_front() {
-            Some(op) => {
-                Ok(Some(self.exec_op((0, op))?))
+            Some((pos, op)) => {
+                self.executed += 1;
+                let res = self.exec_op(op).and_then(|_| self.check_limits());
+                Ok(Some(res.map_err(|e| match e.asm_byte_pos {
+                    None if pos != 0 => e.at(pos),
+                    _ => e,
+                })?))
             }
 
             None => {
@@ -1,15 +1,18 @@
-use std::collections::{HashSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Write};
 use clap::{Parser, Subcommand};
 use camino::Utf8PathBuf;
 use std::rc::Rc;
 use std::cell::RefCell;
-use h6_bytecode::{Bytecode, Header, Op, linker};
-use h6_compiler::{lex, parse, lower};
+use h6_bytecode::{Bytecode, Header, OpEncoding, encoding, linker};
+use h6_bytecode::archive::{Archive, Member};
+use h6_compiler::{doc, lex, parse, lower};
 
 #[cfg(feature = "repl")]
 use reedline::{Highlighter, Hinter, Validator};
+#[cfg(feature = "repl")]
+use h6_bytecode::Op;
 
 #[derive(Parser, Debug)]
 #[clap(name = "h6", version)]
@@ -20,6 +23,35 @@ pub struct App {
     command: Command,
 }
 
+fn parse_namespace(arg: &str) -> Result<(String, Utf8PathBuf), String> {
+    match arg.split_once('=') {
+        Some((prefix, file)) if !prefix.is_empty() && !file.is_empty() => Ok((prefix.to_string(), file.into())),
+        _ => Err(format!("expected PREFIX=FILE, got `{}`", arg)),
+    }
+}
+
+fn parse_defsym(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((a, b)) if !a.is_empty() && !b.is_empty() => Ok((a.to_string(), b.to_string())),
+        _ => Err(format!("expected A=B, got `{}`", arg)),
+    }
+}
+
+fn parse_arithmetic(arg: &str) -> Result<h6_runtime::Arithmetic, String> {
+    match arg {
+        "trapping" => Ok(h6_runtime::Arithmetic::Trapping),
+        "wrapping" => Ok(h6_runtime::Arithmetic::Wrapping),
+        "saturating" => Ok(h6_runtime::Arithmetic::Saturating),
+        _ => Err(format!("expected trapping, wrapping or saturating, got `{}`", arg)),
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum MapFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
     /// compile to bytecode file
@@ -28,10 +60,27 @@ enum Command {
         output: Utf8PathBuf,
 
         input: Utf8PathBuf,
+
+        /// emit debug info, so that runtime errors can be reported with their source location
+        #[clap(short = 'g', action)]
+        debug: bool,
+
+        /// use the compact variable-length op encoding. the ops stay relocatable until they are relaxed by `h6 ld`
+        #[clap(long, action)]
+        compact: bool,
+
+        /// module name stored in the metadata section. defaults to the input file name without extension
+        #[clap(long)]
+        module_name: Option<String>,
+
+        /// module version stored in the metadata section
+        #[clap(long)]
+        module_version: Option<String>,
     },
 
     #[clap(alias = "link")]
     Ld {
+        /// bytecode files and archives. members of archives are only linked if they define a symbol that is needed
         inputs: Vec<Utf8PathBuf>,
 
         #[clap(short = 'o')]
@@ -43,10 +92,89 @@ enum Command {
         /// only concatenate assemblies into output. do not perform linking
         #[clap(long, action)]
         cat_only: bool,
+
+        /// write the output in the compact op encoding, and shrink all ops after linking
+        #[clap(long, action)]
+        compact: bool,
+
+        /// remove everything that is not reachable from the main ops or a `--keep` symbol
+        #[clap(long, action)]
+        gc: bool,
+
+        /// global that `--gc` has to keep, together with everything it references
+        #[clap(long = "keep")]
+        keep: Vec<String>,
+
+        /// resolve references to SYM to `__wrap_SYM`, and references to `__real_SYM` to SYM
+        #[clap(long, value_name = "SYM")]
+        wrap: Vec<String>,
+
+        /// resolve references to A to B
+        #[clap(long, value_name = "A=B", value_parser = parse_defsym)]
+        defsym: Vec<(String, String)>,
+
+        /// linked library that has to export the dso imports. can be passed multiple times
+        #[clap(long = "dso", value_name = "PATH")]
+        dso: Vec<Utf8PathBuf>,
+
+        /// write a map with the offset, size, origin and references of every symbol
+        #[clap(long)]
+        map: Option<Utf8PathBuf>,
+
+        #[clap(long, value_enum, default_value_t = MapFormat::Text)]
+        map_format: MapFormat,
+
+        /// link FILE before the other inputs, with its globals renamed to `PREFIX::name`
+        #[clap(long = "namespace", value_name = "PREFIX=FILE", value_parser = parse_namespace)]
+        namespaces: Vec<(String, Utf8PathBuf)>,
+
+        /// global that is executed after the top level code of all modules
+        #[clap(long, value_name = "NAME")]
+        entry: Option<String>,
+
+        /// do not run the top level code of MODULE, as shown by `h6 nm`. can be passed multiple times
+        #[clap(long = "no-init", value_name = "MODULE")]
+        no_init: Vec<String>,
+    },
+
+    /// create a static archive (.h6a) with an index of the globals of its members
+    Ar {
+        #[clap(short = 'o')]
+        output: Utf8PathBuf,
+
+        inputs: Vec<Utf8PathBuf>,
     },
 
     Run {
         input: Utf8PathBuf,
+
+        /// linked shared object to import `dso_extern` names from. can be passed multiple times
+        #[clap(long = "dso", value_name = "PATH")]
+        dso: Vec<Utf8PathBuf>,
+
+        /// stop after executing N ops
+        #[clap(long, value_name = "N")]
+        max_ops: Option<u64>,
+
+        /// stop if there are more than N values on the stack
+        #[clap(long, value_name = "N")]
+        max_stack: Option<usize>,
+
+        /// stop if more than N ops are waiting to be executed
+        #[clap(long, value_name = "N")]
+        max_todo: Option<usize>,
+
+        /// stop if the arrays on the stack and waiting to be executed contain more than N ops together
+        #[clap(long, value_name = "N")]
+        max_arr_ops: Option<usize>,
+
+        /// stop after MS milliseconds
+        #[clap(long, value_name = "MS")]
+        timeout: Option<u64>,
+
+        /// what arithmetic ops do on overflow: trapping, wrapping or saturating
+        #[clap(long, value_name = "MODE", default_value = "trapping", value_parser = parse_arithmetic)]
+        arithmetic: h6_runtime::Arithmetic,
     },
 
     /// list symbols in bytecode file
@@ -61,8 +189,33 @@ enum Command {
 
     /// disassemble
     Dis {
-        file: Utf8PathBuf
-    }
+        file: Utf8PathBuf,
+
+        /// print in the text format accepted by `h6 as`
+        #[clap(long, action)]
+        asm: bool,
+    },
+
+    /// assemble the text format printed by `h6 dis --asm` into a bytecode file
+    As {
+        input: Utf8PathBuf,
+
+        #[clap(short = 'o')]
+        output: Utf8PathBuf,
+    },
+
+    /// check a bytecode file for problems, without executing it
+    Verify {
+        input: Utf8PathBuf,
+    },
+
+    /// rewrite a V3 bytecode file in the current format
+    Upgrade {
+        input: Utf8PathBuf,
+
+        #[clap(short = 'o')]
+        output: Utf8PathBuf,
+    },
 }
 
 struct HumanError {
@@ -70,12 +223,14 @@ struct HumanError {
     ctx: Option<String>,
 }
 
+#[allow(clippy::enum_variant_names)]
 enum HumanErrorTy {
     IOError(std::io::Error),
     LinkError(linker::LinkError),
     ByteCodeError(h6_bytecode::ByteCodeError),
     RuntimeError(h6_runtime::RuntimeErr),
     LoweringError(h6_compiler::lower::LoweringError),
+    AsmError(h6_bytecode::asm::AsmError),
 }
 
 impl From<std::io::Error> for HumanErrorTy {
@@ -108,6 +263,12 @@ impl From<h6_compiler::lower::LoweringError> for HumanErrorTy {
     }
 }
 
+impl From<h6_bytecode::asm::AsmError> for HumanErrorTy {
+    fn from(value: h6_bytecode::asm::AsmError) -> Self {
+        HumanErrorTy::AsmError(value)
+    }
+}
+
 impl std::fmt::Debug for HumanErrorTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -116,6 +277,7 @@ impl std::fmt::Debug for HumanErrorTy {
             HumanErrorTy::ByteCodeError(err) => write!(f, "Bytecode Decode Error: {:?}", err),
             HumanErrorTy::RuntimeError(err) => write!(f, "{:?}", err),
             HumanErrorTy::LoweringError(err) => write!(f, "{:?}", err),
+            HumanErrorTy::AsmError(err) => write!(f, "Assembler Error: {:?}", err),
         }
     }
 }
@@ -145,18 +307,13 @@ impl<V, T: Sized + Into<HumanErrorTy>> WithCtx<V> for Result<V,T> {
     }
 }
 
+#[derive(Default)]
 struct RT {
     
 }
 
-impl Default for RT {
-    fn default() -> Self {
-        Self {}
-    }
-}
-
 fn register_runtime(rt: &mut h6_runtime::Runtime, _rtio: Rc<RefCell<RT>>) {
-    use smallvec::smallvec;
+    use smallvec::SmallVec;
     use h6_runtime::{Value, InSystemFn};
 
     // write bytes to stream
@@ -167,7 +324,7 @@ fn register_runtime(rt: &mut h6_runtime::Runtime, _rtio: Rc<RefCell<RT>>) {
         if stream != 1 { panic!(); }
         std::io::stdout().write_all(&[byte]).unwrap();
         std::io::stdout().flush().unwrap();
-        Ok(smallvec!())
+        Ok(SmallVec::new())
     }));
 
     // read byte from stream
@@ -178,7 +335,7 @@ fn register_runtime(rt: &mut h6_runtime::Runtime, _rtio: Rc<RefCell<RT>>) {
         let mut by = [0_u8;1];
         std::io::stdin().read_exact(&mut by).in_system_fn()?;
         let n = Value::Num(by[0].into());
-        Ok(smallvec!(n))
+        Ok(SmallVec::from_buf([n]))
     }));
 }
 
@@ -272,6 +429,37 @@ impl Hinter for Hint {
     }
 }
 
+/// completes defined symbols, and shows their doc comment as description
+#[cfg(feature = "repl")]
+struct DocCompleter {
+    /// (symbol, doc comment)
+    symbols: Vec<(String, Option<String>)>,
+}
+
+#[cfg(feature = "repl")]
+impl reedline::Completer for DocCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<reedline::Suggestion> {
+        let begin = line[..pos].rfind(|c: char| !(c.is_alphanumeric() || c == '_')).map_or(0, |x| x + 1);
+        let word = &line[begin..pos];
+        if word.len() < 2 {
+            return vec!();
+        }
+        let mut out = self.symbols.iter()
+            .filter(|(sym, _)| sym.starts_with(word))
+            .map(|(sym, doc)| reedline::Suggestion {
+                value: sym.clone(),
+                description: doc.clone(),
+                style: None,
+                extra: None,
+                span: reedline::Span::new(begin, pos),
+                append_whitespace: false,
+            })
+            .collect::<Vec<_>>();
+        out.sort_by(|a, b| a.value.cmp(&b.value));
+        out
+    }
+}
+
 #[cfg(feature = "repl")]
 struct Validd {}
 
@@ -298,7 +486,7 @@ impl Validator for Validd {
     }
 }
 
-fn print_stack(bc: &Bytecode, stack: &Vec<h6_runtime::Value>) {
+fn print_stack(bc: &Bytecode, stack: &[h6_runtime::Value]) {
     if stack.len() > 1 {
         println!("bot");
     }
@@ -310,47 +498,216 @@ fn print_stack(bc: &Bytecode, stack: &Vec<h6_runtime::Value>) {
     }
 }
 
-fn dis(asm: &Bytecode) -> Result<(), HumanError> {
-    let dis = h6_bytecode::disasm::Disasm::new(asm);
-
-    let mut globals_lut = HashMap::new();
-    println!("globals:");
-    for global in asm.named_globals() {
-        let (name, addr) = global.with_ctx("decoding")?;
-        println!("  {} \tdata+{} (={})", name, addr, addr as usize + 16);
-        globals_lut.insert(addr, name);
+/// prints every problem of the bytecode file, and returns weather or not there were any
+/// prints the module metadata, the globals and the unresolved symbols of [asm], like `nm`
+fn print_symbols(asm: &Bytecode) -> Result<(), HumanError> {
+    let meta = asm.metadata().transpose().with_ctx("while reading metadata")?.unwrap_or_default();
+    for module in meta.modules.iter() {
+        let version = if module.version.is_empty() { String::new() } else { format!(" {}", module.version) };
+        println!("module {}{} ({})", module.name, version, module.sources.join(", "));
+        for line in module.doc.lines() {
+            println!("{}", format!("         {}", line).trim_end());
+        }
     }
-    println!("");
 
-    println!("dso references:");
-    for dso in asm.dso_names().with_ctx("read dso data")? {
-        let str = asm.string(dso).with_ctx("read dso data")?;
-        println!("  {} \t{}", dso, str);
+    let init = asm.initializers().transpose().with_ctx("while reading initializers")?.unwrap_or_default();
+    for x in init.modules.iter() {
+        println!("{:#06x} I {}", x.pos, x.module);
     }
-    println!("");
 
-    for rel_pos in asm.codes_in_data_table().with_ctx("decoding")?.into_iter() {
-        let abs_pos = rel_pos + 16;
-        let name = globals_lut.get(&(rel_pos as u32))
-            .map(|v| *v)
-            .unwrap_or("????");
-        println!("data+{} (={}) : {}", rel_pos, abs_pos, name);
-        println!("  {}", dis.absolute_ops(abs_pos).with_ctx("decoding")?);
-        println!("");
+    for global in asm.globals() {
+        let name = asm.string(global.name).with_ctx("while reading input file")?;
+        println!("{:#06x} {} {}", global.const_id, if global.weak { "W" } else { "T" }, name);
+        for line in meta.doc(name).unwrap_or_default().lines() {
+            println!("{}", format!("         {}", line).trim_end());
+        }
     }
 
-    let main_beg = asm.header.main_ops_area_begin_idx();
-    println!("main (={})", main_beg);
-    println!("  {}", dis.absolute_ops(main_beg).with_ctx("decoding")?);
-    println!("");
+    for ent in asm.unresolved_symbols().with_ctx("while reading input file")? {
+        println!("       t {}", ent);
+    }
 
+    let libs = asm.dso_libraries().transpose().with_ctx("while reading dso libraries")?.unwrap_or_default();
+    for dso in asm.dso_names().with_ctx("while reading input file")? {
+        let name = asm.string(dso).with_ctx("while reading input file")?;
+        match libs.library(name) {
+            Some(lib) => println!("       U {} ({})", name, lib),
+            None => println!("       U {}", name),
+        }
+    }
     Ok(())
 }
 
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// formats the map of `h6 ld --map`. [origins] maps global names to the input file they come from.
+/// code that is not exported is attributed to the first symbol that references it.
+/// offsets are file offsets, like in `h6 dis` and runtime errors. the text form prints them in hex
+fn format_map(map: &linker::LinkMap, origins: &BTreeMap<String, String>, format: MapFormat) -> String {
+    let mut origin_of = BTreeMap::<u32, &str>::new();
+    for sym in map.symbols.iter() {
+        if let Some(origin) = sym.names.iter().find_map(|x| origins.get(x)) {
+            origin_of.insert(sym.offset, origin.as_str());
+        }
+    }
+    loop {
+        let found = map.symbols.iter()
+            .filter(|x| !origin_of.contains_key(&x.offset))
+            .find_map(|x| map.referrers(x.offset).find_map(|r| origin_of.get(&r.offset)).map(|o| (x.offset, *o)));
+        match found {
+            Some((offset, origin)) => { origin_of.insert(offset, origin); },
+            None => break,
+        }
+    }
+
+    let name = |offset: u32| map.symbol(offset)
+        .and_then(|x| x.names.first().cloned())
+        .unwrap_or_else(|| format!("{:#06x}", map.data_begin + offset));
+    let mut totals = BTreeMap::<&str, u32>::new();
+    for sym in map.symbols.iter() {
+        *totals.entry(origin_of.get(&sym.offset).copied().unwrap_or("?")).or_default() += sym.size;
+    }
+    let sections = [
+        ("strings", map.strings_len),
+        ("code", map.code_len),
+        ("pool", map.pool_len),
+        ("globals", map.globals_len),
+        ("main", map.main_len),
+        ("extensions", map.extensions_len),
+    ];
+
+    let mut out = String::new();
+    match format {
+        MapFormat::Text => {
+            out.push_str("sections:\n");
+            for (sec, len) in sections {
+                out.push_str(&format!("  {:<12}{:>8}\n", sec, len));
+            }
+            out.push_str("\nsymbols:\n");
+            for sym in map.symbols.iter() {
+                let names = if sym.names.is_empty() { "<private>".to_string() } else { sym.names.join(", ") };
+                let origin = origin_of.get(&sym.offset).copied().unwrap_or("?");
+                out.push_str(&format!("  {:#06x} {:>6}  {}  ({})\n", map.data_begin + sym.offset, sym.size, names, origin));
+                for r in sym.refs.iter() {
+                    out.push_str(&format!("      -> {}\n", name(*r)));
+                }
+                for r in map.referrers(sym.offset) {
+                    out.push_str(&format!("      <- {}\n", name(r.offset)));
+                }
+                if map.main_refs.contains(&sym.offset) {
+                    out.push_str("      <- <main>\n");
+                }
+            }
+            out.push_str("\nmodules:\n");
+            for (origin, size) in totals.iter() {
+                out.push_str(&format!("  {:>8}  {}\n", size, origin));
+            }
+        }
+
+        MapFormat::Json => {
+            let list = |names: Vec<String>| names.iter().map(|x| json_str(x)).collect::<Vec<_>>().join(", ");
+            out.push_str("{\n  \"sections\": {");
+            out.push_str(&sections.iter().map(|(sec, len)| format!("{}: {}", json_str(sec), len)).collect::<Vec<_>>().join(", "));
+            out.push_str("},\n  \"symbols\": [\n");
+            let syms = map.symbols.iter().map(|sym| {
+                let mut referrers = map.referrers(sym.offset).map(|x| name(x.offset)).collect::<Vec<_>>();
+                if map.main_refs.contains(&sym.offset) {
+                    referrers.push("<main>".to_string());
+                }
+                format!("    {{\"names\": [{}], \"offset\": {}, \"size\": {}, \"origin\": {}, \"refs\": [{}], \"referrers\": [{}]}}",
+                    list(sym.names.clone()), map.data_begin + sym.offset, sym.size,
+                    origin_of.get(&sym.offset).map_or("null".to_string(), |x| json_str(x)),
+                    list(sym.refs.iter().map(|x| name(*x)).collect()), list(referrers))
+            }).collect::<Vec<_>>();
+            out.push_str(&syms.join(",\n"));
+            out.push_str("\n  ],\n  \"modules\": {");
+            out.push_str(&totals.iter().map(|(origin, size)| format!("{}: {}", json_str(origin), size)).collect::<Vec<_>>().join(", "));
+            out.push_str("}\n}\n");
+        }
+    }
+    out
+}
+
+fn report_verify(path: &Utf8PathBuf, bytes: &[u8]) -> bool {
+    let diags = h6_bytecode::verify::verify(bytes);
+    for diag in diags.iter() {
+        match diag.pos {
+            Some(pos) => eprintln!("{}: {:#06x}: {:?}", path, pos, diag.problem),
+            None => eprintln!("{}: {:?}", path, diag.problem),
+        }
+    }
+    !diags.is_empty()
+}
+
+/// prints the unresolved symbols of `h6 ld`, grouped by name.
+/// [origins] maps global names to their input file, and [main_origins] the end of the main ops of each input file
+fn report_unresolved(bc: &Bytecode, syms: &[linker::UnresolvedSymbol], origins: &BTreeMap<String, String>, main_origins: &[(u32, String)]) {
+    let debug = bc.debug_info().and_then(|x| x.ok());
+    for sym in syms.iter() {
+        eprintln!("error: unresolved symbol `{}`", sym.name);
+        for (from, pos) in sym.refs.iter() {
+            let (what, file) = match from {
+                linker::Referrer::Global(name) => (name.clone(), origins.get(name)),
+                linker::Referrer::Main { op } => ("main code".to_string(),
+                    main_origins.iter().find(|(end, _)| op < end).map(|x| &x.1)),
+                linker::Referrer::Code { begin } => (format!("code at {:#06x}", begin), None),
+            };
+            let location = debug.as_ref()
+                .and_then(|x| x.lookup(*pos))
+                .map(|(src, ent)| format!("{}:{}:{}", src, ent.line, ent.col));
+            match (file, location) {
+                (Some(file), Some(location)) => eprintln!("  referenced by {} in {} ({})", what, file, location),
+                (Some(file), None) => eprintln!("  referenced by {} in {}", what, file),
+                (None, Some(location)) => eprintln!("  referenced by {} ({})", what, location),
+                (None, None) => eprintln!("  referenced by {}", what),
+            }
+        }
+        if !sym.suggestions.is_empty() {
+            let names = sym.suggestions.iter().map(|x| format!("`{}`", x)).collect::<Vec<_>>();
+            eprintln!("  help: did you mean {}?", names.join(" or "));
+        }
+    }
+    eprintln!("{} unresolved symbol{}", syms.len(), if syms.len() == 1 { "" } else { "s" });
+}
+
+/// reads an input file of `h6 ld`. V3 files are upgraded first, because the linker needs the sections of V4
+fn read_ld_input(path: &Utf8PathBuf) -> Result<Vec<u8>, HumanError> {
+    let mut content = vec!();
+    File::open(path).with_ctx("while opening input file")?
+        .read_to_end(&mut content).with_ctx("while reading input file")?;
+    if Archive::is_archive(content.as_slice()) {
+        return Ok(content);
+    }
+    h6_bytecode::upgrade::upgrade(content.as_slice()).with_ctx(format!("while upgrading {}", path))
+}
+
+/// source location of a runtime error, if the bytecode contains debug info for it
+fn src_location(bc: &Bytecode, err: &h6_runtime::RuntimeErr) -> Option<String> {
+    let pos = err.asm_byte_pos?.checked_sub(bc.header.data_begin())?;
+    let debug = bc.debug_info()?.ok()?;
+    let (file, ent) = debug.lookup(pos as u32)?;
+    Some(format!("{}:{}:{}", file, ent.line, ent.col))
+}
+
+/// replaces the references to globals in [val] with their names, so it can be lowered again.
+/// private bindings have no name in the globals table, so values that refer to them can not be kept
+#[cfg(feature = "repl")]
 fn val_unlink(val: h6_runtime::Value, bc: &Bytecode) -> Result<h6_runtime::Value, HumanError> {
     let globals = bc.named_globals()
         .collect::<Result<Vec<_>,h6_bytecode::ByteCodeError>>()
-        .unwrap();
+        .with_ctx("while reading the globals")?;
 
     match val {
         h6_runtime::Value::Arr(arr) => Ok(h6_runtime::Value::Arr(arr.into_iter()
@@ -358,26 +715,41 @@ fn val_unlink(val: h6_runtime::Value, bc: &Bytecode) -> Result<h6_runtime::Value
                 match op {
                     Op::Const { idx } => {
                         let v = globals.iter().find(|x| x.1 == idx)
-                            .unwrap().0;
-                        Op::Frontend(h6_bytecode::FrontendOp::Unresolved(v.to_string()))
+                            .ok_or(linker::LinkError::SymbolNotFound(format!("{:#06x}", idx)))
+                            .with_ctx("a value on the stack refers to a private binding")?
+                            .0;
+                        Ok(Op::Frontend(h6_bytecode::FrontendOp::Unresolved(v.to_string())))
                     }
 
-                    _ => op
+                    _ => Ok(op)
                 }
             })
-            .collect::<h6_runtime::ArrTy>())),
+            .collect::<Result<h6_runtime::ArrTy, HumanError>>()?)),
 
         h6_runtime::Value::Num(_) => Ok(val)
     }
 }
 
+/// a binding of the repl, that is lowered again with every line
+#[cfg(feature = "repl")]
+fn repl_define(expr: &h6_compiler::parse::Expr) -> h6_compiler::parse::Expr<'static> {
+    h6_compiler::parse::Expr {
+        binding: expr.binding.as_ref().map(|x| x.to_string().into()),
+        val: expr.val.clone(),
+        dso_extern: expr.dso_extern,
+        private: expr.private,
+        weak: expr.weak,
+        ..Default::default()
+    }
+}
+
 fn main() -> Result<(), HumanError> {
     better_panic::install();
     let args = App::parse();
 
     match args.command {
-        Command::Compile { input, output } => {
-            let content = std::fs::read_to_string(input).with_ctx("could not open input file")?;
+        Command::Compile { input, output, debug, compact, module_name, module_version } => {
+            let content = std::fs::read_to_string(&input).with_ctx("could not open input file")?;
 
             let toks = lex::lex(content.as_str())
                 .unwrap_or_else(|errs| {
@@ -395,118 +767,289 @@ fn main() -> Result<(), HumanError> {
                     std::process::exit(1);
                 });
 
+            let src_info = debug.then(|| lower::SrcInfo::new(
+                input.as_str(),
+                content.as_str(),
+                toks.iter().map(|x| x.1.clone()).collect()));
+
+            let name = module_name.unwrap_or_else(|| input.file_stem().unwrap_or_default().to_string());
+            let mut meta = doc::module_info(name.as_str(), input.as_str(), content.as_str(), &toks, &exprs);
+            meta.version = module_version.unwrap_or_default();
+
             let mut sink = File::create(output).with_ctx("while creating output file")?;
-            lower::lower_full(&mut sink, exprs.iter(), false)
+            let enc = if compact { OpEncoding::Compact } else { OpEncoding::Fixed };
+            lower::lower_full(&mut sink, exprs.iter(), false, enc, src_info.as_ref(), Some(&meta))
                 .with_ctx("while writing output file")?;
         }
 
         Command::Nm { input } => {
             let mut content = vec!();
-            File::open(input).with_ctx("while opening input file")?
+            File::open(&input).with_ctx("while opening input file")?
                 .read_to_end(&mut content).with_ctx("while reading input file")?;
-            let asm = Bytecode::try_from(content.as_slice())
-                .with_ctx("while decoding input file")?;
-
-            let mut defines = HashSet::new();
-            for global in asm.named_globals() {
-                let (name, pos) = global.with_ctx("while reading input file")?;
-                defines.insert(name);
-                println!("{:#06x} T {}", pos, name);
-            }
-
-            let mut discovered = HashSet::new();
-
-            for code in asm.codes_in_data_table().unwrap() {
-                for op in asm.const_ops(code as u32).unwrap() {
-                    let op = op.unwrap().1;
-                    match op {
-                        Op::Unresolved { id } => {
-                            discovered.insert(asm.string(id).unwrap());
-                        }
-                        _ => ()
-                    }
+            if Archive::is_archive(content.as_slice()) {
+                let archive = Archive::try_from(content.as_slice())
+                    .with_ctx("while decoding input file")?;
+                for member in archive.members.iter() {
+                    println!();
+                    println!("{}({}):", input, member.name);
+                    let asm = Bytecode::try_from(member.bytes.as_slice())
+                        .with_ctx("while decoding archive member")?;
+                    print_symbols(&asm)?;
                 }
+            } else {
+                let asm = Bytecode::try_from(content.as_slice())
+                    .with_ctx("while decoding input file")?;
+                print_symbols(&asm)?;
             }
+        }
 
-            for ent in discovered {
-                println!("       t {}", ent);
+        Command::Ar { output, inputs } => {
+            let mut members = vec!();
+            for inp in inputs.iter() {
+                let mut bytes = vec!();
+                File::open(inp).with_ctx("while opening input file")?
+                    .read_to_end(&mut bytes)
+                    .with_ctx("while reading input file")?;
+                let name = inp.file_name().unwrap_or(inp.as_str()).to_string();
+                members.push(Member { name, bytes });
             }
+            let archive = Archive::new(members).with_ctx("while reading input file")?;
+            std::fs::write(&output, archive.serialize()).with_ctx("while writing output file")?;
         }
 
-        Command::Ld { inputs, output, allow_unresolved, cat_only } => {
+        Command::Ld { inputs, output, allow_unresolved, cat_only, compact, gc, keep, wrap, defsym, dso, map, map_format, namespaces, entry, no_init } => {
             let mut inputs = inputs;
+            // linking into one of the inputs keeps its contents and op encoding
+            let mut out_data = None;
             if let Some(pos) = inputs.iter().position(|x| x == &output) {
                 inputs.remove(pos);
-            } else {
-                let mut f = File::create(&output).with_ctx("while creating output file")?;
-                let header = Header::default();
-                header.write(&mut f).with_ctx("while writing output file")?;
-                Op::Terminate.write(&mut f).with_ctx("while writing output file")?;
-                f.flush().unwrap();
+                out_data = Some(read_ld_input(&output)?);
+            }
+
+            let mut ns_inputs = vec!();
+            for (prefix, inp) in namespaces.iter() {
+                ns_inputs.push((prefix.as_str(), read_ld_input(inp)?));
+            }
+            // references between files in the same namespace have to be renamed too
+            let mut ns_names = HashMap::<&str, BTreeSet<&str>>::new();
+            for (prefix, inp_data) in ns_inputs.iter() {
+                let names = ns_names.entry(prefix).or_default();
+                for global in Bytecode::try_from(inp_data.as_slice()).with_ctx("while reading input file")?.named_globals() {
+                    names.insert(global.with_ctx("while reading input file")?.0);
+                }
+            }
+
+            // (contents, input file) of every module, in the order they are linked
+            let mut modules = vec!();
+            for ((prefix, inp_data), (_, path)) in ns_inputs.iter().zip(namespaces.iter()) {
+                let renamed = linker::namespace(inp_data.as_slice(), prefix, &ns_names[prefix])
+                    .with_ctx("while applying namespace")?;
+                modules.push((renamed, path.to_string()));
             }
 
-            let mut out = std::fs::OpenOptions::new()
-                .write(true)
-                .create(false)
-                .truncate(false)
-                .read(true)
-                .open(&output)
-                .with_ctx("while opening output file")?;
+            let mut archives = vec!();
+            let mut archive_paths = vec!();
             for inp in inputs.into_iter() {
-                let mut inp_data = vec!();
-                File::open(&inp).with_ctx("while opening input file")?
-                    .read_to_end(&mut inp_data)
-                    .with_ctx("while reading input file")?;
+                let inp_data = read_ld_input(&inp)?;
+                if Archive::is_archive(inp_data.as_slice()) {
+                    archives.push(Archive::try_from(inp_data.as_slice()).with_ctx("while reading archive")?);
+                    archive_paths.push(inp);
+                    continue;
+                }
+                modules.push((inp_data, inp.to_string()));
+            }
 
-                linker::cat_together(&mut out, inp_data.as_slice())
-                    .with_ctx("while linking")?;
+            // input file of every global, for the map and errors
+            let mut origins = BTreeMap::<String, String>::new();
+            // end of the main ops of every input file, in the order they are linked
+            let mut main_origins = Vec::<(u32, String)>::new();
+            let mut add_origins = |bc: &Bytecode, origin: &str| -> Result<(), HumanError> {
+                for global in bc.named_globals() {
+                    origins.entry(global.with_ctx("while reading input file")?.0.to_string())
+                        .or_insert_with(|| origin.to_string());
+                }
+                let main_begin = main_origins.last().map(|x| x.0).unwrap_or(0);
+                main_origins.push((main_begin + bc.main_ops().count() as u32, origin.to_string()));
+                Ok(())
+            };
+
+            let mut ld = linker::Linker::new();
+            ld.set_op_encoding(match &out_data {
+                Some(out_data) => Header::try_from(out_data.as_slice()).with_ctx("while reading output file")?.op_encoding(),
+                None if compact => OpEncoding::Compact,
+                None => OpEncoding::Fixed,
+            });
+            if let Some(out_data) = out_data {
+                modules.insert(0, (out_data, output.to_string()));
+            }
+            for (data, origin) in modules.iter() {
+                let bc = Bytecode::try_from(data.as_slice()).with_ctx("while reading input file")?;
+                add_origins(&bc, origin)?;
+                ld.add(bc).with_ctx("while linking")?;
             }
+            let members = ld.add_archives(archives.as_slice())
+                .with_ctx("while linking archive members")?;
+            for (a, m) in members {
+                let member = &archives[a].members[m];
+                let bc = Bytecode::try_from(member.bytes.as_slice()).with_ctx("while reading archive")?;
+                add_origins(&bc, &format!("{}({})", archive_paths[a], member.name))?;
+            }
+            let mut bytes = ld.cat().with_ctx("while linking")?;
 
             struct TargetImpl {
                 allow_unresolved: bool,
+                wrap: Vec<String>,
+                defsym: Vec<(String, String)>,
             }
 
             impl linker::Target for TargetImpl {
                 fn allow_undeclared_symbol(&self, _: &str) -> bool {
-                    return self.allow_unresolved;
+                    self.allow_unresolved
+                }
+
+                fn redirect(&self, sym: &str) -> Option<String> {
+                    if let Some((_, to)) = self.defsym.iter().find(|(from, _)| from == sym) {
+                        return Some(to.clone());
+                    }
+                    if let Some(real) = sym.strip_prefix("__real_").filter(|x| self.wrap.iter().any(|w| w == x)) {
+                        return Some(real.to_string());
+                    }
+                    self.wrap.iter().any(|w| w == sym).then(|| format!("__wrap_{}", sym))
                 }
             }
 
             if !cat_only {
-                out.rewind().unwrap();
-                let mut bytes = vec!();
-                out.read_to_end(&mut bytes).unwrap();
+                let linked = linker::self_link(&mut bytes, &TargetImpl { allow_unresolved, wrap, defsym });
+                if let Err(linker::LinkError::UnresolvedSymbols(syms)) = &linked {
+                    let bc = Bytecode::try_from(bytes.as_slice()).with_ctx("while linking")?;
+                    report_unresolved(&bc, syms.as_slice(), &origins, main_origins.as_slice());
+                    std::process::exit(1);
+                }
+                linked.with_ctx("while linking")?;
 
-                linker::self_link(&mut bytes, &TargetImpl { allow_unresolved }).with_ctx("while linking")?;
+                if entry.is_some() || !no_init.is_empty() {
+                    let drop = no_init.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+                    bytes = linker::set_main(bytes.as_slice(), entry.as_deref(), drop.as_slice())
+                        .with_ctx("while setting the entry")?;
+                }
+
+                if gc {
+                    let roots = keep.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+                    let (new, report) = linker::self_gc(bytes.as_slice(), roots.as_slice())
+                        .with_ctx("while removing unused code")?;
+                    println!("gc: removed {} bytes of strings, {} bytes of code, {} bytes of arrays, {} globals, {} dso entries",
+                        report.strings, report.code, report.pool, report.globals, report.dso);
+                    println!("gc: saved {} bytes ({} -> {})", bytes.len() as isize - new.len() as isize, bytes.len(), new.len());
+                    bytes = new;
+                }
 
-                out.rewind().unwrap();
-                out.write_all(bytes.as_slice()).unwrap();
+                if !dso.is_empty() {
+                    let mut libs = vec!();
+                    for path in dso.iter() {
+                        libs.push((path.file_name().unwrap_or(path.as_str()), std::fs::read(path).with_ctx("while reading dso")?));
+                    }
+                    let libs = libs.iter().map(|(name, data)| (*name, data.as_slice())).collect::<Vec<_>>();
+                    bytes = linker::bind_dso(bytes.as_slice(), libs.as_slice()).with_ctx("while checking dso imports")?;
+                }
+
+                let header = Header::try_from(bytes.as_slice()).with_ctx("while linking")?;
+                if header.op_encoding() == OpEncoding::Compact {
+                    bytes = encoding::relax(bytes.as_slice()).with_ctx("while relaxing ops")?;
+                }
+
+                if let Some(map) = map {
+                    let link_map = linker::link_map(bytes.as_slice()).with_ctx("while creating map")?;
+                    std::fs::write(&map, format_map(&link_map, &origins, map_format))
+                        .with_ctx("while writing map file")?;
+                }
             }
+            std::fs::write(&output, bytes.as_slice()).with_ctx("while writing output file")?;
         }
 
-        Command::Run { input } => {
+        Command::Run { input, dso, max_ops, max_stack, max_todo, max_arr_ops, timeout, arithmetic } => {
             let mut content = vec!();
-            File::open(input).with_ctx("while opening input file")?
+            File::open(&input).with_ctx("while opening input file")?
                 .read_to_end(&mut content).with_ctx("while reading input file")?;
+            if report_verify(&input, content.as_slice()) {
+                std::process::exit(1);
+            }
             let asm = Bytecode::try_from(content.as_slice())
                 .with_ctx("while decoding input file")?;
 
-            let mut rt = h6_runtime::Runtime::new(asm).unwrap();
+            let mut dso_contents = vec!();
+            for path in dso.iter() {
+                let bytes = std::fs::read(path).with_ctx("while reading dso")?;
+                if report_verify(path, bytes.as_slice()) {
+                    std::process::exit(1);
+                }
+                dso_contents.push(bytes);
+            }
+            let dsos = dso_contents.iter()
+                .map(|x| Bytecode::try_from(x.as_slice()))
+                .collect::<Result<Vec<_>, _>>()
+                .with_ctx("while decoding dso")?;
+
+            let mut rt = h6_runtime::Runtime::with_dsos(asm, dsos).with_ctx("while loading dso")?;
             register_runtime(&mut rt, Rc::new(RefCell::new(RT::default())));
+            rt.set_limits(h6_runtime::Limits { ops: max_ops, stack: max_stack, todo: max_todo, arr_ops: max_arr_ops })
+                .set_arithmetic(arithmetic);
+            if let Some(ms) = timeout {
+                let cancel = rt.cancel_handle();
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(ms));
+                    cancel.cancel();
+                });
+            }
 
-            while let Some(_) = rt.step().with_ctx("exec")? {}
+            loop {
+                match rt.step() {
+                    Ok(Some(_)) => {},
+                    Ok(None) => break,
+                    Err(e) => {
+                        let ctx = src_location(&rt.bc, &e).unwrap_or_else(|| "exec".to_string());
+                        return Err(e).with_ctx(ctx);
+                    }
+                }
+            }
 
-            print_stack(&rt.bc, &rt.stack.into());
+            print_stack(&rt.bc, &Into::<Vec<_>>::into(rt.stack));
         }
 
-        Command::Dis { file } => {
+        Command::Dis { file, asm: as_text } => {
             let mut content = vec!();
             File::open(file).with_ctx("while opening input file")?
                 .read_to_end(&mut content).with_ctx("while reading input file")?;
 
             let asm = Bytecode::try_from(content.as_slice())
                 .with_ctx("while decoding input file")?;
-            dis(&asm)?;
+            if as_text {
+                print!("{}", h6_bytecode::asm::disassemble(&asm).with_ctx("decoding")?);
+            } else {
+                print!("{}", h6_bytecode::disasm::Disasm::new(&asm).listing());
+            }
+        }
+
+        Command::As { input, output } => {
+            let content = std::fs::read_to_string(&input).with_ctx("could not open input file")?;
+            let bytes = h6_bytecode::asm::assemble(content.as_str()).with_ctx(input.as_str())?;
+            std::fs::write(output, bytes).with_ctx("while writing output file")?;
+        }
+
+        Command::Verify { input } => {
+            let mut content = vec!();
+            File::open(&input).with_ctx("while opening input file")?
+                .read_to_end(&mut content).with_ctx("while reading input file")?;
+            if report_verify(&input, content.as_slice()) {
+                std::process::exit(1);
+            }
+        }
+
+        Command::Upgrade { input, output } => {
+            let mut content = vec!();
+            File::open(&input).with_ctx("while opening input file")?
+                .read_to_end(&mut content).with_ctx("while reading input file")?;
+            let bytes = h6_bytecode::upgrade::upgrade(content.as_slice()).with_ctx(input.as_str())?;
+            std::fs::write(output, bytes).with_ctx("while writing output file")?;
         }
 
         #[cfg(not(feature = "repl"))]
@@ -523,7 +1066,8 @@ fn main() -> Result<(), HumanError> {
 
 
             let mut stack = Vec::<h6_runtime::Value>::new();
-            let mut defines = HashMap::<String, smallvec::SmallVec<Op, 8>>::new();
+            let mut defines = HashMap::<String, Expr<'static>>::new();
+            let mut docs = HashMap::<String, String>::new();
 
             for path in import.into_iter() {
                 let content = std::fs::read_to_string(&path).with_ctx("reading input file")?;
@@ -544,9 +1088,10 @@ fn main() -> Result<(), HumanError> {
                         std::process::exit(1);
                     });
 
+                docs.extend(doc::module_info("", path.as_str(), content.as_str(), &toks, &exprs).docs);
                 for expr in exprs.into_iter() {
                     if let Some(def) = &expr.binding {
-                        defines.insert(def.to_string(), expr.val.clone());
+                        defines.insert(def.to_string(), repl_define(&expr));
                     }
                 }
             }
@@ -578,9 +1123,9 @@ fn main() -> Result<(), HumanError> {
             loop {
                 let mut autocomp = vec!();
                 for (sym,_) in defines.iter() {
-                    autocomp.push(sym.clone());
+                    autocomp.push((sym.clone(), docs.get(sym).cloned()));
                 }
-                let compl = Box::new(reedline::DefaultCompleter::new_with_wordlen(autocomp, 2));
+                let compl = Box::new(DocCompleter { symbols: autocomp });
                 editor = editor.with_completer(compl);
                 let sig = editor.read_line(&prompt).unwrap();
                 match sig {
@@ -605,13 +1150,16 @@ fn main() -> Result<(), HumanError> {
                                         }
                                     })
                                 {
-                                    for e in &exprs {
-                                        if let Some(e) = &e.binding {
-                                            defines.remove(e.as_ref());
-                                        }
+                                    // bindings of this line replace the previous ones
+                                    let redefined = exprs.iter()
+                                        .filter_map(|e| e.binding.as_ref().map(|e| e.to_string()))
+                                        .collect::<std::collections::HashSet<_>>();
+                                    for e in redefined.iter() {
+                                        docs.remove(e);
                                     }
+                                    docs.extend(doc::module_info("", "", text.as_str(), &toks, &exprs).docs);
                                     let mut all = vec!();
-                                    for val in stack.drain(0..) {
+                                    for val in stack.iter().cloned() {
                                         let ops = val.into_ops();
                                         let e = h6_compiler::parse::Expr {
                                             tok_span: 0..0,
@@ -621,14 +1169,7 @@ fn main() -> Result<(), HumanError> {
                                         all.push(e);
                                     }
                                     all.extend(exprs.into_iter());
-                                    for (k, v) in defines.iter() {
-                                        all.push(Expr {
-                                            tok_span: 0..0,
-                                            binding: Some(k.clone().into()),
-                                            val: v.clone(),
-                                            ..Default::default()
-                                        });
-                                    }
+                                    all.extend(defines.iter().filter(|(k, _)| !redefined.contains(*k)).map(|(_, v)| v.clone()));
 
                                     struct TargetImpl {}
 
@@ -639,30 +1180,40 @@ fn main() -> Result<(), HumanError> {
                                     }
 
                                     let mut bytes = vec!();
-                                    let header = h6_compiler::lower::lower(&mut bytes, all.iter(), true).unwrap();
+                                    let header = h6_compiler::lower::lower(&mut bytes, all.iter(), true, OpEncoding::Fixed, None, None);
+                                    let Ok(header) = header.inspect_err(|err| eprintln!("lowering error: {:?}", err)) else {
+                                        continue;
+                                    };
                                     bytes.splice(0..0, header.into_iter());
 
-                                    if let Ok(_) = h6_bytecode::linker::self_link(bytes.as_mut_slice(), &TargetImpl {})
+                                    let mut ld = linker::Linker::new();
+                                    let linked = ld.add(Bytecode::try_from(bytes.as_slice()).unwrap())
+                                        .and_then(|ld| ld.link(&TargetImpl {}))
                                         .inspect_err(|err| {
                                             eprintln!("linker error: {:?}", err);
-                                        })
-                                    {
+                                        });
+                                    if let Ok(bytes) = linked {
                                         let bc = Bytecode::try_from(bytes.as_slice()).unwrap();
                                         let mut rt = h6_runtime::Runtime::new(bc).unwrap();
                                         register_runtime(&mut rt, Rc::new(RefCell::new(RT::default())));
 
                                         while let Ok(Some(_)) = rt.step().with_ctx("exec").inspect_err(|e| { eprintln!("{:?}", e); }) {}
-                                        stack = Into::<Vec<_>>::into(rt.stack)
+                                        // the stack and the bindings stay as they were if the new stack can not be kept
+                                        let unlinked = Into::<Vec<_>>::into(rt.stack)
                                             .into_iter()
                                             .map(|x| val_unlink(x, &rt.bc))
-                                            .collect::<Result<Vec<h6_runtime::Value>,HumanError>>()?;
+                                            .collect::<Result<Vec<h6_runtime::Value>,HumanError>>();
+                                        match unlinked {
+                                            Ok(new) => stack = new,
+                                            Err(err) => {
+                                                eprintln!("{:?}", err);
+                                                continue;
+                                            }
+                                        }
                                         print_stack(&rt.bc, &stack);
 
                                         defines = all.iter()
-                                            .filter_map(|x| match &x.binding {
-                                                Some(bind) => Some((bind.to_string(), x.val.clone())),
-                                                None => None,
-                                            })
+                                            .filter_map(|x| x.binding.as_ref().map(|bind| (bind.to_string(), repl_define(x))))
                                             .collect();
                                     }
                                 }
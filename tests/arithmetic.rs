@@ -0,0 +1,108 @@
+//! both runtimes have to give the same results for arithmetic edge cases, in every overflow mode
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use common::{dir, h6, ok};
+
+const MAX: &str = "2147483647";
+/// the lexer can not read -2147483648 directly
+const MIN: &str = "-2147483647 1 -";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    Num(i32),
+    DivByZero,
+    Overflow,
+}
+
+use Expect::*;
+
+/// (program, trapping, wrapping, saturating)
+fn cases() -> Vec<(String, Expect, Expect, Expect)> {
+    let all = |e: Expect| (e, e, e);
+    let cases = [
+        ("7 5 +".to_string(), all(Num(12))),
+        ("7 5 -".to_string(), all(Num(2))),
+        ("-7 5 *".to_string(), all(Num(-35))),
+        ("-7 2 /".to_string(), all(Num(-3))),
+        ("-7 2 %".to_string(), all(Num(-1))),
+        ("7 -2 %".to_string(), all(Num(1))),
+        ("1 0 /".to_string(), all(DivByZero)),
+        ("1 0 %".to_string(), all(DivByZero)),
+        (format!("{} 1 +", MAX), (Overflow, Num(i32::MIN), Num(i32::MAX))),
+        (format!("{} -1 +", MIN), (Overflow, Num(i32::MAX), Num(i32::MIN))),
+        (format!("{} 1 -", MIN), (Overflow, Num(i32::MAX), Num(i32::MIN))),
+        (format!("{} -1 -", MAX), (Overflow, Num(i32::MIN), Num(i32::MAX))),
+        (format!("{} 2 *", MAX), (Overflow, Num(-2), Num(i32::MAX))),
+        (format!("{} -2 *", MAX), (Overflow, Num(2), Num(i32::MIN))),
+        (format!("{} -1 *", MIN), (Overflow, Num(i32::MIN), Num(i32::MAX))),
+        (format!("{} -1 /", MIN), (Overflow, Num(i32::MIN), Num(i32::MAX))),
+        (format!("{} -1 %", MIN), (Overflow, Num(0), Num(0))),
+        (format!("{} 2 /", MIN), all(Num(-1073741824))),
+    ];
+    cases.into_iter().map(|(src, (t, w, s))| (src, t, w, s)).collect()
+}
+
+/// builds the crt into [dir], None if there is no C compiler
+fn build_crt(dir: &Path) -> Option<PathBuf> {
+    let crt = Path::new(env!("CARGO_MANIFEST_DIR")).join("crt");
+    let out = dir.join("crt");
+    let status = Command::new("cc")
+        .args([crt.join("main.c"), crt.join("rt.c")])
+        .arg("-o")
+        .arg(&out)
+        .status()
+        .ok()?;
+    assert!(status.success(), "building the crt failed");
+    Some(out)
+}
+
+/// the value printed by `h6 run` or the crt, or which error it failed with
+fn result(out: &Output, div_by_zero: &str, overflow: &str) -> Expect {
+    if out.status.success() {
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let val = stdout.lines()
+            .find_map(|x| x.trim().parse::<i32>().ok())
+            .unwrap_or_else(|| panic!("no result in {:?}", stdout));
+        return Num(val);
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    if stderr.contains(div_by_zero) {
+        DivByZero
+    } else if stderr.contains(overflow) {
+        Overflow
+    } else {
+        panic!("unexpected error {:?}", stderr)
+    }
+}
+
+#[test]
+fn arithmetic_conformance() {
+    let dir = dir("arithmetic");
+    let crt = build_crt(&dir);
+    if crt.is_none() {
+        eprintln!("no C compiler found, only checking the Rust runtime");
+    }
+
+    for (idx, (src, trapping, wrapping, saturating)) in cases().into_iter().enumerate() {
+        let src_path = dir.join(format!("{}.h6", idx));
+        let obj = dir.join(format!("{}.h6b", idx));
+        let bin = dir.join(format!("{}.bin", idx));
+        std::fs::write(&src_path, format!("{}\n", src)).unwrap();
+        ok(&["compile", src_path.to_str().unwrap(), "-o", obj.to_str().unwrap()]);
+        ok(&["ld", obj.to_str().unwrap(), "-o", bin.to_str().unwrap()]);
+
+        for (mode, expect) in [("trapping", trapping), ("wrapping", wrapping), ("saturating", saturating)] {
+            let out = h6(&["run", "--arithmetic", mode, bin.to_str().unwrap()]);
+            assert_eq!(result(&out, "DivByZero", "Overflow"), expect, "`{}` in h6 run, {}", src, mode);
+
+            if let Some(crt) = &crt {
+                let out = Command::new(crt).args(["--arith", mode]).arg(&bin).output().unwrap();
+                assert_eq!(result(&out, "division by zero", "overflow"), expect, "`{}` in crt, {}", src, mode);
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,50 @@
+//! assembling the output of `h6 dis --asm` has to give back the exact same bytes
+
+mod common;
+
+use std::path::Path;
+use common::{dir, ok, sources};
+
+fn compile(dir: &Path, src: &Path) -> String {
+    let obj = dir.join(src.file_name().unwrap()).with_extension("h6b");
+    ok(&["compile", "-g", src.to_str().unwrap(), "-o", obj.to_str().unwrap()]);
+    obj.to_str().unwrap().to_string()
+}
+
+fn round_trip(path: &str) {
+    let asm = ok(&["dis", "--asm", path]);
+    let src = format!("{}.s", path);
+    let out = format!("{}.as", path);
+    std::fs::write(&src, asm.stdout).unwrap();
+    ok(&["as", &src, "-o", &out]);
+    assert!(std::fs::read(path).unwrap() == std::fs::read(&out).unwrap(), "{} changes in the round trip", path);
+}
+
+#[test]
+fn std_and_examples_round_trip() {
+    let dir = dir("asm");
+
+    let std = sources("std").iter().map(|x| compile(&dir, x)).collect::<Vec<_>>();
+    let examples = sources("examples").iter().map(|x| compile(&dir, x)).collect::<Vec<_>>();
+    for obj in std.iter().chain(examples.iter()) {
+        round_trip(obj);
+    }
+
+    let variants: [&[&str]; 4] = [&[], &["--compact"], &["--gc"], &["--gc", "--compact"]];
+    for (idx, flags) in variants.into_iter().enumerate() {
+        // the std alone, and every example with the std. gc drops the weak `print` of the std
+        let mut inputs = vec!(std.clone());
+        inputs.extend(examples.iter().map(|x| vec!(x.clone())));
+        inputs.push(std.iter().chain(examples.iter().filter(|x| x.ends_with("02.h6b"))).cloned().collect());
+
+        for (i, objs) in inputs.into_iter().enumerate() {
+            let out = dir.join(format!("linked-{}-{}.h6b", idx, i));
+            let mut args = vec!("ld", "--allow-unresolved", "-o", out.to_str().unwrap());
+            args.extend(objs.iter().map(|x| x.as_str()));
+            args.extend_from_slice(flags);
+            ok(&args);
+            round_trip(out.to_str().unwrap());
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
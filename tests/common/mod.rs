@@ -0,0 +1,58 @@
+//! helpers for the tests that run the `h6` binary. every test file only uses some of them
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// runs `h6` with [args], whether it succeeds or not
+pub fn h6(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_h6"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// runs `h6` with [args], which has to succeed
+pub fn ok(args: &[&str]) -> Output {
+    let out = h6(args);
+    assert!(out.status.success(), "h6 {:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+    out
+}
+
+/// creates a directory for the files of one test. [name] should start with the name of the test file
+pub fn dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("h6-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// writes [src] to `[dir]/[name].h6` and compiles it with the extra [flags]. returns the path of the object file
+pub fn compile(dir: &Path, name: &str, src: &str, flags: &[&str]) -> String {
+    let src_path = dir.join(name).with_extension("h6");
+    let obj = dir.join(name).with_extension("h6b");
+    std::fs::write(&src_path, src).unwrap();
+    let mut args = vec!("compile", src_path.to_str().unwrap(), "-o", obj.to_str().unwrap());
+    args.extend_from_slice(flags);
+    ok(&args);
+    obj.to_str().unwrap().to_string()
+}
+
+/// the stack that a successful `h6 run` printed, one item per line
+pub fn stack(out: &Output) -> Vec<String> {
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    String::from_utf8_lossy(&out.stdout).lines()
+        .map(|x| x.trim().to_string())
+        .filter(|x| x != "bot" && x != "top")
+        .collect()
+}
+
+/// the `.h6` files in the directory [dir] of the repository, sorted by name
+pub fn sources(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut out = std::fs::read_dir(dir).unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "h6"))
+        .collect::<Vec<_>>();
+    out.sort();
+    out
+}
@@ -0,0 +1,68 @@
+//! doc comments are extracted by the compiler and stored in the metadata section
+
+use std::io::Cursor;
+use h6_bytecode::{Bytecode, OpEncoding};
+use h6_compiler::{doc, lex, parse, lower};
+
+const SRC: &str = "# math helpers
+# for tests
+
+# squares a number
+sq: { . * }
+
+cube: { . sq! * }
+
+# not exported
+private helper: { 1 }
+
+# first line
+#
+# third line
+quad: { sq! sq! helper! }
+
+# separated by a blank line
+
+lonely: { 2 }
+two: { 1 } # behind code
+three: { 3 }
+
+# imported
+dso_extern ext
+";
+
+#[test]
+fn module_docs() {
+    let toks = lex::lex(SRC).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let meta = doc::module_info("math", "math.h6", SRC, &toks, &exprs);
+
+    assert_eq!(meta.name, "math");
+    assert_eq!(meta.sources, ["math.h6"]);
+    assert_eq!(meta.doc, "math helpers\nfor tests");
+    // undocumented, private and dso_extern bindings have no entry
+    assert_eq!(meta.docs, [
+        ("sq".to_string(), "squares a number".to_string()),
+        ("quad".to_string(), "first line\n\nthird line".to_string()),
+    ]);
+
+    let mut out = Cursor::new(vec!());
+    lower::lower_full(&mut out, exprs.iter(), false, OpEncoding::Fixed, None, Some(&meta)).unwrap();
+    let bc = Bytecode::try_from(out.get_ref().as_slice()).unwrap();
+    let stored = bc.metadata().unwrap().unwrap();
+    assert_eq!(stored.modules, [meta]);
+    assert_eq!(stored.doc("quad"), Some("first line\n\nthird line"));
+    for name in ["cube", "helper", "lonely", "three", "ext"] {
+        assert_eq!(stored.doc(name), None, "{}", name);
+    }
+}
+
+#[test]
+fn no_module_doc() {
+    // without a blank line, the leading comment documents the first binding
+    let src = "# squares a number\nsq: { . * }\n";
+    let toks = lex::lex(src).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let meta = doc::module_info("sq", "sq.h6", src, &toks, &exprs);
+    assert_eq!(meta.doc, "");
+    assert_eq!(meta.docs, [("sq".to_string(), "squares a number".to_string())]);
+}
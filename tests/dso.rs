@@ -0,0 +1,80 @@
+//! `dso_extern` names have to be imported from the libraries passed to `h6 run --dso`. `h6 ld --dso` checks them when linking
+
+mod common;
+
+use std::path::Path;
+use h6_bytecode::Bytecode;
+use common::{compile, dir, h6, ok, stack};
+
+const LIB: &str = "sq: { . * }\nnums: { 1 2 3 4 5 6 7 8 9 10 }\nquad: { sq! sq! }\nwrapped: { { 5 sq! } }\n";
+
+/// compiles and links [src] into [dir]
+fn build(dir: &Path, name: &str, src: &str) -> String {
+    let obj = compile(dir, name, src, &[]);
+    let bin = dir.join(name).with_extension("bin");
+    ok(&["ld", &obj, "-o", bin.to_str().unwrap()]);
+    bin.to_str().unwrap().to_string()
+}
+
+#[test]
+fn imports() {
+    let dir = dir("dso-imports");
+    let lib = build(&dir, "lib", LIB);
+    let other = build(&dir, "other", "quad: { 0 }\n");
+    // the imported code refers to the globals and the constant pool of the library
+    let main = build(&dir, "main", "dso_extern quad\ndso_extern nums\ndso_extern wrapped\n\n3 quad! nums! wrapped! !\n");
+
+    let nums = (1..=10).map(|x| x.to_string());
+    let expected = ["81".to_string()].into_iter().chain(nums).chain(["25".to_string()]).collect::<Vec<_>>();
+    assert_eq!(stack(&h6(&["run", "--dso", &lib, &main])), expected);
+
+    // the first library that exports a name is used
+    assert_eq!(stack(&h6(&["run", "--dso", &lib, "--dso", &other, &main])), expected);
+    let out = stack(&h6(&["run", "--dso", &other, "--dso", &lib, &main]));
+    assert_eq!(out[..2], ["3", "0"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn missing_imports() {
+    let dir = dir("dso-missing");
+    let lib = build(&dir, "lib", LIB);
+    let main = build(&dir, "missing", "dso_extern quad\ndso_extern missing\n\n2 quad! missing!\n");
+
+    for args in [vec!("run", main.as_str()), vec!("run", "--dso", lib.as_str(), main.as_str())] {
+        let out = h6(&args);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let name = if args.len() == 2 { "quad" } else { "missing" };
+        assert!(stderr.contains(&format!("DsoNotFound(\"{}\")", name)), "{:?}: {}", args, stderr);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn ld_checks_imports() {
+    let dir = dir("dso-ld");
+    let lib = build(&dir, "lib", LIB);
+    let other = build(&dir, "other", "quad: { 0 }\n");
+    let obj = compile(&dir, "main", "dso_extern quad\ndso_extern nums\n\n3 quad! nums!\n", &[]);
+    let bin = dir.join("main.bin");
+    let (obj, bin) = (obj.as_str(), bin.to_str().unwrap());
+
+    // the library of every import is recorded, by file name
+    let out = h6(&["ld", obj, "-o", bin, "--dso", &other, "--dso", &lib]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let bytes = std::fs::read(bin).unwrap();
+    let libs = Bytecode::try_from(bytes.as_slice()).unwrap().dso_libraries().unwrap().unwrap();
+    assert_eq!(libs.library("quad"), Some("other.bin"));
+    assert_eq!(libs.library("nums"), Some("lib.bin"));
+    let nums = (1..=10).map(|x| x.to_string());
+    assert_eq!(stack(&h6(&["run", "--dso", &lib, bin])), ["81".to_string()].into_iter().chain(nums).collect::<Vec<_>>());
+
+    // other does not export nums
+    let out = h6(&["ld", obj, "-o", bin, "--dso", &other]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("DsoImportsNotFound([\"nums\"])"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,122 @@
+//! the compact op encoding has to link and run like the fixed one, with ops that are padded or relaxed
+
+mod common;
+
+use std::io::Cursor;
+use h6_bytecode::{encoding, linker, Bytecode, OpEncoding};
+use common::{compile, dir, h6, ok, stack};
+
+fn is_compact(path: &str) -> bool {
+    let bytes = std::fs::read(path).unwrap();
+    Bytecode::try_from(bytes.as_slice()).unwrap().header.op_encoding() == OpEncoding::Compact
+}
+
+#[test]
+fn runtime_error_location() {
+    let dir = dir("encoding-location");
+    // the failing addition is in an array, after two relocatable ops
+    let src = "one: 1\n\n{ one one + + } !\n";
+    let fixed = compile(&dir, "fixed", src, &["-g"]);
+    let compact = compile(&dir, "compact", src, &["-g", "--compact"]);
+
+    let relaxed = dir.join("relaxed.h6b").to_str().unwrap().to_string();
+    ok(&["ld", &compact, "--compact", "-o", &relaxed]);
+    let linked = dir.join("linked.h6b").to_str().unwrap().to_string();
+    ok(&["ld", &fixed, "-o", &linked]);
+
+    // the relocatable ops of the unlinked file are still padded
+    for (path, compact) in [(&linked, false), (&relaxed, true), (&compact, true)] {
+        assert_eq!(is_compact(path), compact, "{}", path);
+        let out = h6(&["run", path]);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains(".h6:3:13: ") && stderr.contains("StackUnderflow"), "{}: {}", path, stderr);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+struct Strict;
+
+impl linker::Target for Strict {
+    fn allow_undeclared_symbol(&self, _: &str) -> bool {
+        false
+    }
+}
+
+#[test]
+fn patch_in_place() {
+    let dir = dir("encoding-patch");
+    let a = "sq: { . * }\ntwice: { quad! 2 / }\n\n3 twice! 5 sq!\n";
+    let b = "quad: { sq! sq! }\n\n{ 2 sq! } !\n";
+
+    let expected = {
+        let (a, b) = (compile(&dir, "a", a, &["-g"]), compile(&dir, "b", b, &["-g"]));
+        let out = dir.join("fixed.h6b").to_str().unwrap().to_string();
+        ok(&["ld", &a, &b, "-o", &out]);
+        ok(&["run", &out]).stdout
+    };
+
+    let a = std::fs::read(compile(&dir, "a", a, &["-g", "--compact"])).unwrap();
+    let b = std::fs::read(compile(&dir, "b", b, &["-g", "--compact"])).unwrap();
+    let mut out = Cursor::new(a);
+    linker::cat_together(&mut out, b.as_slice()).unwrap();
+    let mut bin = out.into_inner();
+    assert_eq!(h6_bytecode::verify::verify(&bin), vec!());
+
+    // self_link writes the resolved ops into the padding of the unresolved ones
+    let bc = Bytecode::try_from(bin.as_slice()).unwrap();
+    assert_eq!(bc.header.op_encoding(), OpEncoding::Compact);
+    assert!(encoding::is_relocatable(&bc).unwrap());
+    let len = bin.len();
+    linker::self_link(&mut bin, &Strict).unwrap();
+    assert_eq!(bin.len(), len);
+    assert_eq!(h6_bytecode::verify::verify(&bin), vec!());
+    assert!(encoding::is_relocatable(&Bytecode::try_from(bin.as_slice()).unwrap()).unwrap());
+
+    let relaxed = encoding::relax(&bin).unwrap();
+    assert!(relaxed.len() < bin.len());
+    assert!(!encoding::is_relocatable(&Bytecode::try_from(relaxed.as_slice()).unwrap()).unwrap());
+    for (name, bytes) in [("linked", bin), ("relaxed", relaxed)] {
+        let path = dir.join(name).with_extension("h6b");
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(ok(&["run", path.to_str().unwrap()]).stdout, expected, "{}", name);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn packed_arrays() {
+    let dir = dir("encoding-packed");
+    let big = "{ 1000 2000 3000 4000 5000 6000 7000 8000 9000 -10000 }";
+    // the string in strs is nested into the code of strs, so only the one in the main ops is packed.
+    // the two equal i16 arrays of a share their pool entry
+    let a = format!("strs: {{ \"hello world\" }}\n\n\"hello world\" strs! @+ {big} @< {{ {{ 5 6 }} 7 }} @0 {big} @*\n");
+    let b = format!("{big} @*\n");
+
+    for (name, flags) in [("fixed", &[][..]), ("compact", &["--compact"][..])] {
+        let a = compile(&dir, &format!("a-{}", name), &a, flags);
+        let b = compile(&dir, &format!("b-{}", name), &b, flags);
+        let linked = dir.join(name).with_extension("bin").to_str().unwrap().to_string();
+        let mut args = vec!("ld", a.as_str(), b.as_str(), "-o", linked.as_str());
+        args.extend_from_slice(flags);
+        ok(&args);
+
+        let pool = |path: &str| {
+            let bytes = std::fs::read(path).unwrap();
+            Bytecode::try_from(bytes.as_slice()).unwrap().pool_section().unwrap().len()
+        };
+        // the string as u8: 4 + 11 bytes, the big array as i16: 4 + 20 bytes
+        assert_eq!(pool(&a), 15 + 24, "{}", name);
+        assert_eq!(pool(&b), 24, "{}", name);
+        assert_eq!(pool(&linked), 15 + 24 + 24, "{}", name);
+
+        let expected = [
+            "\"hello worldhello world\"",
+            "{ 2000 3000 4000 5000 6000 7000 8000 9000 -10000 }",
+            "{ 5 6 }",
+            "10",
+            "10",
+        ];
+        assert_eq!(stack(&h6(&["run", &linked])), expected, "{}", name);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,75 @@
+//! the top level code of every module is an initializer, that runs in link order before the entry
+
+mod common;
+
+use std::path::Path;
+use common::{compile, dir, h6, ok, stack};
+
+/// links [inputs] with the extra [flags], and returns the path of the output
+fn ld(dir: &Path, out: &str, inputs: &[&str], flags: &[&str]) -> String {
+    let out = dir.join(out).to_str().unwrap().to_string();
+    let mut args = vec!("ld", "-o", out.as_str());
+    args.extend_from_slice(inputs);
+    args.extend_from_slice(flags);
+    ok(&args);
+    out
+}
+
+/// the modules of the initializers shown by `h6 nm`, in their order
+fn initializers(path: &str) -> Vec<String> {
+    String::from_utf8_lossy(&ok(&["nm", path]).stdout).lines()
+        .filter_map(|x| x.split_once(" I ").map(|x| x.1.to_string()))
+        .collect()
+}
+
+#[test]
+fn init_order() {
+    let dir = dir("init-order");
+    let a = compile(&dir, "a", "sq: { . * }\n\n1\n", &[]);
+    let b = compile(&dir, "b", "2\n", &[]);
+    let main = compile(&dir, "main", "go: { 3 sq! }\n\n0\n", &[]);
+
+    let linked = ld(&dir, "linked.bin", &[&a, &b, &main], &[]);
+    assert_eq!(stack(&h6(&["run", &linked])), ["1", "2", "0"]);
+    assert_eq!(initializers(&linked), ["a", "b", "main"]);
+
+    let reversed = ld(&dir, "reversed.bin", &[&main, &b, &a], &[]);
+    assert_eq!(stack(&h6(&["run", &reversed])), ["0", "2", "1"]);
+    assert_eq!(initializers(&reversed), ["main", "b", "a"]);
+
+    // a linked file keeps the initializers of its modules
+    let other = compile(&dir, "other", "4\n", &[]);
+    let relinked = ld(&dir, "relinked.bin", &[&linked, &other], &[]);
+    assert_eq!(stack(&h6(&["run", &relinked])), ["1", "2", "0", "4"]);
+    assert_eq!(initializers(&relinked), ["a", "b", "main", "other"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn entry_and_no_init() {
+    let dir = dir("init-entry");
+    let a = compile(&dir, "a", "sq: { . * }\n\n1\n", &[]);
+    let b = compile(&dir, "b", "2\n", &[]);
+    let main = compile(&dir, "main", "go: { 3 sq! }\n\n0\n", &[]);
+
+    // the entry runs after all initializers
+    let entry = ld(&dir, "entry.bin", &[&a, &b, &main], &["--entry", "go"]);
+    assert_eq!(stack(&h6(&["run", &entry])), ["1", "2", "0", "9"]);
+    assert_eq!(initializers(&entry), ["a", "b", "main"]);
+
+    let dropped = ld(&dir, "dropped.bin", &[&a, &b, &main], &["--no-init", "b", "--no-init", "main"]);
+    assert_eq!(stack(&h6(&["run", &dropped])), ["1"]);
+    assert_eq!(initializers(&dropped), ["a"]);
+
+    let only_entry = ld(&dir, "only-entry.bin", &[&a, &b, &main], &["--entry", "go", "--no-init", "a", "--no-init", "b", "--no-init", "main"]);
+    assert_eq!(stack(&h6(&["run", &only_entry])), ["9"]);
+    assert_eq!(initializers(&only_entry), Vec::<String>::new());
+
+    for (flag, name, err) in [("--entry", "missing", "SymbolNotFound"), ("--no-init", "missing", "InitializerNotFound")] {
+        let out = h6(&["ld", "-o", dir.join("err.bin").to_str().unwrap(), &a, &main, flag, name]);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains(err), "{}: {}", flag, stderr);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
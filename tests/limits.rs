@@ -0,0 +1,124 @@
+//! `h6 run` and embedders can stop untrusted code with limits and a cancel handle
+
+mod common;
+
+use std::io::Cursor;
+use h6_bytecode::{Bytecode, OpEncoding, linker};
+use h6_compiler::{lex, parse, lower};
+use h6_runtime::{CancelHandle, Limit, Limits, Runtime, RuntimeErrType};
+use common::{dir, h6};
+
+struct NoUnresolved;
+
+impl linker::Target for NoUnresolved {
+    fn allow_undeclared_symbol(&self, _: &str) -> bool {
+        false
+    }
+}
+
+fn build(src: &str) -> Vec<u8> {
+    let toks = lex::lex(src).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let mut out = Cursor::new(vec!());
+    lower::lower_full(&mut out, exprs.iter(), false, OpEncoding::Fixed, None, None).unwrap();
+    let mut ld = linker::Linker::new();
+    ld.add(Bytecode::try_from(out.get_ref().as_slice()).unwrap()).unwrap();
+    ld.link(&NoUnresolved).unwrap()
+}
+
+/// runs [bytes] to the end, or until the first error
+fn run(bytes: &[u8], limits: Limits, cancel: Option<&mut dyn FnMut(CancelHandle)>) -> Result<(), RuntimeErrType> {
+    let mut rt = Runtime::new(Bytecode::try_from(bytes).unwrap()).unwrap();
+    rt.set_limits(limits);
+    if let Some(cancel) = cancel {
+        cancel(rt.cancel_handle());
+    }
+    loop {
+        match rt.step() {
+            Ok(Some(())) => (),
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(e.ty),
+        }
+    }
+}
+
+/// the highest value of the limit that [src] exceeds
+fn exceeds(src: &str, limit: Limit, n: usize) {
+    let bytes = build(src);
+    let limits = |n: usize| match limit {
+        Limit::Ops => Limits { ops: Some(n as u64), ..Limits::default() },
+        Limit::Stack => Limits { stack: Some(n), ..Limits::default() },
+        Limit::Todo => Limits { todo: Some(n), ..Limits::default() },
+        Limit::ArrOps => Limits { arr_ops: Some(n), ..Limits::default() },
+    };
+    let ok = run(&bytes, limits(n + 1), None);
+    assert!(ok.is_ok(), "{}: {:?}", src, ok);
+    let err = run(&bytes, limits(n), None);
+    assert!(matches!(err, Err(RuntimeErrType::LimitExceeded(x)) if x == limit), "{}: {:?}", src, err);
+}
+
+#[test]
+fn limits() {
+    exceeds("1 2 +\n", Limit::Ops, 2);
+    exceeds("1 2 3\n", Limit::Stack, 2);
+    exceeds("{ 1 2 3 4 } !\n", Limit::Todo, 3);
+    // the ops of nested arrays count too
+    exceeds("{ 1 2 } { 3 } { { 4 } 5 }\n", Limit::ArrOps, 6);
+    // and the ones of arrays that are waiting to be pushed
+    exceeds("{ 1 2 3 } ; { 4 . 5 }\n", Limit::ArrOps, 5);
+
+    let endless = build("loop: { loop! }\n\nloop!\n");
+    let err = run(&endless, Limits { ops: Some(1000), ..Limits::default() }, None);
+    assert!(matches!(err, Err(RuntimeErrType::LimitExceeded(Limit::Ops))), "{:?}", err);
+    let err = run(&endless, Limits::default(), Some(&mut |x: CancelHandle| x.cancel()));
+    assert!(matches!(err, Err(RuntimeErrType::Cancelled)), "{:?}", err);
+}
+
+#[test]
+fn long_todo_queue() {
+    // every main op is in the todo queue at the start, the weight of the queue must not be summed up for each step
+    let bytes = build(&"{ 1 } ; ".repeat(50_000));
+    let start = std::time::Instant::now();
+    let res = run(&bytes, Limits { todo: Some(200_000), arr_ops: Some(100_000), ..Limits::default() }, None);
+    assert!(res.is_ok(), "{:?}", res);
+    assert!(start.elapsed() < std::time::Duration::from_secs(10), "{:?}", start.elapsed());
+}
+
+#[test]
+fn cancel_from_other_thread() {
+    let endless = build("loop: { loop! }\n\nloop!\n");
+    let mut start = |cancel: CancelHandle| {
+        assert!(!cancel.is_cancelled());
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            cancel.cancel();
+        });
+    };
+    let err = run(&endless, Limits::default(), Some(&mut start));
+    assert!(matches!(err, Err(RuntimeErrType::Cancelled)), "{:?}", err);
+}
+
+#[test]
+fn run_flags() {
+    let dir = dir("limits");
+    let endless = dir.join("loop.h6b");
+    std::fs::write(&endless, build("loop: { loop! }\n\nloop!\n")).unwrap();
+    let growing = dir.join("grow.h6b");
+    std::fs::write(&growing, build("grow: { 1 grow! }\n\ngrow!\n")).unwrap();
+
+    // the timeout stops the test if a limit does not work
+    for (path, flags, err) in [
+        (&endless, &["--max-ops", "1000", "--timeout", "10000"][..], "LimitExceeded(Ops)"),
+        (&growing, &["--max-stack", "10", "--timeout", "10000"][..], "LimitExceeded(Stack)"),
+        (&endless, &["--timeout", "20"][..], "Cancelled"),
+    ] {
+        let mut args = vec!("run");
+        args.extend_from_slice(flags);
+        args.push(path.to_str().unwrap());
+        let out = h6(&args);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains(err), "{:?}: {}", flags, stderr);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,494 @@
+//! linking modules that are compiled in process
+
+use std::io::Cursor;
+use h6_bytecode::{Bytecode, OpEncoding, linker};
+use h6_bytecode::linker::Linker;
+use h6_compiler::{lex, parse, lower};
+use h6_runtime::Value;
+
+struct NoUnresolved;
+
+impl linker::Target for NoUnresolved {
+    fn allow_undeclared_symbol(&self, _: &str) -> bool {
+        false
+    }
+}
+
+fn compile(src: &str) -> Vec<u8> {
+    let toks = lex::lex(src).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let mut out = Cursor::new(vec!());
+    lower::lower_full(&mut out, exprs.iter(), false, OpEncoding::Fixed, None, None).unwrap();
+    out.into_inner()
+}
+
+/// concatenates the [modules] and links them, like `h6 ld`
+fn link(modules: &[&[u8]]) -> Vec<u8> {
+    let mut ld = Linker::new();
+    for m in modules {
+        ld.add(Bytecode::try_from(*m).unwrap()).unwrap();
+    }
+    ld.link(&NoUnresolved).unwrap()
+}
+
+fn run(bytes: &[u8]) -> Vec<Value> {
+    assert_eq!(h6_bytecode::verify::verify(bytes), vec!());
+    let mut rt = h6_runtime::Runtime::new(Bytecode::try_from(bytes).unwrap()).unwrap();
+    while rt.step().unwrap().is_some() {}
+    rt.stack.into()
+}
+
+fn globals(bytes: &[u8]) -> Vec<String> {
+    let bc = Bytecode::try_from(bytes).unwrap();
+    bc.named_globals().map(|x| x.unwrap().0.to_string()).collect()
+}
+
+fn num(v: i32) -> Value {
+    Value::Num(v)
+}
+
+const GC_SRC: &str = "
+sq: { . * }
+nums: { 1 2 3 4 5 6 7 8 9 10 }
+quad: { sq! sq! nums ; }
+rec: { . { ; 0 } { 1 - rec! } l?! }
+unusednums: { 11 12 13 14 15 16 17 18 19 20 }
+unused: { unusednums }
+alsounused: { unused! 7 }
+
+3 quad! 2 rec!
+";
+
+#[test]
+fn gc_keeps_reachable_code() {
+    let linked = link(&[&compile(GC_SRC)]);
+    let expected = run(&linked);
+    assert_eq!(expected, vec!(num(81), num(0)));
+
+    // only what `unused` and `alsounused` reference is removed
+    let (gced, report) = linker::self_gc(&linked, &[]).unwrap();
+    assert_eq!(run(&gced), expected);
+    assert_eq!(globals(&gced), Vec::<String>::new());
+    assert!(report.code > 0);
+    // the length prefix and 10 u8 elements
+    assert_eq!((report.pool, report.globals), (4 + 10, 7));
+
+    // everything that `alsounused` references is kept with it
+    let (kept, report) = linker::self_gc(&linked, &["alsounused"]).unwrap();
+    assert_eq!(run(&kept), expected);
+    assert_eq!(globals(&kept), vec!("alsounused"));
+    assert_eq!((report.code, report.pool, report.globals), (0, 0, 6));
+    assert!(kept.len() > gced.len());
+
+    // the globals stay usable
+    let relinked = link(&[&kept, &compile("alsounused! $ ;\n")]);
+    assert_eq!(run(&relinked)[2..], [num(7)]);
+
+    assert!(matches!(linker::self_gc(&linked, &["missing"]), Err(linker::LinkError::SymbolNotFound(_))));
+}
+
+fn archive(members: &[(&str, &str)]) -> h6_bytecode::archive::Archive {
+    let members = members.iter()
+        .map(|(name, src)| h6_bytecode::archive::Member { name: name.to_string(), bytes: compile(src) })
+        .collect();
+    h6_bytecode::archive::Archive::new(members).unwrap()
+}
+
+#[test]
+fn archive_members_only_when_needed() {
+    let archives = [
+        archive(&[
+            ("sq.h6b", "sq: { . * }\n"),
+            ("cube.h6b", "cube: { . sq! * }\n"),
+            ("unused.h6b", "unused: { 1 }\n"),
+            ("local.h6b", "local: { 2 }\n"),
+        ]),
+        archive(&[
+            ("other_sq.h6b", "sq: { 0 }\n"),
+            ("neg.h6b", "neg: { 0 $ - }\n"),
+        ]),
+    ];
+    let main = compile("local: { 3 }\n\n2 cube! neg! local!\n");
+
+    let mut ld = Linker::new();
+    ld.add(Bytecode::try_from(main.as_slice()).unwrap()).unwrap();
+    // `cube` and `neg` are needed by main, then `sq` by `cube`. `local` is already defined
+    assert_eq!(ld.add_archives(&archives).unwrap(), vec!((0, 1), (1, 1), (0, 0)));
+    assert_eq!(ld.undefined_symbols().unwrap().len(), 0);
+    let linked = ld.link(&NoUnresolved).unwrap();
+    assert_eq!(run(&linked), vec!(num(-8), num(3)));
+    assert_eq!(globals(&linked), vec!("local", "cube", "neg", "sq"));
+
+    // nothing is taken if nothing is undefined
+    let defined = compile("1 2 +\n");
+    let mut ld = Linker::new();
+    ld.add(Bytecode::try_from(defined.as_slice()).unwrap()).unwrap();
+    assert_eq!(ld.add_archives(&archives).unwrap(), vec!());
+}
+
+#[test]
+fn linker_equals_cat_together() {
+    let modules = [
+        compile("sq: { . * }\nnums: { 1 2 3 4 5 6 7 8 9 10 }\n\n3 sq!\n"),
+        compile("cube: { . sq! * }\n\n2 cube! nums\n"),
+        compile("weak neg: { 0 $ - }\nlater: { 4 cube! neg! }\n\nlater!\n"),
+    ];
+
+    for enc in [OpEncoding::Fixed, OpEncoding::Compact] {
+        let mut ld = Linker::new();
+        ld.set_op_encoding(enc);
+        for m in modules.iter() {
+            ld.add(Bytecode::try_from(m.as_slice()).unwrap()).unwrap();
+        }
+        let linked = ld.link(&NoUnresolved).unwrap();
+
+        let mut out = Cursor::new(vec!());
+        let mut header = h6_bytecode::Header::default();
+        header.set_op_encoding(enc);
+        header.write(&mut out).unwrap();
+        h6_bytecode::Op::Terminate.write(&mut out).unwrap();
+        for m in modules.iter() {
+            linker::cat_together(&mut out, m.as_slice()).unwrap();
+        }
+        let mut cat = out.into_inner();
+        linker::self_link(&mut cat, &NoUnresolved).unwrap();
+
+        assert!(linked == cat, "{:?}", enc);
+        assert_eq!(run(&linked)[..2], [num(9), num(8)]);
+    }
+}
+
+/// the relocations section has to list exactly the relocatable ops
+fn assert_relocations(bytes: &[u8], what: &str) {
+    let bc = Bytecode::try_from(bytes).unwrap();
+    let listed = bc.relocations().expect("no relocations section").unwrap();
+    assert_eq!(listed, h6_bytecode::reloc::Relocations::scan(&bc).unwrap(), "{}", what);
+}
+
+/// positions of the ops in the code section of [lib] that change when it is concatenated behind [prefix]
+fn patched_ops(prefix: &[u8], lib: &[u8]) -> Vec<u32> {
+    let mut ld = Linker::new();
+    ld.add(Bytecode::try_from(prefix).unwrap()).unwrap();
+    ld.add(Bytecode::try_from(lib).unwrap()).unwrap();
+    let linked = ld.cat().unwrap();
+    let (before, after) = (Bytecode::try_from(lib).unwrap(), Bytecode::try_from(linked.as_slice()).unwrap());
+    let code = before.code_section().unwrap();
+    let moved = after.code_section().unwrap().start + Bytecode::try_from(prefix).unwrap().code_section().unwrap().len();
+    let enc = before.header.op_encoding();
+    let mut pos = code.start;
+    let mut patched = vec!();
+    while pos < code.end {
+        let (size, _) = h6_bytecode::OpType::read_enc(&before.data_table()[pos..], enc).unwrap();
+        let new = moved + pos - code.start;
+        if before.data_table()[pos..pos + size] != after.data_table()[new..new + size] {
+            patched.push(pos as u32);
+        }
+        pos += size;
+    }
+    patched
+}
+
+#[test]
+fn relocations_list_the_patched_ops() {
+    let lib = compile("sq: { . * }\nnums: { 1 2 3 4 5 6 7 8 9 10 }\nquad: { sq! sq! nums ; }\nunused: { quad! }\n");
+    let main = compile("dso_extern ext\ncube: { . sq! * }\n\n2 cube! quad! { sq! } ;\n");
+    assert_relocations(&lib, "lib");
+    assert_relocations(&main, "main");
+
+    // the prefix moves the strings, code and pool of lib, so every listed op changes
+    let prefix = compile("pre: { 1 2 + }\nprenums: { 1 2 3 4 5 6 7 8 9 10 }\n");
+    let listed = Bytecode::try_from(lib.as_slice()).unwrap().relocations().unwrap().unwrap();
+    let code = Bytecode::try_from(lib.as_slice()).unwrap().code_section().unwrap();
+    let listed = listed.positions.iter().copied().filter(|x| code.contains(&(*x as usize))).collect::<Vec<_>>();
+    assert!(listed.len() > 3);
+    assert_eq!(patched_ops(&prefix, &lib), listed);
+
+    // an op that is missing in the section is left alone, even if it is relocatable
+    let mut unlisted = lib.clone();
+    let section = listed.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+    let at = unlisted.windows(section.len()).position(|x| x == section).unwrap();
+    unlisted.copy_within(at..at + 4, at + 4);
+    assert_eq!(patched_ops(&prefix, &unlisted), [&listed[..1], &listed[2..]].concat());
+
+    let mut ld = Linker::new();
+    ld.add(Bytecode::try_from(lib.as_slice()).unwrap()).unwrap();
+    ld.add(Bytecode::try_from(main.as_slice()).unwrap()).unwrap();
+    let cat = ld.cat().unwrap();
+    assert_relocations(&cat, "cat");
+    let mut linked = cat.clone();
+    linker::self_link(&mut linked, &NoUnresolved).unwrap();
+    assert_relocations(&linked, "self_link");
+
+    let names = ["sq", "cube"].into_iter().collect();
+    assert_relocations(&linker::namespace(&cat, "ns", &names).unwrap(), "namespace");
+    assert_relocations(&linker::self_gc(&linked, &[]).unwrap().0, "self_gc");
+    assert_relocations(&linker::set_main(&linked, Some("quad"), &[]).unwrap(), "set_main");
+    assert_relocations(&h6_bytecode::encoding::reencode(&linked, OpEncoding::Compact).unwrap(), "reencode");
+    assert_relocations(&h6_bytecode::encoding::relax(&linked).unwrap(), "relax");
+}
+
+/// compiles [src] as module [name] with debug info, binds its dso imports to [lib] and adds an unknown `XTRA` section
+/// with [extra]
+fn module_with_sections(name: &str, src: &str, lib: &[u8], extra: &[u8]) -> Vec<u8> {
+    let toks = lex::lex(src).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let file = format!("{}.h6", name);
+    let src_info = lower::SrcInfo::new(&file, src, toks.iter().map(|x| x.1.clone()).collect());
+    let meta = h6_bytecode::metadata::ModuleInfo { name: name.to_string(), ..Default::default() };
+    let mut out = Cursor::new(vec!());
+    lower::lower_full(&mut out, exprs.iter(), false, OpEncoding::Fixed, Some(&src_info), Some(&meta)).unwrap();
+    let bound = linker::bind_dso(out.get_ref(), &[(&format!("lib{}.h6b", name), lib)]).unwrap();
+
+    let mut asm = h6_bytecode::asm::disassemble(&Bytecode::try_from(bound.as_slice()).unwrap()).unwrap();
+    asm.push_str(&format!(".ext \"XTRA\" {}\n", extra.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")));
+    h6_bytecode::asm::assemble(&asm).unwrap()
+}
+
+#[test]
+fn named_sections_are_merged() {
+    let lib = compile("ext: { 1 }\nother: { 2 }\n");
+    let a = module_with_sections("a", "dso_extern ext\nsq: { . * }\n\n3 sq! ext!\n", &lib, &[1, 2]);
+    let b = module_with_sections("b", "dso_extern other\n\n4 sq! other!\n", &lib, &[3, 4]);
+    let c = compile("5\n");
+
+    let mut out = Cursor::new(vec!());
+    h6_bytecode::Header::default().write(&mut out).unwrap();
+    h6_bytecode::Op::Terminate.write(&mut out).unwrap();
+    for m in [&a, &c, &b] {
+        linker::cat_together(&mut out, m.as_slice()).unwrap();
+    }
+    let cat = out.into_inner();
+    assert_eq!(h6_bytecode::verify::verify(&cat), vec!());
+
+    // one section per tag
+    let bc = Bytecode::try_from(cat.as_slice()).unwrap();
+    let mut tags = bc.named_sections().unwrap().into_iter().map(|x| x.tag).collect::<Vec<_>>();
+    let num = tags.len();
+    tags.sort();
+    tags.dedup();
+    assert_eq!(tags.len(), num, "{:?}", tags);
+
+    assert_eq!(bc.section(b"XTRA").unwrap().unwrap(), [1, 2, 3, 4]);
+    assert_eq!(bc.debug_info().unwrap().unwrap().files, ["a.h6", "b.h6"]);
+    let modules = bc.metadata().unwrap().unwrap().modules.into_iter().map(|x| x.name).collect::<Vec<_>>();
+    assert_eq!(modules, ["a", "b"]);
+    assert_eq!(bc.dso_libraries().unwrap().unwrap().imports, [
+        ("ext".to_string(), "liba.h6b".to_string()),
+        ("other".to_string(), "libb.h6b".to_string()),
+    ]);
+}
+
+#[test]
+fn private_bindings() {
+    let a = compile("private go: { 2 * }\nuse_a: { go! }\n\n3 go!\n");
+    let b = compile("private go: { 1 + }\nuse_b: { go! }\n\n3 go! 5 use_a!\n");
+    let linked = link(&[&a, &b]);
+    assert_eq!(run(&linked), vec!(num(6), num(4), num(10)));
+    assert_eq!(globals(&linked), vec!("use_a", "use_b"));
+
+    let toks = lex::lex("private go: { 2 * }\nprivate go: { 1 + }\n").unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let err = lower::lower_full(&mut Cursor::new(vec!()), exprs.iter(), false, OpEncoding::Fixed, None, None).unwrap_err();
+    assert!(matches!(err, lower::LoweringError::CodeError { err: lower::SrcError::SymbolDefinedTwice(ref name), .. } if name == "go"), "{:?}", err);
+}
+
+/// lowers and links the bindings of the previous lines with the stack and the new [line], like the repl does
+fn repl_line(defines: &[parse::Expr], stack: &[Value], line: &str) -> Vec<u8> {
+    let toks = lex::lex(line).unwrap();
+    let exprs = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    let mut all = stack.iter()
+        .map(|x| parse::Expr { val: x.clone().into_ops().into_iter().collect(), ..Default::default() })
+        .collect::<Vec<_>>();
+    all.extend(exprs);
+    all.extend(defines.iter().cloned());
+    let mut out = Cursor::new(vec!());
+    lower::lower_full(&mut out, all.iter(), true, OpEncoding::Fixed, None, None).unwrap();
+    link(&[&out.into_inner()])
+}
+
+#[test]
+fn repl_private_helper() {
+    let src = "private helper: { 2 * }\ndouble: { helper! }\n";
+    let toks = lex::lex(src).unwrap();
+    let defines = parse::parse(toks.iter().map(|x| x.0.clone())).unwrap();
+    assert!(defines[0].private);
+
+    // the helper stays private when the bindings are lowered again, with a value from the previous line
+    let first = run(&repl_line(&defines, &[], "3 double!\n"));
+    assert_eq!(first, vec!(num(6)));
+    let second = repl_line(&defines, &first, "double! 1 +\n");
+    assert_eq!(run(&second), vec!(num(13)));
+    assert_eq!(globals(&second), vec!("double"));
+
+    // a value that refers to the helper has no name that it could be lowered again with
+    let bytes = repl_line(&defines, &[], "{ helper! }\n");
+    let Value::Arr(arr) = run(&bytes).remove(0) else { panic!() };
+    let bc = Bytecode::try_from(bytes.as_slice()).unwrap();
+    let named = bc.named_globals().map(|x| x.unwrap().1).collect::<Vec<_>>();
+    assert!(arr.iter().any(|op| matches!(op, h6_bytecode::Op::Const { idx } if !named.contains(idx))), "{:?}", arr);
+}
+
+#[test]
+fn qualified_identifiers() {
+    let toks = lex::lex("std::map a.b").unwrap().into_iter().map(|x| x.0).collect::<Vec<_>>();
+    assert!(toks == vec!(lex::Tok::Ident("std::map".into()), lex::Tok::Ident("a".into()), lex::Tok::Dot, lex::Tok::Ident("b".into())));
+
+    // `a.b` is `a`, dup, `b`, also if `a` and `b` are defined in another file
+    let defs = compile("a: 1\nb: 2\n");
+    for (modules, src) in [(vec!(), "a: 1\nb: 2\n\na.b\n"), (vec!(&defs), "a.b\n"), (vec!(&defs), "a . b\n")] {
+        let main = compile(src);
+        let mut modules = modules.iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+        modules.push(&main);
+        let out = run(&link(&modules));
+        assert_eq!(out.len(), 3, "{}", src);
+        assert_eq!(out[0], out[1], "{}", src);
+        assert_ne!(out[1], out[2], "{}", src);
+    }
+
+    // `std::map` refers to a global of a file linked with a namespace
+    let lib = link(&[&compile("map: { 10 * }\na: { 2 }\n")]);
+    let names = ["map", "a"].into_iter().collect();
+    let lib = linker::namespace(&lib, "std", &names).unwrap();
+    assert_eq!(globals(&lib), vec!("std::a", "std::map"));
+    let main = compile("a: { 3 }\n\na! std::map! std::a!\n");
+    assert_eq!(run(&link(&[&lib, &main])), vec!(num(30), num(2)));
+}
+
+#[test]
+fn link_map() {
+    let a_src = "sq: { . * }\nquad: { sq! sq! }\n";
+    let b_src = "private twice: { 2 * }\ncube: { . sq! * twice! }\n\n3 cube! 2 quad!\n";
+    let (a, b) = (compile(a_src), compile(b_src));
+    let linked = link(&[&a, &b]);
+    let bc = Bytecode::try_from(linked.as_slice()).unwrap();
+    let map = linker::link_map(&linked).unwrap();
+
+    assert_eq!(map.data_begin as usize, bc.header.data_begin());
+    assert_eq!(map.strings_len as usize, bc.strings_section().unwrap().len());
+    assert_eq!(map.code_len as usize, bc.code_section().unwrap().len());
+    assert_eq!(map.pool_len, 0);
+    assert_eq!(map.globals_len, 3 * 8);
+    assert_eq!(map.symbols.iter().map(|x| x.size).sum::<u32>(), map.code_len);
+    assert_eq!(map.data_begin as usize + map.strings_len as usize + map.code_len as usize + map.pool_len as usize
+        + map.globals_len as usize + map.main_len as usize + map.extensions_len as usize, linked.len());
+
+    let names = map.symbols.iter().map(|x| x.names.join(",")).collect::<Vec<_>>();
+    assert_eq!(names, ["sq", "quad", "", "cube"]);
+    let off = |name: &str| bc.named_globals().map(|x| x.unwrap()).find(|x| x.0 == name).unwrap().1;
+    let private = map.symbols[2].offset;
+    let refs = |name: &str| map.symbol(off(name)).unwrap().refs.iter().copied().collect::<Vec<_>>();
+    let referrers = |offset: u32| map.referrers(offset).map(|x| x.offset).collect::<Vec<_>>();
+    assert_eq!(refs("sq"), []);
+    assert_eq!(refs("quad"), [off("sq")]);
+    assert_eq!(refs("cube"), [off("sq"), private]);
+    assert_eq!(referrers(off("sq")), [off("quad"), off("cube")]);
+    assert_eq!(referrers(private), [off("cube")]);
+    assert_eq!(map.main_refs.iter().copied().collect::<Vec<_>>(), [off("quad"), off("cube")]);
+
+    // `h6 ld --map` prints file offsets. the private code belongs to the file of the code that uses it
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("h6-linker-map-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    std::fs::write(path("a.h6b"), &a).unwrap();
+    std::fs::write(path("b.h6b"), &b).unwrap();
+    let abs = |name: &str| map.data_begin + off(name);
+    for format in ["text", "json"] {
+        let out = std::process::Command::new(env!("CARGO_BIN_EXE_h6"))
+            .args(["ld", &path("a.h6b"), &path("b.h6b"), "-o", &path("o.bin"), "--map", &path("o.map"), "--map-format", format])
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        assert_eq!(std::fs::read(path("o.bin")).unwrap(), linked);
+        let text = std::fs::read_to_string(path("o.map")).unwrap();
+        let (a, b) = (path("a.h6b"), path("b.h6b"));
+
+        let expected = if format == "text" {
+            vec!(
+                format!("  {:<12}{:>8}\n", "code", map.code_len),
+                format!("  {:#06x} {:>6}  sq  ({})\n      <- quad\n      <- cube\n", abs("sq"), 5, a),
+                format!("  {:#06x} {:>6}  quad  ({})\n      -> sq\n      <- <main>\n", abs("quad"), 15, a),
+                format!("  {:#06x} {:>6}  <private>  ({})\n      <- cube\n", map.data_begin + private, 9, b),
+                format!("  {:#06x} {:>6}  cube  ({})\n      -> sq\n      -> {:#06x}\n      <- <main>\n", abs("cube"), 17, b, map.data_begin + private),
+                format!("  {:>8}  {}\n  {:>8}  {}\n", 20, a, 26, b),
+            )
+        } else {
+            vec!(
+                format!("\"code\": {},", map.code_len),
+                format!("{{\"names\": [\"sq\"], \"offset\": {}, \"size\": 5, \"origin\": \"{}\", \"refs\": [], \"referrers\": [\"quad\", \"cube\"]}}", abs("sq"), a),
+                format!("{{\"names\": [\"quad\"], \"offset\": {}, \"size\": 15, \"origin\": \"{}\", \"refs\": [\"sq\"], \"referrers\": [\"<main>\"]}}", abs("quad"), a),
+                format!("{{\"names\": [], \"offset\": {}, \"size\": 9, \"origin\": \"{}\", \"refs\": [], \"referrers\": [\"cube\"]}}", map.data_begin + private, b),
+                format!("{{\"names\": [\"cube\"], \"offset\": {}, \"size\": 17, \"origin\": \"{}\", \"refs\": [\"sq\", \"{:#06x}\"], \"referrers\": [\"<main>\"]}}", abs("cube"), b, map.data_begin + private),
+                format!("\"modules\": {{\"{}\": 20, \"{}\": 26}}", a, b),
+            )
+        };
+        for line in expected {
+            assert!(text.contains(&line), "{} map does not contain {:?}:\n{}", format, line, text);
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn weak_symbols() {
+    let weak = compile("weak neg: { 1 }\nuse_neg: { neg! }\n\nuse_neg!\n");
+    let also_weak = compile("weak neg: { 2 }\n\nneg!\n");
+    let strong = compile("neg: { 3 }\n\nneg!\n");
+
+    // the strong definition is used, also by the file with the weak one, in any order
+    assert_eq!(run(&link(&[&weak, &strong])), vec!(num(3), num(3)));
+    assert_eq!(run(&link(&[&strong, &weak])), vec!(num(3), num(3)));
+    assert_eq!(run(&link(&[&weak, &also_weak, &strong])), vec!(num(3), num(3), num(3)));
+
+    // without a strong definition, the first weak one is used
+    assert_eq!(run(&link(&[&weak, &also_weak])), vec!(num(1), num(1)));
+    assert_eq!(run(&link(&[&also_weak, &weak])), vec!(num(2), num(2)));
+
+    // every entry of the name points to the used definition
+    let linked = link(&[&weak, &strong, &also_weak]);
+    let bc = Bytecode::try_from(linked.as_slice()).unwrap();
+    let negs = bc.globals()
+        .filter(|x| bc.string(x.name).unwrap() == "neg")
+        .map(|x| (x.const_id, x.weak))
+        .collect::<Vec<_>>();
+    assert_eq!(negs.iter().map(|x| x.1).collect::<Vec<_>>(), vec!(true, false, true));
+    assert!(negs.iter().all(|x| x.0 == negs[1].0));
+
+    let mut ld = Linker::new();
+    ld.add(Bytecode::try_from(strong.as_slice()).unwrap()).unwrap();
+    ld.add(Bytecode::try_from(strong.as_slice()).unwrap()).unwrap();
+    assert!(matches!(ld.link(&NoUnresolved), Err(linker::LinkError::SymbolDefinedTwice(name)) if name == "neg"));
+}
+
+/// every op in the code section of [bytes]
+fn code_ops(bytes: &[u8]) -> Vec<h6_bytecode::Op> {
+    let bc = Bytecode::try_from(bytes).unwrap();
+    let code = bc.code_section().unwrap();
+    let data = &bc.data_table()[..code.end];
+    let mut pos = code.start;
+    let mut out = vec!();
+    while pos < code.end {
+        let (size, op) = h6_bytecode::OpType::read_enc(&data[pos..], bc.header.op_encoding()).unwrap();
+        pos += size;
+        out.push(op);
+    }
+    out
+}
+
+#[test]
+fn self_link_resolves_unreachable_code() {
+    // no global or op refers to `hidden`, like to code that is only reached with `constAt!`
+    let lib = compile("sq: { . * }\n");
+    let main = compile("private hidden: { sq! }\n\n2\n");
+    assert!(code_ops(&main).iter().any(|op| matches!(op, h6_bytecode::Op::Unresolved { .. })));
+    let linked = link(&[&lib, &main]);
+    assert!(!code_ops(&linked).iter().any(|op| matches!(op, h6_bytecode::Op::Unresolved { .. })));
+
+    // and undefined names in it are reported
+    let main = compile("private hidden: { missing! }\n\n2\n");
+    let mut ld = Linker::new();
+    ld.add(Bytecode::try_from(main.as_slice()).unwrap()).unwrap();
+    let Err(linker::LinkError::UnresolvedSymbols(syms)) = ld.link(&NoUnresolved) else { panic!() };
+    assert_eq!(syms.len(), 1);
+    assert_eq!(syms[0].name, "missing");
+    assert!(matches!(syms[0].refs[..], [(linker::Referrer::Code { .. }, _)]), "{:?}", syms[0].refs);
+}
@@ -0,0 +1,57 @@
+//! compiling and linking the same inputs has to produce the same bytes, independent of the process
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+mod common;
+
+use std::path::Path;
+use common::{ok, sources};
+
+const RUNS: usize = 4;
+
+fn hash(path: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    std::fs::read(path).unwrap().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// compiles and links std/*.h6 into [dir], and returns the hashes of all outputs
+fn build(dir: &Path, flags: &[&str]) -> Vec<u64> {
+    std::fs::create_dir_all(dir).unwrap();
+    let mut objs = vec!();
+    for src in sources("std") {
+        let obj = dir.join(src.file_name().unwrap()).with_extension("h6b");
+        let mut args = vec!("compile", "-g", src.to_str().unwrap(), "-o", obj.to_str().unwrap());
+        args.extend_from_slice(flags);
+        ok(&args);
+        objs.push(obj);
+    }
+
+    let out = dir.join("_out.h6b");
+    let _ = std::fs::remove_file(&out);
+    let mut args = vec!("ld", "--allow-unresolved", "-o", out.to_str().unwrap());
+    args.extend(objs.iter().map(|x| x.to_str().unwrap()));
+    args.extend_from_slice(flags);
+    ok(&args);
+
+    objs.iter().chain([&out]).map(|x| hash(x)).collect()
+}
+
+fn check(name: &str, flags: &[&str]) {
+    let base = std::env::temp_dir().join(format!("h6-reproducible-{}-{}", name, std::process::id()));
+    let first = build(&base.join("0"), flags);
+    for run in 1..RUNS {
+        assert_eq!(first, build(&base.join(run.to_string()), flags), "run {} differs from run 0", run);
+    }
+    let _ = std::fs::remove_dir_all(&base);
+}
+
+#[test]
+fn std_is_reproducible() {
+    check("fixed", &[]);
+}
+
+#[test]
+fn std_is_reproducible_compact() {
+    check("compact", &["--compact"]);
+}
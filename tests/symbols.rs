@@ -0,0 +1,128 @@
+//! symbols as shown by `h6 nm`, and as changed by the flags of `h6 ld`
+
+mod common;
+
+use std::path::Path;
+use common::{compile, dir, h6, ok, stack};
+
+/// (type, name) of every symbol
+fn nm(path: &str) -> Vec<(String, String)> {
+    String::from_utf8_lossy(&ok(&["nm", path]).stdout).lines()
+        .filter(|x| x.starts_with("0x"))
+        .map(|x| {
+            let mut parts = x.split_whitespace().skip(1);
+            (parts.next().unwrap().to_string(), parts.next().unwrap().to_string())
+        })
+        .collect()
+}
+
+fn sym(ty: &str, name: &str) -> (String, String) {
+    (ty.to_string(), name.to_string())
+}
+
+#[test]
+fn nm_weak() {
+    let dir = dir("symbols-weak");
+    let weak = compile(&dir, "weak", "weak neg: { 1 }\nsq: { . * }\n", &[]);
+    let strong = compile(&dir, "strong", "neg: { 3 }\n", &[]);
+    assert_eq!(nm(&weak), vec!(sym("W", "neg"), sym("T", "sq")));
+
+    // the weak entry stays in the linked file
+    let linked = dir.join("linked.bin");
+    let linked = linked.to_str().unwrap();
+    ok(&["ld", &weak, &strong, "-o", linked]);
+    let syms = nm(linked);
+    assert!(syms.contains(&sym("W", "neg")) && syms.contains(&sym("T", "neg")), "{:?}", syms);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// links [inputs] with the extra [flags] and runs the result, or returns the error of the linker
+fn ld_run(dir: &Path, inputs: &[&str], flags: &[&str]) -> Result<Vec<String>, String> {
+    let out = dir.join("out.bin");
+    let out = out.to_str().unwrap();
+    let mut args = vec!("ld", "-o", out);
+    args.extend_from_slice(inputs);
+    args.extend_from_slice(flags);
+    let ld = h6(&args);
+    if !ld.status.success() {
+        return Err(String::from_utf8_lossy(&ld.stderr).to_string());
+    }
+    Ok(stack(&h6(&["run", out])))
+}
+
+#[test]
+fn reserved_identifiers() {
+    let toks = h6_compiler::lex::lex("__real_x _ _x").unwrap().into_iter().map(|x| x.0).collect::<Vec<_>>();
+    assert_eq!(toks.len(), 4);
+    assert!(toks[0] == h6_compiler::lex::Tok::Ident("__real_x".into()));
+    assert!(toks[3] == h6_compiler::lex::Tok::Ident("x".into()));
+}
+
+#[test]
+fn wrap() {
+    let dir = dir("symbols-wrap");
+    // references in the file that defines `x` are resolved by the compiler, and are not wrapped
+    let lib = compile(&dir, "lib", "x: { 5 }\n\nx!\n", &[]);
+    let wrapper = compile(&dir, "wrapper", "__wrap_x: { __real_x! 1 + }\n\nx! __real_x!\n", &[]);
+    let no_wrapper = compile(&dir, "no_wrapper", "x! __real_x!\n", &[]);
+
+    assert_eq!(ld_run(&dir, &[&lib, &wrapper], &["--wrap", "x"]).unwrap(), ["5", "6", "5"]);
+    // the wrapper has to be defined, and `__real_x` only refers to `x` with `--wrap`
+    let err = ld_run(&dir, &[&lib, &no_wrapper], &["--wrap", "x"]).unwrap_err();
+    assert!(err.contains("unresolved symbol `__wrap_x`") && !err.contains("`__real_x`"), "{}", err);
+    let err = ld_run(&dir, &[&lib, &no_wrapper], &[]).unwrap_err();
+    assert!(err.contains("unresolved symbol `__real_x`"), "{}", err);
+    let err = ld_run(&dir, &[&lib, &wrapper], &[]).unwrap_err();
+    assert!(err.contains("unresolved symbol `__real_x`"), "{}", err);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn defsym() {
+    let dir = dir("symbols-defsym");
+    let main = compile(&dir, "main", "y: { 7 }\n\nx! z!\n", &[]);
+
+    assert_eq!(ld_run(&dir, &[&main], &["--defsym", "x=y", "--defsym", "z=y"]).unwrap(), ["7", "7"]);
+    let err = ld_run(&dir, &[&main], &["--defsym", "x=missing"]).unwrap_err();
+    assert!(err.contains("unresolved symbol `missing`") && err.contains("unresolved symbol `z`"), "{}", err);
+    let err = ld_run(&dir, &[&main], &["--defsym", "x"]).unwrap_err();
+    assert!(err.contains("expected A=B"), "{}", err);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn weak_with_allow_unresolved() {
+    let dir = dir("symbols-weak-partial");
+    let lib = compile(&dir, "lib", "weak greet: { 7 }\ngreet_twice: { greet! greet! }\n", &[]);
+    let main = compile(&dir, "main", "greet_twice!\n", &[]);
+    let strong = compile(&dir, "strong", "greet: { 8 }\n", &[]);
+
+    // a weak definition is bound if there is no strong one, even if unresolved symbols are allowed
+    for flags in [&[][..], &["--allow-unresolved"][..]] {
+        assert_eq!(ld_run(&dir, &[&lib, &main], flags).unwrap(), ["7", "7"], "{:?}", flags);
+        assert_eq!(ld_run(&dir, &[&lib, &main, &strong], flags).unwrap(), ["8", "8"], "{:?}", flags);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn unresolved_suggestions() {
+    let dir = dir("symbols-unresolved");
+    let lib = compile(&dir, "lib", "square: { . * }\n", &[]);
+    // with debug info, the diagnostic points at the misspelled name
+    let main = compile(&dir, "main", "twice: { sqare! sqare! }\n\n3 sqare!\n", &["-g"]);
+    let src = dir.join("main.h6");
+
+    let err = ld_run(&dir, &[&lib, &main], &[]).unwrap_err();
+    let expected = [
+        "error: unresolved symbol `sqare`".to_string(),
+        format!("  referenced by twice in {} ({}:1:10)", main, src.display()),
+        format!("  referenced by main code in {} ({}:3:3)", main, src.display()),
+        "  help: did you mean `square`?".to_string(),
+        "1 unresolved symbol".to_string(),
+    ];
+    for line in expected {
+        assert!(err.lines().any(|x| x == line), "missing {:?} in:\n{}", line, err);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,53 @@
+//! a V3 file has to run and disassemble the same after `h6 upgrade`
+
+mod common;
+
+use std::path::Path;
+use common::{dir, ok};
+
+/// the ops of every code block, and what references it, without any positions.
+/// they change in the upgrade, because the data table is split into sections
+fn ops(dis: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(dis).lines()
+        .filter_map(|line| {
+            let trimmed = line.trim_start();
+            if let Some((addr, op)) = trimmed.split_once(':') && addr.starts_with("0x") {
+                Some(op.trim().to_string())
+            } else if let Some((_, name)) = line.strip_prefix("data+").and_then(|x| x.split_once(" : ")) {
+                Some(format!("== {}", name))
+            } else {
+                trimmed.starts_with(';').then(|| trimmed.to_string())
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn upgrade_v3() {
+    let v3 = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/v3.h6b");
+    let v3 = v3.to_str().unwrap();
+    let dir = dir("upgrade");
+    let upgraded = dir.join("v5.h6b");
+    let upgraded = upgraded.to_str().unwrap();
+
+    assert_eq!(std::fs::read(v3).unwrap()[4..6], [3, 3]);
+    ok(&["upgrade", v3, "-o", upgraded]);
+    assert_eq!(ok(&["verify", upgraded]).status.code(), Some(0));
+
+    let run = ok(&["run", v3]).stdout;
+    assert_eq!(String::from_utf8_lossy(&run).split_whitespace().collect::<Vec<_>>(),
+        ["bot", "{", "0", "1", "4", "9", "16", "}", "{", "3", "2", "1", "}", "49", "top"]);
+    assert_eq!(run, ok(&["run", upgraded]).stdout);
+
+    // the linker upgrades V3 inputs itself
+    let linked = dir.join("linked.h6b");
+    let linked = linked.to_str().unwrap();
+    ok(&["ld", v3, "-o", linked]);
+    assert_eq!(std::fs::read(linked).unwrap()[4..6], std::fs::read(upgraded).unwrap()[4..6]);
+    assert_eq!(run, ok(&["run", linked]).stdout);
+
+    let dis = ops(&ok(&["dis", v3]).stdout);
+    assert!(dis.contains(&"== sq".to_string()), "{:?}", dis);
+    assert_eq!(dis, ops(&ok(&["dis", upgraded]).stdout));
+    let _ = std::fs::remove_dir_all(&dir);
+}
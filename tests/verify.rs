@@ -0,0 +1,203 @@
+//! the verifier has to report broken and hostile files, and the runtime must not panic on files that pass it
+
+mod common;
+
+use h6_bytecode::verify::{verify, Problem};
+use h6_bytecode::{Bytecode, Op, OpType};
+use common::{compile, dir, ok};
+
+const PROGRAM: &str = "sq: { . * }\nwrap: { 1 sq! }\n3 sq! { 1 2 } ; { 4 wrap! } ;\n";
+
+/// compiles [src], and links it with the extra [ld] args unless that is None
+fn build(name: &str, src: &str, ld: Option<&[&str]>) -> Vec<u8> {
+    let dir = dir("verify");
+    let obj = compile(&dir, name, src, &[]);
+    let Some(ld) = ld else {
+        return std::fs::read(obj).unwrap();
+    };
+
+    let bin = dir.join(name).with_extension("bin");
+    let mut args = vec!("ld", obj.as_str(), "-o", bin.to_str().unwrap());
+    args.extend_from_slice(ld);
+    ok(&args);
+    std::fs::read(bin).unwrap()
+}
+
+fn problems(bytes: &[u8]) -> Vec<Problem> {
+    verify(bytes).into_iter().map(|x| x.problem).collect()
+}
+
+fn assert_reports<F: Fn(&Problem) -> bool>(bytes: &[u8], what: &str, f: F) {
+    let problems = problems(bytes);
+    assert!(problems.iter().any(f), "expected {}, got {:?}", what, problems);
+}
+
+/// runs the file if it passes the verifier, which must not panic
+fn run_if_valid(bytes: &[u8]) {
+    if !verify(bytes).is_empty() {
+        return;
+    }
+    let bc = Bytecode::try_from(bytes).unwrap();
+    let Ok(mut rt) = h6_runtime::Runtime::new(bc) else {
+        return;
+    };
+    rt.set_limits(h6_runtime::Limits { ops: Some(10_000), stack: Some(1_000), todo: Some(1_000), arr_ops: Some(1_000) });
+    while let Ok(Some(())) = rt.step() {}
+}
+
+fn set_u32(bytes: &mut [u8], at: usize, val: u32) {
+    bytes[at..at + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+fn ex_header_off(bytes: &[u8]) -> usize {
+    u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize
+}
+
+/// replaces the first main op that matches [find] with [new], which has to have the same size
+fn patch_main_op<F: Fn(&Op) -> bool>(bytes: &mut [u8], find: F, new: Op) {
+    let bc = Bytecode::try_from(&*bytes).unwrap();
+    let enc = bc.header.op_encoding();
+    let pos = bc.main_ops()
+        .map(|x| x.unwrap())
+        .find(|(_, op)| find(op))
+        .expect("op not found").0;
+    let (size, _) = OpType::read_enc(&bytes[pos..], enc).unwrap();
+    bytes[pos..pos + size].copy_from_slice(&new.encode_sized(enc, size).unwrap());
+}
+
+#[test]
+fn valid_files_pass() {
+    for bytes in [build("valid", PROGRAM, Some(&[])), build("valid", PROGRAM, None)] {
+        assert_eq!(problems(&bytes), vec!());
+        run_if_valid(&bytes);
+    }
+}
+
+#[test]
+fn bad_header() {
+    let bytes = build("header", PROGRAM, Some(&[]));
+    assert_reports(&bytes[..10], "a header error", |x| matches!(x, Problem::Header(_)));
+
+    let mut magic = bytes.clone();
+    magic[0] = b'X';
+    assert_reports(&magic, "a header error", |x| matches!(x, Problem::Header(_)));
+}
+
+#[test]
+fn bad_table_bounds() {
+    let bytes = build("bounds", PROGRAM, Some(&[]));
+
+    let mut globals_off = bytes.clone();
+    set_u32(&mut globals_off, 12, 0xffff);
+    assert_reports(&globals_off, "DataTableOutOfBounds", |x| *x == Problem::DataTableOutOfBounds);
+
+    let mut globals_num = bytes.clone();
+    set_u32(&mut globals_num, 8, 0xffff);
+    assert_reports(&globals_num, "GlobalsTableOutOfBounds", |x| *x == Problem::GlobalsTableOutOfBounds);
+
+    for off in [4, bytes.len() as u32 + 4] {
+        let mut ex = bytes.clone();
+        set_u32(&mut ex, 16, off);
+        assert_reports(&ex, "ExtendedHeaderOutOfBounds", |x| *x == Problem::ExtendedHeaderOutOfBounds);
+    }
+
+    // the extended header is only written if something needs it
+    let bytes = build("bounds-dso", "dso_extern ext\next!\n", Some(&[]));
+    let mut registry = bytes.clone();
+    let ex = ex_header_off(&bytes);
+    set_u32(&mut registry, ex + 6, 0xffff);
+    assert_reports(&registry, "SectionRegistryOutOfBounds", |x| *x == Problem::SectionRegistryOutOfBounds);
+}
+
+#[test]
+fn truncated_files() {
+    let bytes = build("truncated", PROGRAM, Some(&[]));
+    for len in 0..bytes.len() {
+        assert_ne!(problems(&bytes[..len]), vec!(), "truncated to {} bytes", len);
+    }
+}
+
+#[test]
+fn missing_nul() {
+    let mut bytes = build("nul", PROGRAM, Some(&[]));
+    let bc = Bytecode::try_from(&*bytes).unwrap();
+    let (data_begin, strings) = (bc.header.data_begin(), bc.strings_section().unwrap());
+    // the last string then runs into the code section
+    let last = bytes[data_begin..data_begin + strings.end - 1].iter().rposition(|&b| b == 0).map_or(0, |x| x + 1);
+    assert_eq!(bytes[data_begin + strings.end - 1], 0);
+    bytes[data_begin + strings.end - 1] = b'x';
+    assert_reports(&bytes, "InvalidString", |x| *x == Problem::InvalidString { off: last as u32 });
+}
+
+#[test]
+fn op_targets_out_of_range() {
+    let bytes = build("targets", PROGRAM, Some(&[]));
+
+    let mut konst = bytes.clone();
+    patch_main_op(&mut konst, |x| matches!(x, Op::Const { .. }), Op::Const { idx: 0xffff });
+    assert_reports(&konst, "ConstOutOfBounds", |x| *x == Problem::ConstOutOfBounds { idx: 0xffff });
+
+    let mut wrong = bytes.clone();
+    patch_main_op(&mut wrong, |x| matches!(x, Op::Const { .. }), Op::Const { idx: 0 });
+    assert_reports(&wrong, "WrongSection", |x| *x == Problem::WrongSection { idx: 0, expected: "code" });
+
+    let mut arr = bytes.clone();
+    patch_main_op(&mut arr, |x| matches!(x, Op::ArrAt { .. }), Op::ArrAt { ty: h6_bytecode::PushConstArrType::U8, idx: 0xffff });
+    assert_reports(&arr, "ArrAtOutOfBounds", |x| *x == Problem::ArrAtOutOfBounds { idx: 0xffff });
+
+    let mut unresolved = build("targets", "undefined!\n", None);
+    patch_main_op(&mut unresolved, |x| matches!(x, Op::Unresolved { .. }), Op::Unresolved { id: 0xffff });
+    assert_reports(&unresolved, "InvalidString", |x| *x == Problem::InvalidString { off: 0xffff });
+}
+
+#[test]
+fn unbalanced_arr() {
+    let bytes = build("arr", PROGRAM, Some(&[]));
+    assert!(Bytecode::try_from(&*bytes).unwrap().main_ops().any(|x| x.unwrap().1 == Op::ArrBegin));
+
+    let mut no_end = bytes.clone();
+    patch_main_op(&mut no_end, |x| *x == Op::ArrEnd, Op::ArrBegin);
+    assert_reports(&no_end, "UnbalancedArr", |x| *x == Problem::UnbalancedArr);
+
+    let mut no_begin = bytes.clone();
+    patch_main_op(&mut no_begin, |x| *x == Op::ArrBegin, Op::ArrEnd);
+    assert_reports(&no_begin, "UnbalancedArr", |x| *x == Problem::UnbalancedArr);
+}
+
+#[test]
+fn bad_dso_tables() {
+    let bytes = build("dso", "dso_extern ext\next!\n", Some(&[]));
+    assert_eq!(problems(&bytes), vec!());
+    let ex = ex_header_off(&bytes);
+
+    let mut num_dso = bytes.clone();
+    set_u32(&mut num_dso, ex + 2, 0xffff);
+    assert_reports(&num_dso, "DsoTableOutOfBounds", |x| *x == Problem::DsoTableOutOfBounds);
+
+    // the dso table follows the 10 byte extended header
+    let mut name = bytes.clone();
+    set_u32(&mut name, ex + 10, 0xffff);
+    assert_reports(&name, "InvalidString", |x| *x == Problem::InvalidString { off: 0xffff });
+
+    let mut dso_id = bytes.clone();
+    patch_main_op(&mut dso_id, |x| matches!(x, Op::DsoConst { .. }), Op::DsoConst { dso_id: 7 });
+    assert_reports(&dso_id, "DsoIdOutOfBounds", |x| *x == Problem::DsoIdOutOfBounds { dso_id: 7 });
+}
+
+#[test]
+fn runtime_accepts_verified_files() {
+    let files = [
+        build("fuzz", PROGRAM, Some(&[])),
+        build("fuzz", PROGRAM, None),
+        build("fuzz-compact", PROGRAM, Some(&["--compact"])),
+    ];
+    for bytes in files {
+        for pos in 0..bytes.len() {
+            for val in [0, 1, 0xff, bytes[pos].wrapping_add(1)] {
+                let mut bytes = bytes.clone();
+                bytes[pos] = val;
+                run_if_valid(&bytes);
+            }
+        }
+    }
+}